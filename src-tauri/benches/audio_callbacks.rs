@@ -0,0 +1,111 @@
+// src-tauri/benches/audio_callbacks.rs
+//
+// Drives the capture and playback callbacks the way a device would (480
+// frames every 10 ms) while the capture worker and mixer do real encoding,
+// decoding and mixing on their own threads, then reports how long the
+// callbacks themselves took. Run with `cargo bench --bench audio_callbacks`.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+use atomic_float::AtomicF32;
+use ringbuf::HeapRb;
+use tokio::sync::mpsc;
+use llas_lib::audio::capture::spawn_capture_worker;
+use llas_lib::audio::codec::{EncoderSettings, SAMPLE_RATE};
+use llas_lib::audio::dsp::DspControls;
+use llas_lib::audio::engine::EngineShared;
+use llas_lib::audio::loopback::Loopback;
+use llas_lib::audio::recorder::Recorder;
+use llas_lib::audio::mixer::{MixerCommand, MixerHandle};
+use llas_lib::audio::soundboard::Soundboard;
+use llas_lib::audio::realtime::{CallbackStats, CallbackTimer, CaptureCallback, PlaybackCallback};
+
+const PERIOD_FRAMES: usize = 480;
+const RUN_FOR: Duration = Duration::from_secs(5);
+const PEERS: u16 = 4;
+
+fn report(name: &str, stats: &CallbackStats) {
+    println!(
+        "{:<8} callbacks={:<6} mean={:>8.2}us worst={:>8.2}us overruns={} dropped_samples={}",
+        name, stats.callbacks, stats.mean_us, stats.worst_us, stats.overruns, stats.dropped_samples
+    );
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    for settings in [EncoderSettings::default(), EncoderSettings::music()] {
+        let channels = settings.channels() as usize;
+        println!("profile: {} channel(s), {} ms frames", channels, settings.frame_size as f32 / 10.0);
+
+        let dsp = DspControls::default();
+        let input_timer = Arc::new(CallbackTimer::default());
+        let output_timer = Arc::new(CallbackTimer::default());
+
+        let mixer = MixerHandle::spawn(Arc::new(AtomicF32::new(1.0)))?;
+        let (capture_producer, capture_consumer) = HeapRb::<f32>::new(4800 * channels).split();
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(100);
+        let shared = EngineShared {
+            tx,
+            mixer: mixer.clone(),
+            is_muted: Arc::new(AtomicBool::new(false)),
+            input_volume: Arc::new(AtomicF32::new(1.0)),
+            dsp: dsp.clone(),
+            loopback: Loopback::default(),
+            recorder: Recorder::default(),
+            soundboard: Soundboard::default(),
+            input_timer: input_timer.clone(),
+            output_timer: output_timer.clone(),
+        };
+        let worker = spawn_capture_worker(capture_consumer, settings.clone(), shared)?;
+        let mut capture = CaptureCallback::new(capture_producer, channels, SAMPLE_RATE, input_timer.clone());
+
+        let (playback_producer, playback_consumer) = HeapRb::<f32>::new(4800 * channels).split();
+        mixer.send(MixerCommand::SetOutput { producer: playback_producer, channels });
+        let mut playback = PlaybackCallback::new(
+            playback_consumer,
+            channels,
+            SAMPLE_RATE,
+            Arc::new(AtomicBool::new(false)),
+            dsp.echo_reference(),
+            output_timer.clone(),
+        );
+
+        let mut input = vec![0f32; PERIOD_FRAMES * channels];
+        let mut output = vec![0f32; PERIOD_FRAMES * channels];
+        let mut phase = 0f32;
+        let started = Instant::now();
+        let mut next = started;
+        while started.elapsed() < RUN_FOR {
+            for frame in input.chunks_exact_mut(channels) {
+                let sample = (phase * std::f32::consts::TAU).sin() * 0.25;
+                phase = (phase + 440.0 / SAMPLE_RATE as f32).fract();
+                frame.fill(sample);
+            }
+            capture.process(&input);
+            playback.process(&mut output);
+
+            // Loop our own packets back in as if several peers were talking.
+            while let Ok(packet) = rx.try_recv() {
+                for port in 0..PEERS {
+                    mixer.send(MixerCommand::Packet {
+                        from: SocketAddr::from(([127, 0, 0, 1], 40000 + port)),
+                        payload: packet.clone(),
+                        stereo: settings.stereo,
+                        whisper: false,
+                    });
+                }
+            }
+
+            next += Duration::from_micros(10_000);
+            if let Some(wait) = next.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+        }
+
+        report("capture", &input_timer.stats());
+        report("playback", &output_timer.stats());
+        drop(worker);
+    }
+    Ok(())
+}
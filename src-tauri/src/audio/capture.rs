@@ -0,0 +1,104 @@
+// src-tauri/src/audio/capture.rs
+
+use ringbuf::HeapConsumer;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use super::codec::{EncoderSettings, MAX_PACKET_SIZE, SAMPLE_RATE};
+use super::dsp::DspChain;
+use super::engine::EngineShared;
+use super::mixer::MixerCommand;
+use super::recorder::TrackSource;
+use super::realtime::Worker;
+
+// How long the worker sleeps when the capture ring is empty.
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+/// Runs the DSP chain and the Opus encoder on its own thread, fed by the
+/// capture ring the input callback writes into. The input volume is applied
+/// before the DSP chain; soundboard clips are mixed in ahead of its limiter,
+/// so they can't push the encoded signal past the ceiling either.
+pub fn spawn_capture_worker(
+    mut consumer: HeapConsumer<f32>,
+    settings: EncoderSettings,
+    shared: EngineShared,
+) -> Result<Worker, Box<dyn std::error::Error>> {
+    let mut encoder = settings.build_encoder()?;
+    let channels = settings.channels() as usize;
+    let frame_len = settings.frame_samples() * channels;
+
+    let worker = Worker::spawn("llas-capture", move |stop: Arc<AtomicBool>| {
+        // One chain per channel; the stages themselves are mono.
+        let mut chains: Vec<DspChain> = (0..channels)
+            .map(|_| DspChain::new(SAMPLE_RATE, shared.dsp.clone()))
+            .collect();
+        let mut frame = vec![0f32; frame_len];
+        let mut channel_buf = Vec::with_capacity(frame_len / channels);
+        let mut injected = vec![0f32; frame_len];
+        let mut opus_data = vec![0u8; MAX_PACKET_SIZE];
+
+        while !stop.load(Ordering::Relaxed) {
+            if consumer.len() < frame_len {
+                std::thread::sleep(IDLE_WAIT);
+                continue;
+            }
+            consumer.pop_slice(&mut frame);
+
+            let input_volume = shared.input_volume.load(Ordering::Relaxed);
+            if input_volume != 1.0 {
+                for sample in frame.iter_mut() {
+                    *sample *= input_volume;
+                }
+            }
+
+            each_channel(&mut chains, &mut frame, &mut channel_buf, DspChain::shape);
+
+            if let Some(monitor) = shared.soundboard.render(&mut injected, channels) {
+                for (sample, clip) in frame.iter_mut().zip(&injected) {
+                    *sample += clip;
+                }
+                if monitor {
+                    shared.mixer.send(MixerCommand::Monitor {
+                        samples: injected.clone(),
+                        channels,
+                    });
+                }
+            }
+
+            each_channel(&mut chains, &mut frame, &mut channel_buf, DspChain::limit);
+
+            match encoder.encode_float(&frame, &mut opus_data) {
+                Ok(size) => {
+                    shared.loopback.tap(&opus_data[..size]);
+                    shared.recorder.tap(TrackSource::Local, &opus_data[..size], settings.stereo);
+                    let _ = shared.tx.try_send(opus_data[..size].to_vec());
+                }
+                Err(e) => eprintln!("Error encoding audio: {}", e),
+            }
+        }
+    })?;
+    Ok(worker)
+}
+
+/// Runs `pass` of each channel's chain over its samples of the interleaved
+/// `frame`.
+fn each_channel(
+    chains: &mut [DspChain],
+    frame: &mut [f32],
+    channel_buf: &mut Vec<f32>,
+    pass: fn(&mut DspChain, &mut [f32]),
+) {
+    let channels = chains.len();
+    if channels == 1 {
+        pass(&mut chains[0], frame);
+        return;
+    }
+    for (ch, chain) in chains.iter_mut().enumerate() {
+        channel_buf.clear();
+        channel_buf.extend(frame.iter().skip(ch).step_by(channels));
+        pass(chain, channel_buf);
+        for (i, sample) in channel_buf.iter().enumerate() {
+            frame[i * channels + ch] = *sample;
+        }
+    }
+}
@@ -0,0 +1,253 @@
+// src-tauri/src/audio/codec.rs
+
+use audiopus_sys as ffi;
+use serde::{Serialize, Deserialize};
+use std::ffi::CStr;
+use std::os::raw::c_int;
+use std::ptr::NonNull;
+use opus::{Channels, Decoder};
+
+pub const SAMPLE_RATE: u32 = 48000;
+// Largest Opus frame (120 ms) per channel; decoders size their output for this.
+pub const MAX_FRAME_SAMPLES: usize = 5760;
+// Recommended upper bound for a single encoded packet.
+pub const MAX_PACKET_SIZE: usize = 4000;
+
+/// What a room is used for. Music rooms stream stereo at a high bitrate and
+/// bypass the voice processing stages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioMode {
+    #[default]
+    Voice,
+    Music,
+}
+
+impl AudioMode {
+    pub fn default_encoder_settings(self) -> EncoderSettings {
+        match self {
+            AudioMode::Voice => EncoderSettings::default(),
+            AudioMode::Music => EncoderSettings::music(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncoderApplication {
+    Voip,
+    Audio,
+    LowDelay,
+}
+
+impl EncoderApplication {
+    fn code(self) -> c_int {
+        match self {
+            EncoderApplication::Voip => ffi::OPUS_APPLICATION_VOIP,
+            EncoderApplication::Audio => ffi::OPUS_APPLICATION_AUDIO,
+            EncoderApplication::LowDelay => ffi::OPUS_APPLICATION_RESTRICTED_LOWDELAY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BitrateMode {
+    Vbr,
+    Cvbr,
+    Cbr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncoderBandwidth {
+    Auto,
+    Narrowband,
+    Mediumband,
+    Wideband,
+    Superwideband,
+    Fullband,
+}
+
+impl EncoderBandwidth {
+    fn code(self) -> c_int {
+        match self {
+            EncoderBandwidth::Auto => ffi::OPUS_AUTO,
+            EncoderBandwidth::Narrowband => ffi::OPUS_BANDWIDTH_NARROWBAND,
+            EncoderBandwidth::Mediumband => ffi::OPUS_BANDWIDTH_MEDIUMBAND,
+            EncoderBandwidth::Wideband => ffi::OPUS_BANDWIDTH_WIDEBAND,
+            EncoderBandwidth::Superwideband => ffi::OPUS_BANDWIDTH_SUPERWIDEBAND,
+            EncoderBandwidth::Fullband => ffi::OPUS_BANDWIDTH_FULLBAND,
+        }
+    }
+}
+
+/// Opus encoder profile. A room carries one of these and every participant
+/// encodes with it, so all peers in the room agree on the stream format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncoderSettings {
+    pub application: EncoderApplication,
+    /// Target bitrate in bits per second; `None` lets libopus decide.
+    pub bitrate: Option<i32>,
+    pub bitrate_mode: BitrateMode,
+    /// 0 (fastest) to 10 (best quality).
+    pub complexity: u8,
+    /// Frame duration in tenths of a millisecond: 25, 50, 100, 200, 400 or 600.
+    pub frame_size: u16,
+    pub stereo: bool,
+    pub bandwidth: EncoderBandwidth,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        Self {
+            application: EncoderApplication::Voip,
+            bitrate: None,
+            bitrate_mode: BitrateMode::Vbr,
+            complexity: 10,
+            frame_size: 100,
+            stereo: false,
+            bandwidth: EncoderBandwidth::Auto,
+        }
+    }
+}
+
+impl EncoderSettings {
+    pub fn music() -> Self {
+        Self {
+            application: EncoderApplication::Audio,
+            bitrate: Some(192000),
+            bitrate_mode: BitrateMode::Cvbr,
+            complexity: 10,
+            frame_size: 200,
+            stereo: true,
+            bandwidth: EncoderBandwidth::Fullband,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if ![25, 50, 100, 200, 400, 600].contains(&self.frame_size) {
+            return Err(format!("Unsupported frame size: {}", self.frame_size));
+        }
+        if let Some(bitrate) = self.bitrate {
+            if !(6000..=510000).contains(&bitrate) {
+                return Err(format!("Bitrate out of range: {}", bitrate));
+            }
+        }
+        if self.complexity > 10 {
+            return Err(format!("Complexity out of range: {}", self.complexity));
+        }
+        Ok(())
+    }
+
+    pub fn channels(&self) -> u16 {
+        if self.stereo { 2 } else { 1 }
+    }
+
+    /// Samples per channel in one encoded frame.
+    pub fn frame_samples(&self) -> usize {
+        SAMPLE_RATE as usize * self.frame_size as usize / 10000
+    }
+
+    /// Builds an encoder for this profile, every field applied.
+    pub fn build_encoder(&self) -> Result<Encoder, String> {
+        self.validate()?;
+        let mut encoder = Encoder::new(self.channels(), self.application)?;
+        encoder.ctl("bitrate", ffi::OPUS_SET_BITRATE_REQUEST, self.bitrate.unwrap_or(ffi::OPUS_AUTO))?;
+        let (vbr, constrained) = match self.bitrate_mode {
+            BitrateMode::Vbr => (1, 0),
+            BitrateMode::Cvbr => (1, 1),
+            BitrateMode::Cbr => (0, 0),
+        };
+        encoder.ctl("VBR", ffi::OPUS_SET_VBR_REQUEST, vbr)?;
+        encoder.ctl("VBR constraint", ffi::OPUS_SET_VBR_CONSTRAINT_REQUEST, constrained)?;
+        encoder.ctl("complexity", ffi::OPUS_SET_COMPLEXITY_REQUEST, self.complexity as c_int)?;
+        encoder.ctl("bandwidth", ffi::OPUS_SET_BANDWIDTH_REQUEST, self.bandwidth.code())?;
+        Ok(encoder)
+    }
+}
+
+fn opus_error(what: &str, code: c_int) -> String {
+    // libopus returns a static string for any code.
+    let description = unsafe { CStr::from_ptr(ffi::opus_strerror(code)) };
+    format!("Opus {} failed: {}", what, description.to_string_lossy())
+}
+
+/// Opus encoder configured from an `EncoderSettings`. Drives libopus
+/// directly because `opus` 0.3 neither wraps the complexity and bandwidth
+/// controls nor hands out its encoder pointer.
+pub struct Encoder {
+    ptr: NonNull<ffi::OpusEncoder>,
+    channels: usize,
+}
+
+// The encoder state is plain memory owned by this handle, and every call
+// takes `&mut self`.
+unsafe impl Send for Encoder {}
+
+impl Encoder {
+    fn new(channels: u16, application: EncoderApplication) -> Result<Self, String> {
+        let mut error = ffi::OPUS_OK;
+        let ptr = unsafe { ffi::opus_encoder_create(SAMPLE_RATE as i32, channels as c_int, application.code(), &mut error) };
+        match NonNull::new(ptr) {
+            Some(ptr) if error == ffi::OPUS_OK => Ok(Self { ptr, channels: channels as usize }),
+            Some(ptr) => {
+                unsafe { ffi::opus_encoder_destroy(ptr.as_ptr()) };
+                Err(opus_error("encoder setup", error))
+            }
+            None => Err(opus_error("encoder setup", error)),
+        }
+    }
+
+    fn ctl(&mut self, what: &str, request: c_int, value: c_int) -> Result<(), String> {
+        let code = unsafe { ffi::opus_encoder_ctl(self.ptr.as_ptr(), request, value) };
+        if code < 0 {
+            return Err(opus_error(what, code));
+        }
+        Ok(())
+    }
+
+    /// Encodes one frame of interleaved samples into `output`. Returns the
+    /// packet length.
+    pub fn encode_float(&mut self, input: &[f32], output: &mut [u8]) -> Result<usize, String> {
+        let frame_size = (input.len() / self.channels) as c_int;
+        let max_bytes = output.len().min(i32::MAX as usize) as i32;
+        let len = unsafe { ffi::opus_encode_float(self.ptr.as_ptr(), input.as_ptr(), frame_size, output.as_mut_ptr(), max_bytes) };
+        if len < 0 {
+            return Err(opus_error("encode", len));
+        }
+        Ok(len as usize)
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe { ffi::opus_encoder_destroy(self.ptr.as_ptr()) };
+    }
+}
+
+/// Decoder that follows the channel layout flagged on each packet. Opus can
+/// decode either layout with either decoder, but matching them keeps stereo
+/// peers in stereo.
+pub struct StreamDecoder {
+    decoder: Decoder,
+    stereo: bool,
+}
+
+impl StreamDecoder {
+    pub fn new(stereo: bool) -> Result<Self, opus::Error> {
+        let channels = if stereo { Channels::Stereo } else { Channels::Mono };
+        Ok(Self {
+            decoder: Decoder::new(SAMPLE_RATE, channels)?,
+            stereo,
+        })
+    }
+
+    /// Returns the number of samples per channel written to `output`.
+    pub fn decode(&mut self, data: &[u8], stereo: bool, output: &mut [f32]) -> Result<usize, opus::Error> {
+        if stereo != self.stereo {
+            *self = Self::new(stereo)?;
+        }
+        self.decoder.decode_float(data, output, false)
+    }
+}
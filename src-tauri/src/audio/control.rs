@@ -0,0 +1,287 @@
+// src-tauri/src/audio/control.rs
+//
+// Control messages ride the audio stream in packets flagged
+// `FLAG_CONTROL`, sealed with the room key like everything else. UDP loses
+// and reorders packets, so each peer gets its own numbered stream of chat
+// packets, acknowledges every one and has them resent until it does; the
+// receiving side puts them back in order. Texts too long for one packet
+// are split across consecutive ones. A peer whose address changed says so
+// from the new one, naming the old.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+const KIND_CHAT: u8 = 1;
+const KIND_ACK: u8 = 2;
+const KIND_MOVED: u8 = 3;
+// The text continues in the next packet of the stream.
+const CHAT_MORE: u8 = 0x01;
+// kind (1) + stream (8) + sequence (8)
+const ACK_LEN: usize = 17;
+// ... + flags (1), then the text.
+const CHAT_HEADER_LEN: usize = 18;
+/// Most text bytes in one packet, so chat stays well inside a datagram.
+pub const MAX_CHAT_CHUNK: usize = 1000;
+/// Unacknowledged chat packets are resent this often...
+pub const RETRANSMIT_AFTER: Duration = Duration::from_millis(500);
+/// ...this many times, before the stream to that peer is given up.
+const MAX_ATTEMPTS: u32 = 10;
+/// Packets accepted ahead of the next one due; later ones are left for the
+/// sender to resend.
+const REORDER_WINDOW: u64 = 256;
+/// Longest text reassembled from one sender, in bytes.
+const MAX_TEXT_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlMessage {
+    /// Part of a chat text; `more` when the next packet continues it.
+    Chat { stream: u64, sequence: u64, more: bool, text: String },
+    /// The chat packet `sequence` of `stream` arrived.
+    Ack { stream: u64, sequence: u64 },
+    /// The sender reconnected and was reachable at `old` until now.
+    Moved { old: SocketAddr },
+}
+
+impl ControlMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            ControlMessage::Chat { stream, sequence, more, text } => {
+                let mut out = Vec::with_capacity(CHAT_HEADER_LEN + text.len());
+                out.push(KIND_CHAT);
+                out.extend_from_slice(&stream.to_be_bytes());
+                out.extend_from_slice(&sequence.to_be_bytes());
+                out.push(if *more { CHAT_MORE } else { 0 });
+                out.extend_from_slice(text.as_bytes());
+                out
+            }
+            ControlMessage::Ack { stream, sequence } => {
+                let mut out = Vec::with_capacity(ACK_LEN);
+                out.push(KIND_ACK);
+                out.extend_from_slice(&stream.to_be_bytes());
+                out.extend_from_slice(&sequence.to_be_bytes());
+                out
+            }
+            ControlMessage::Moved { old } => {
+                let mut out = vec![KIND_MOVED];
+                match old.ip() {
+                    IpAddr::V4(ip) => {
+                        out.push(4);
+                        out.extend_from_slice(&ip.octets());
+                    }
+                    IpAddr::V6(ip) => {
+                        out.push(6);
+                        out.extend_from_slice(&ip.octets());
+                    }
+                }
+                out.extend_from_slice(&old.port().to_be_bytes());
+                out
+            }
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let kind = *data.first()?;
+        match kind {
+            KIND_CHAT | KIND_ACK => {
+                let stream = u64::from_be_bytes(data.get(1..9)?.try_into().ok()?);
+                let sequence = u64::from_be_bytes(data.get(9..17)?.try_into().ok()?);
+                if kind == KIND_ACK {
+                    return (data.len() == ACK_LEN).then_some(ControlMessage::Ack { stream, sequence });
+                }
+                let flags = *data.get(17)?;
+                let text = std::str::from_utf8(&data[CHAT_HEADER_LEN..]).ok()?.to_string();
+                Some(ControlMessage::Chat { stream, sequence, more: flags & CHAT_MORE != 0, text })
+            }
+            KIND_MOVED => {
+                let (ip, port) = match (data.get(1)?, data.len()) {
+                    (4, 8) => (IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(&data[2..6]).ok()?)), &data[6..]),
+                    (6, 20) => (IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&data[2..18]).ok()?)), &data[18..]),
+                    _ => return None,
+                };
+                Some(ControlMessage::Moved { old: SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]])) })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Splits `text` into pieces of at most `MAX_CHAT_CHUNK` bytes, on
+/// character boundaries.
+fn chunks(text: &str) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while rest.len() > MAX_CHAT_CHUNK {
+        let mut end = MAX_CHAT_CHUNK;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks.push(rest);
+    chunks
+}
+
+struct Pending {
+    packet: Vec<u8>,
+    sent_at: Instant,
+    attempts: u32,
+}
+
+/// Our chat stream to one peer.
+struct OutboundStream {
+    id: u64,
+    next_sequence: u64,
+    pending: BTreeMap<u64, Pending>,
+}
+
+impl OutboundStream {
+    fn new() -> Self {
+        Self { id: rand::random(), next_sequence: 0, pending: BTreeMap::new() }
+    }
+}
+
+/// Chat we sent that peers haven't acknowledged yet.
+#[derive(Default)]
+pub struct ChatOutbox {
+    streams: HashMap<SocketAddr, OutboundStream>,
+}
+
+impl ChatOutbox {
+    /// Numbers `text` on each of `peers`' streams. Returns the packets to
+    /// send now.
+    pub fn send(&mut self, text: &str, peers: &[SocketAddr], now: Instant) -> Vec<(SocketAddr, Vec<u8>)> {
+        let chunks = chunks(text);
+        let mut packets = Vec::new();
+        for peer in peers {
+            let stream = self.streams.entry(*peer).or_insert_with(OutboundStream::new);
+            for (index, chunk) in chunks.iter().enumerate() {
+                let sequence = stream.next_sequence;
+                stream.next_sequence += 1;
+                let packet = ControlMessage::Chat {
+                    stream: stream.id,
+                    sequence,
+                    more: index + 1 < chunks.len(),
+                    text: chunk.to_string(),
+                }
+                .encode();
+                stream.pending.insert(sequence, Pending { packet: packet.clone(), sent_at: now, attempts: 1 });
+                packets.push((*peer, packet));
+            }
+        }
+        packets
+    }
+
+    pub fn acked(&mut self, from: SocketAddr, stream: u64, sequence: u64) {
+        if let Some(outbound) = self.streams.get_mut(&from).filter(|outbound| outbound.id == stream) {
+            outbound.pending.remove(&sequence);
+        }
+    }
+
+    /// Packets due to be sent again. A peer that never acknowledges one
+    /// gets a new stream, so what follows isn't stuck behind it.
+    pub fn due(&mut self, now: Instant) -> Vec<(SocketAddr, Vec<u8>)> {
+        let mut packets = Vec::new();
+        let mut abandoned = Vec::new();
+        for (peer, stream) in &mut self.streams {
+            for pending in stream.pending.values_mut() {
+                if now.duration_since(pending.sent_at) < RETRANSMIT_AFTER {
+                    continue;
+                }
+                if pending.attempts >= MAX_ATTEMPTS {
+                    abandoned.push(*peer);
+                    break;
+                }
+                pending.attempts += 1;
+                pending.sent_at = now;
+                packets.push((*peer, pending.packet.clone()));
+            }
+        }
+        for peer in abandoned {
+            eprintln!("Chat to {} went unacknowledged, dropping it", peer);
+            self.streams.remove(&peer);
+        }
+        packets
+    }
+
+    pub fn forget(&mut self, peer: &SocketAddr) {
+        self.streams.remove(peer);
+    }
+
+    /// Carries the stream to a peer over to its new address.
+    pub fn readdress(&mut self, old: &SocketAddr, new: SocketAddr) {
+        if let Some(stream) = self.streams.remove(old) {
+            self.streams.insert(new, stream);
+        }
+    }
+}
+
+/// One peer's chat stream to us.
+struct InboundStream {
+    id: u64,
+    next_sequence: u64,
+    early: BTreeMap<u64, (bool, String)>,
+    partial: String,
+    // Skipping the rest of a text that grew too long.
+    discarding: bool,
+}
+
+impl InboundStream {
+    fn new(id: u64) -> Self {
+        Self { id, next_sequence: 0, early: BTreeMap::new(), partial: String::new(), discarding: false }
+    }
+}
+
+/// Puts peers' chat packets back in order and joins split texts.
+#[derive(Default)]
+pub struct ChatInbox {
+    streams: HashMap<SocketAddr, InboundStream>,
+}
+
+impl ChatInbox {
+    /// Takes one chat packet from `from`. Returns whether to acknowledge
+    /// it, and the texts it completed, in the order they were sent.
+    pub fn receive(&mut self, from: SocketAddr, stream: u64, sequence: u64, more: bool, text: String) -> (bool, Vec<String>) {
+        let inbound = self.streams.entry(from).or_insert_with(|| InboundStream::new(stream));
+        if inbound.id != stream {
+            // The sender started over.
+            *inbound = InboundStream::new(stream);
+        }
+        if sequence < inbound.next_sequence {
+            // Our ack was lost; acknowledge it again.
+            return (true, Vec::new());
+        }
+        if sequence - inbound.next_sequence >= REORDER_WINDOW {
+            return (false, Vec::new());
+        }
+        inbound.early.insert(sequence, (more, text));
+
+        let mut texts = Vec::new();
+        while let Some((more, text)) = inbound.early.remove(&inbound.next_sequence) {
+            inbound.next_sequence += 1;
+            if inbound.discarding || inbound.partial.len() + text.len() > MAX_TEXT_BYTES {
+                inbound.partial.clear();
+                inbound.discarding = more;
+                continue;
+            }
+            inbound.partial.push_str(&text);
+            if !more {
+                texts.push(std::mem::take(&mut inbound.partial));
+            }
+        }
+        (true, texts)
+    }
+
+    pub fn forget(&mut self, peer: &SocketAddr) {
+        self.streams.remove(peer);
+    }
+
+    /// Carries a peer's stream over to its new address.
+    pub fn readdress(&mut self, old: &SocketAddr, new: SocketAddr) {
+        if let Some(stream) = self.streams.remove(old) {
+            self.streams.insert(new, stream);
+        }
+    }
+}
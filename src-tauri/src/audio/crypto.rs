@@ -0,0 +1,468 @@
+// src-tauri/src/audio/crypto.rs
+//
+// Audio payloads are sealed with ChaCha20-Poly1305 under a per-room key.
+// The packet header stays readable but is authenticated, so a relay can
+// neither read the audio nor alter its sequence, timestamp or flags. The
+// room key itself travels inside envelopes wrapped with an X25519 shared
+// secret between the member handing it out and the member receiving it.
+
+use bytes::{BufMut, BytesMut};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use uuid::Uuid;
+use x25519_dalek::{PublicKey, StaticSecret};
+use super::packet::{PacketHeader, FLAG_ENCRYPTED, HEADER_LEN};
+
+// Random per-session prefix of every packet nonce; the sequence number fills the rest.
+pub const SALT_LEN: usize = 8;
+pub const TAG_LEN: usize = 16;
+// How far behind the newest sequence number a packet may arrive.
+const REPLAY_WINDOW: u32 = 64;
+const WRAP_INFO: &[u8] = b"llas room key wrap v1";
+
+/// Symmetric key shared by every member of a room.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RoomKey([u8; 32]);
+
+impl RoomKey {
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+}
+
+/// A room key encrypted for one member.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyEnvelope {
+    /// Public key of the member who wrapped the room key.
+    pub sender: [u8; 32],
+    pub recipient: [u8; 32],
+    pub nonce: [u8; 12],
+    pub ciphertext: Vec<u8>,
+}
+
+/// Our long-term X25519 key pair.
+pub struct Identity {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self::from_secret(rand::random())
+    }
+
+    /// Restores a key pair saved with `secret_bytes`.
+    pub fn from_secret(secret: [u8; 32]) -> Self {
+        let secret = StaticSecret::from(secret);
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    pub fn secret_bytes(&self) -> [u8; 32] {
+        self.secret.to_bytes()
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+
+    /// Key-encryption key for envelopes between us and `peer` in `room_id`.
+    /// Both ends derive the same key whichever of them is the sender.
+    fn wrapping_cipher(&self, peer: &[u8; 32], sender: &[u8; 32], recipient: &[u8; 32], room_id: Uuid) -> Result<ChaCha20Poly1305, String> {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(*peer));
+        if !shared.was_contributory() {
+            return Err("Refusing a low-order public key".to_string());
+        }
+        let mut info = Vec::with_capacity(WRAP_INFO.len() + 64);
+        info.extend_from_slice(WRAP_INFO);
+        info.extend_from_slice(sender);
+        info.extend_from_slice(recipient);
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(room_id.as_bytes()), shared.as_bytes())
+            .expand(&info, &mut key)
+            .map_err(|e| e.to_string())?;
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+
+    /// Encrypts `key` so only the holder of `recipient`'s secret can read it.
+    pub fn wrap_key(&self, recipient: &[u8; 32], room_id: Uuid, key: &RoomKey) -> Result<KeyEnvelope, String> {
+        let sender = self.public_key();
+        let cipher = self.wrapping_cipher(recipient, &sender, recipient, room_id)?;
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &key.0, aad: room_id.as_bytes() })
+            .map_err(|_| "Failed to wrap room key".to_string())?;
+        Ok(KeyEnvelope { sender, recipient: *recipient, nonce, ciphertext })
+    }
+
+    pub fn unwrap_key(&self, envelope: &KeyEnvelope, room_id: Uuid) -> Result<RoomKey, String> {
+        if envelope.recipient != self.public_key() {
+            return Err("Room key was wrapped for someone else".to_string());
+        }
+        let cipher = self.wrapping_cipher(&envelope.sender, &envelope.sender, &envelope.recipient, room_id)?;
+        let key = cipher
+            .decrypt(
+                Nonce::from_slice(&envelope.nonce),
+                Payload { msg: &envelope.ciphertext, aad: room_id.as_bytes() },
+            )
+            .map_err(|_| "Room key envelope failed authentication".to_string())?;
+        let key: [u8; 32] = key.try_into().map_err(|_| "Room key has the wrong length".to_string())?;
+        Ok(RoomKey(key))
+    }
+}
+
+/// A public key as 64 hex digits, for pasting into settings.
+pub fn format_public_key(key: &[u8; 32]) -> String {
+    key.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn parse_public_key(text: &str) -> Result<[u8; 32], String> {
+    let text = text.trim();
+    if text.len() != 64 || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("A public key is 64 hex digits".to_string());
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).map_err(|_| "A public key is 64 hex digits".to_string())?;
+    }
+    Ok(key)
+}
+
+fn packet_nonce(salt: &[u8], sequence: u32) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..SALT_LEN].copy_from_slice(salt);
+    nonce[SALT_LEN..].copy_from_slice(&sequence.to_be_bytes());
+    nonce
+}
+
+/// Encrypts our outgoing packets. Every sealer picks a fresh salt, so
+/// sequence numbers restarting with a new session never reuse a nonce.
+pub struct PacketSealer {
+    cipher: ChaCha20Poly1305,
+    salt: [u8; SALT_LEN],
+}
+
+impl PacketSealer {
+    pub fn new(key: &RoomKey) -> Self {
+        Self {
+            cipher: key.cipher(),
+            salt: rand::random(),
+        }
+    }
+
+    /// Turns a datagram from `build_audio_packet` into header, salt and
+    /// sealed payload. The header and salt are authenticated, not encrypted.
+    pub fn seal(&self, mut packet: BytesMut) -> Result<BytesMut, String> {
+        if packet.len() < HEADER_LEN {
+            return Err("Packet is shorter than its header".to_string());
+        }
+        packet[HEADER_LEN - 1] |= FLAG_ENCRYPTED;
+        let payload = packet.split_off(HEADER_LEN);
+        packet.put_slice(&self.salt);
+        let sequence = u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&packet_nonce(&self.salt, sequence)),
+                Payload { msg: &payload, aad: &packet },
+            )
+            .map_err(|_| "Failed to encrypt audio packet".to_string())?;
+        packet.put_slice(&ciphertext);
+        Ok(packet)
+    }
+}
+
+/// Why an incoming packet was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenError {
+    Malformed,
+    Unencrypted,
+    Unauthenticated,
+    Replayed,
+}
+
+impl std::fmt::Display for OpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OpenError::Malformed => "malformed packet",
+            OpenError::Unencrypted => "unencrypted packet",
+            OpenError::Unauthenticated => "packet failed authentication",
+            OpenError::Replayed => "replayed packet",
+        })
+    }
+}
+
+/// Sliding window over one sender session's sequence numbers.
+struct ReplayWindow {
+    salt: [u8; SALT_LEN],
+    newest_timestamp: u64,
+    highest: u32,
+    // Bit n set: `highest - n` has been seen.
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn accepts(&self, salt: &[u8], header: &PacketHeader) -> bool {
+        if salt != self.salt {
+            // A new session from this peer must be newer than anything we
+            // have seen, or an old session could be replayed from scratch.
+            return header.timestamp > self.newest_timestamp;
+        }
+        if header.sequence > self.highest {
+            return true;
+        }
+        let age = self.highest - header.sequence;
+        age < REPLAY_WINDOW && self.seen & (1 << age) == 0
+    }
+
+    fn record(&mut self, salt: &[u8], header: &PacketHeader) {
+        if salt != self.salt {
+            *self = ReplayWindow::new(salt, header);
+            return;
+        }
+        self.newest_timestamp = self.newest_timestamp.max(header.timestamp);
+        if header.sequence > self.highest {
+            let shift = header.sequence - self.highest;
+            self.seen = if shift < REPLAY_WINDOW { (self.seen << shift) | 1 } else { 1 };
+            self.highest = header.sequence;
+        } else {
+            self.seen |= 1 << (self.highest - header.sequence);
+        }
+    }
+
+    fn new(salt: &[u8], header: &PacketHeader) -> Self {
+        let mut window_salt = [0u8; SALT_LEN];
+        window_salt.copy_from_slice(salt);
+        Self {
+            salt: window_salt,
+            newest_timestamp: header.timestamp,
+            highest: header.sequence,
+            seen: 1,
+        }
+    }
+}
+
+/// Authenticates and decrypts incoming packets, rejecting replays per sender.
+pub struct PacketOpener {
+    cipher: ChaCha20Poly1305,
+    windows: HashMap<SocketAddr, ReplayWindow>,
+}
+
+impl PacketOpener {
+    pub fn new(key: &RoomKey) -> Self {
+        Self {
+            cipher: key.cipher(),
+            windows: HashMap::new(),
+        }
+    }
+
+    pub fn open(&mut self, from: SocketAddr, datagram: &[u8]) -> Result<(PacketHeader, Vec<u8>), OpenError> {
+        let (header, rest) = PacketHeader::parse(datagram).ok_or(OpenError::Malformed)?;
+        if !header.is_encrypted() {
+            return Err(OpenError::Unencrypted);
+        }
+        if rest.len() < SALT_LEN + TAG_LEN {
+            return Err(OpenError::Malformed);
+        }
+        let (salt, ciphertext) = rest.split_at(SALT_LEN);
+        if self.windows.get(&from).is_some_and(|window| !window.accepts(salt, &header)) {
+            return Err(OpenError::Replayed);
+        }
+
+        let payload = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&packet_nonce(salt, header.sequence)),
+                Payload { msg: ciphertext, aad: &datagram[..HEADER_LEN + SALT_LEN] },
+            )
+            .map_err(|_| OpenError::Unauthenticated)?;
+
+        // Only authenticated packets move the window.
+        match self.windows.get_mut(&from) {
+            Some(window) => window.record(salt, &header),
+            None => {
+                self.windows.insert(from, ReplayWindow::new(salt, &header));
+            }
+        }
+        Ok((header, payload))
+    }
+
+    /// The sender whose session `datagram` continues, by its salt: the
+    /// address a peer had before its own changed. Nothing is decrypted.
+    pub fn session_owner(&self, datagram: &[u8]) -> Option<SocketAddr> {
+        let (_, rest) = PacketHeader::parse(datagram)?;
+        let salt = rest.get(..SALT_LEN)?;
+        self.windows
+            .iter()
+            .find(|(_, window)| window.salt == salt)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Keeps the replay window of a sender that moved to `new`.
+    pub fn readdress(&mut self, old: &SocketAddr, new: SocketAddr) {
+        if let Some(window) = self.windows.remove(old) {
+            self.windows.insert(new, window);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from() -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], 5000))
+    }
+
+    fn packet(sequence: u32, timestamp: u64) -> BytesMut {
+        let mut packet = BytesMut::new();
+        PacketHeader { sequence, timestamp, flags: 0 }.write(&mut packet);
+        packet.put_slice(b"opus payload");
+        packet
+    }
+
+    fn sealed(sealer: &PacketSealer, sequence: u32, timestamp: u64) -> BytesMut {
+        sealer.seal(packet(sequence, timestamp)).unwrap()
+    }
+
+    #[test]
+    fn sealed_packets_open_to_their_header_and_payload() {
+        let key = RoomKey::generate();
+        let datagram = sealed(&PacketSealer::new(&key), 7, 1000);
+        assert_eq!(datagram.len(), HEADER_LEN + SALT_LEN + b"opus payload".len() + TAG_LEN);
+
+        let (header, payload) = PacketOpener::new(&key).open(from(), &datagram).unwrap();
+        assert_eq!(header, PacketHeader { sequence: 7, timestamp: 1000, flags: FLAG_ENCRYPTED });
+        assert_eq!(payload, b"opus payload");
+    }
+
+    #[test]
+    fn tampering_fails_authentication() {
+        let key = RoomKey::generate();
+        let datagram = sealed(&PacketSealer::new(&key), 7, 1000);
+        // A sequence bit, a payload byte and a salt byte.
+        for (index, bit) in [(3, 0x01), (HEADER_LEN + SALT_LEN, 0x80), (HEADER_LEN, 0x10)] {
+            let mut tampered = datagram.clone();
+            tampered[index] ^= bit;
+            assert_eq!(
+                PacketOpener::new(&key).open(from(), &tampered).unwrap_err(),
+                OpenError::Unauthenticated,
+                "byte {}",
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn other_keys_and_plain_packets_are_refused() {
+        let datagram = sealed(&PacketSealer::new(&RoomKey::generate()), 7, 1000);
+        let mut opener = PacketOpener::new(&RoomKey::generate());
+        assert_eq!(opener.open(from(), &datagram).unwrap_err(), OpenError::Unauthenticated);
+        assert_eq!(opener.open(from(), &packet(7, 1000)).unwrap_err(), OpenError::Unencrypted);
+        assert_eq!(opener.open(from(), &datagram[..HEADER_LEN - 1]).unwrap_err(), OpenError::Malformed);
+    }
+
+    #[test]
+    fn replay_window_accepts_late_packets_once() {
+        let key = RoomKey::generate();
+        let sealer = PacketSealer::new(&key);
+        let mut opener = PacketOpener::new(&key);
+        assert!(opener.open(from(), &sealed(&sealer, 100, 1000)).is_ok());
+        assert_eq!(opener.open(from(), &sealed(&sealer, 100, 1000)).unwrap_err(), OpenError::Replayed);
+
+        // Out of order but inside the window, once each.
+        assert!(opener.open(from(), &sealed(&sealer, 90, 1000)).is_ok());
+        assert_eq!(opener.open(from(), &sealed(&sealer, 90, 1000)).unwrap_err(), OpenError::Replayed);
+        assert!(opener.open(from(), &sealed(&sealer, 100 - (REPLAY_WINDOW - 1), 1000)).is_ok());
+
+        // Too far behind to tell whether we've seen it.
+        assert_eq!(opener.open(from(), &sealed(&sealer, 100 - REPLAY_WINDOW, 1000)).unwrap_err(), OpenError::Replayed);
+        assert_eq!(opener.open(from(), &sealed(&sealer, 10, 1000)).unwrap_err(), OpenError::Replayed);
+
+        // A big jump forward slides the window past everything before it.
+        assert!(opener.open(from(), &sealed(&sealer, 500, 1000)).is_ok());
+        assert_eq!(opener.open(from(), &sealed(&sealer, 100, 1000)).unwrap_err(), OpenError::Replayed);
+        assert!(opener.open(from(), &sealed(&sealer, 499, 1000)).is_ok());
+    }
+
+    #[test]
+    fn replay_windows_are_per_sender() {
+        let key = RoomKey::generate();
+        let sealer = PacketSealer::new(&key);
+        let mut opener = PacketOpener::new(&key);
+        let datagram = sealed(&sealer, 1, 1000);
+        assert!(opener.open(from(), &datagram).is_ok());
+        assert!(opener.open(SocketAddr::from(([10, 0, 0, 2], 5000)), &datagram).is_ok());
+    }
+
+    #[test]
+    fn a_new_session_must_be_newer_than_the_last() {
+        let key = RoomKey::generate();
+        let mut opener = PacketOpener::new(&key);
+        let first = PacketSealer::new(&key);
+        assert!(opener.open(from(), &sealed(&first, 50, 2000)).is_ok());
+
+        // A fresh salt restarts the sequence, but not with an old timestamp.
+        let replayed = PacketSealer::new(&key);
+        assert_eq!(opener.open(from(), &sealed(&replayed, 0, 1999)).unwrap_err(), OpenError::Replayed);
+        assert_eq!(opener.open(from(), &sealed(&replayed, 0, 2000)).unwrap_err(), OpenError::Replayed);
+
+        let restarted = PacketSealer::new(&key);
+        assert!(opener.open(from(), &sealed(&restarted, 0, 2001)).is_ok());
+        assert!(opener.open(from(), &sealed(&restarted, 1, 2002)).is_ok());
+        // Packets captured from the old session can't be slipped back in.
+        assert_eq!(opener.open(from(), &sealed(&first, 51, 2000)).unwrap_err(), OpenError::Replayed);
+    }
+
+    #[test]
+    fn wrapped_keys_open_only_for_their_recipient_and_room() {
+        let (alice, bob, carol) = (Identity::generate(), Identity::generate(), Identity::generate());
+        let room_id = Uuid::new_v4();
+        let key = RoomKey::generate();
+        let envelope = alice.wrap_key(&bob.public_key(), room_id, &key).unwrap();
+        assert!(bob.unwrap_key(&envelope, room_id).unwrap() == key);
+
+        assert!(carol.unwrap_key(&envelope, room_id).is_err());
+        assert!(bob.unwrap_key(&envelope, Uuid::new_v4()).is_err());
+        // Posing as the recipient doesn't help without its secret.
+        let readdressed = KeyEnvelope { recipient: carol.public_key(), ..envelope.clone() };
+        assert!(carol.unwrap_key(&readdressed, room_id).is_err());
+        let mut tampered = envelope;
+        tampered.ciphertext[0] ^= 1;
+        assert!(bob.unwrap_key(&tampered, room_id).is_err());
+    }
+
+    #[test]
+    fn low_order_public_keys_are_refused() {
+        let alice = Identity::generate();
+        let room_id = Uuid::new_v4();
+        let key = RoomKey::generate();
+        assert!(alice.wrap_key(&[0u8; 32], room_id, &key).is_err());
+
+        let mut envelope = Identity::generate().wrap_key(&alice.public_key(), room_id, &key).unwrap();
+        envelope.sender = [0u8; 32];
+        assert!(alice.unwrap_key(&envelope, room_id).is_err());
+    }
+
+    #[test]
+    fn public_keys_survive_formatting() {
+        let key = Identity::generate().public_key();
+        let text = format_public_key(&key);
+        assert_eq!(text.len(), 64);
+        assert_eq!(parse_public_key(&text).unwrap(), key);
+        assert_eq!(parse_public_key(&format!("  {}\n", text.to_uppercase())).unwrap(), key);
+
+        assert!(parse_public_key(&text[..62]).is_err());
+        assert!(parse_public_key(&format!("{}zz", &text[..62])).is_err());
+        assert!(parse_public_key("").is_err());
+    }
+}
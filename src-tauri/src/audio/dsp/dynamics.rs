@@ -0,0 +1,238 @@
+// src-tauri/src/audio/dsp/dynamics.rs
+
+use serde::{Serialize, Deserialize};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use atomic_float::AtomicF32;
+use super::DspStage;
+
+// Frames quieter than this don't move the AGC, so background noise between words isn't pumped up.
+const AGC_GATE_DBFS: f32 = -50.0;
+const AGC_MAX_ATTENUATION_DB: f32 = 20.0;
+
+// 1 ms lookahead at 48 kHz.
+const LIMITER_LOOKAHEAD: usize = 48;
+const LIMITER_RELEASE_MS: f32 = 80.0;
+
+fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Smoothing coefficient for a one-pole follower stepped once per `samples`.
+fn time_coefficient(time_ms: f32, samples: usize, sample_rate: f32) -> f32 {
+    let time_samples = (time_ms.max(0.1) / 1000.0) * sample_rate;
+    1.0 - (-(samples as f32) / time_samples).exp()
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AgcSettings {
+    pub target_dbfs: f32,
+    pub max_gain_db: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+}
+
+impl Default for AgcSettings {
+    fn default() -> Self {
+        Self {
+            target_dbfs: -18.0,
+            max_gain_db: 20.0,
+            attack_ms: 10.0,
+            release_ms: 400.0,
+        }
+    }
+}
+
+impl AgcSettings {
+    /// Pulls each parameter into the range the gain control works in.
+    pub fn clamped(self) -> Self {
+        Self {
+            target_dbfs: self.target_dbfs.clamp(-40.0, 0.0),
+            max_gain_db: self.max_gain_db.clamp(0.0, 40.0),
+            attack_ms: self.attack_ms.clamp(1.0, 1000.0),
+            release_ms: self.release_ms.clamp(10.0, 5000.0),
+        }
+    }
+}
+
+/// AGC parameters shared between the command handlers and the input callback.
+pub struct AgcControl {
+    target_dbfs: AtomicF32,
+    max_gain_db: AtomicF32,
+    attack_ms: AtomicF32,
+    release_ms: AtomicF32,
+}
+
+impl AgcControl {
+    pub fn new(settings: AgcSettings) -> Self {
+        let control = Self {
+            target_dbfs: AtomicF32::new(0.0),
+            max_gain_db: AtomicF32::new(0.0),
+            attack_ms: AtomicF32::new(0.0),
+            release_ms: AtomicF32::new(0.0),
+        };
+        control.store(settings);
+        control
+    }
+
+    pub fn store(&self, settings: AgcSettings) {
+        let settings = settings.clamped();
+        self.target_dbfs.store(settings.target_dbfs, Ordering::Relaxed);
+        self.max_gain_db.store(settings.max_gain_db, Ordering::Relaxed);
+        self.attack_ms.store(settings.attack_ms, Ordering::Relaxed);
+        self.release_ms.store(settings.release_ms, Ordering::Relaxed);
+    }
+
+    pub fn load(&self) -> AgcSettings {
+        AgcSettings {
+            target_dbfs: self.target_dbfs.load(Ordering::Relaxed),
+            max_gain_db: self.max_gain_db.load(Ordering::Relaxed),
+            attack_ms: self.attack_ms.load(Ordering::Relaxed),
+            release_ms: self.release_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Automatic gain control. Follows the speech level with separate attack and
+/// release times and steers it towards the target loudness. Strength scales
+/// how much of the correction is applied.
+pub struct AutomaticGainControl {
+    control: Arc<AgcControl>,
+    sample_rate: f32,
+    level_db: Option<f32>,
+    gain: f32,
+    strength: f32,
+}
+
+impl AutomaticGainControl {
+    pub fn new(sample_rate: u32, control: Arc<AgcControl>) -> Self {
+        Self {
+            control,
+            sample_rate: sample_rate as f32,
+            level_db: None,
+            gain: 1.0,
+            strength: 1.0,
+        }
+    }
+}
+
+impl DspStage for AutomaticGainControl {
+    fn process(&mut self, frame: &mut [f32]) {
+        if frame.is_empty() {
+            return;
+        }
+        let settings = self.control.load();
+        let energy = frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32;
+        let frame_db = 10.0 * energy.max(1e-12).log10();
+
+        if frame_db > AGC_GATE_DBFS {
+            let level = self.level_db.get_or_insert(frame_db);
+            let time_ms = if frame_db > *level { settings.attack_ms } else { settings.release_ms };
+            *level += (frame_db - *level) * time_coefficient(time_ms, frame.len(), self.sample_rate);
+        }
+
+        let target_gain = match self.level_db {
+            Some(level) => {
+                let correction = (settings.target_dbfs - level)
+                    .clamp(-AGC_MAX_ATTENUATION_DB, settings.max_gain_db);
+                db_to_linear(correction * self.strength)
+            }
+            None => 1.0,
+        };
+
+        // Ramp across the frame to avoid zipper noise.
+        let step = (target_gain - self.gain) / frame.len() as f32;
+        for sample in frame.iter_mut() {
+            self.gain += step;
+            *sample *= self.gain;
+        }
+        self.gain = target_gain;
+    }
+
+    fn set_strength(&mut self, strength: f32) {
+        self.strength = strength.clamp(0.0, 1.0);
+    }
+
+    fn reset(&mut self) {
+        self.level_db = None;
+        self.gain = 1.0;
+    }
+}
+
+/// Lookahead brick-wall limiter. Gain reduction starts ahead of a peak and
+/// the output is hard-clamped to the ceiling, so nothing past it ever reaches
+/// the encoder or the output device. Strength lowers the ceiling from
+/// -0.1 dBFS down to -6 dBFS.
+pub struct Limiter {
+    ceiling: f32,
+    delay: [f32; LIMITER_LOOKAHEAD],
+    delay_pos: usize,
+    hold_peak: f32,
+    hold_remaining: usize,
+    gain: f32,
+    attack: f32,
+    release: f32,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f32;
+        let mut limiter = Self {
+            ceiling: 1.0,
+            delay: [0.0; LIMITER_LOOKAHEAD],
+            delay_pos: 0,
+            hold_peak: 0.0,
+            hold_remaining: 0,
+            gain: 1.0,
+            // Reach the target gain within the lookahead window.
+            attack: 1.0 - (-5.0 / LIMITER_LOOKAHEAD as f32).exp(),
+            release: time_coefficient(LIMITER_RELEASE_MS, 1, sample_rate),
+        };
+        limiter.set_strength(0.0);
+        limiter
+    }
+
+    pub fn process_sample(&mut self, input: f32) -> f32 {
+        let magnitude = input.abs();
+        if magnitude >= self.hold_peak {
+            self.hold_peak = magnitude;
+            self.hold_remaining = LIMITER_LOOKAHEAD;
+        } else if self.hold_remaining > 0 {
+            self.hold_remaining -= 1;
+        } else {
+            self.hold_peak += (magnitude - self.hold_peak) * self.release;
+        }
+
+        let target = if self.hold_peak > self.ceiling {
+            self.ceiling / self.hold_peak
+        } else {
+            1.0
+        };
+        let coefficient = if target < self.gain { self.attack } else { self.release };
+        self.gain += (target - self.gain) * coefficient;
+
+        let delayed = std::mem::replace(&mut self.delay[self.delay_pos], input);
+        self.delay_pos = (self.delay_pos + 1) % LIMITER_LOOKAHEAD;
+        (delayed * self.gain).clamp(-self.ceiling, self.ceiling)
+    }
+}
+
+impl DspStage for Limiter {
+    fn process(&mut self, frame: &mut [f32]) {
+        for sample in frame.iter_mut() {
+            *sample = self.process_sample(*sample);
+        }
+    }
+
+    fn set_strength(&mut self, strength: f32) {
+        self.ceiling = db_to_linear(-0.1 - 5.9 * strength.clamp(0.0, 1.0));
+    }
+
+    fn reset(&mut self) {
+        self.delay = [0.0; LIMITER_LOOKAHEAD];
+        self.delay_pos = 0;
+        self.hold_peak = 0.0;
+        self.hold_remaining = 0;
+        self.gain = 1.0;
+    }
+}
@@ -0,0 +1,311 @@
+// src-tauri/src/audio/dsp/echo.rs
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use super::DspStage;
+
+// ~680 ms of far-end history at 48 kHz; must cover the largest echo delay we search plus a frame.
+const REFERENCE_CAPACITY: usize = 1 << 15;
+// Adaptive filter length (~10.7 ms) placed around the estimated bulk delay.
+const TAPS: usize = 512;
+// Start the filter window a little before the estimated delay so small misestimates still land inside it.
+const DELAY_MARGIN: usize = 192;
+// Far-end samples kept between the two callbacks when (re)aligning the read cursor.
+const CURSOR_CUSHION: usize = 960;
+
+// Delay estimation runs on 2 ms energy envelopes over a 2 s window, searching up to 400 ms.
+const BLOCK: usize = 96;
+const ENVELOPE_BLOCKS: usize = 1000;
+const MAX_LAG_BLOCKS: usize = 200;
+const ESTIMATE_EVERY_BLOCKS: usize = 500;
+const MIN_CORRELATION: f32 = 0.4;
+
+const STEP_SIZE: f32 = 0.3;
+const FAR_ACTIVE_POWER: f32 = 1e-6;
+const DOUBLE_TALK_RATIO: f32 = 0.6;
+const DOUBLE_TALK_HANGOVER: usize = 1440;
+
+/// The far-end signal as it was handed to the output device. Written by the
+/// output callback, read by the echo canceller in the input callback. Single
+/// writer, wait-free; a reader that falls behind by more than the capacity
+/// simply loses the overwritten samples and realigns.
+pub struct EchoReference {
+    samples: Box<[AtomicU32]>,
+    written: AtomicUsize,
+}
+
+impl EchoReference {
+    pub fn new() -> Self {
+        Self {
+            samples: (0..REFERENCE_CAPACITY).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+        }
+    }
+
+    /// Called from the output callback with the samples actually played.
+    pub fn push(&self, data: &[f32]) {
+        let mut pos = self.written.load(Ordering::Relaxed);
+        for sample in data {
+            self.samples[pos % REFERENCE_CAPACITY].store(sample.to_bits(), Ordering::Relaxed);
+            pos = pos.wrapping_add(1);
+        }
+        self.written.store(pos, Ordering::Release);
+    }
+
+    /// Like `push`, but downmixes interleaved multi-channel output to mono first.
+    pub fn push_interleaved(&self, data: &[f32], channels: usize) {
+        if channels <= 1 {
+            self.push(data);
+            return;
+        }
+        let mut pos = self.written.load(Ordering::Relaxed);
+        for frame in data.chunks_exact(channels) {
+            let mono = frame.iter().sum::<f32>() / channels as f32;
+            self.samples[pos % REFERENCE_CAPACITY].store(mono.to_bits(), Ordering::Relaxed);
+            pos = pos.wrapping_add(1);
+        }
+        self.written.store(pos, Ordering::Release);
+    }
+
+    fn written(&self) -> usize {
+        self.written.load(Ordering::Acquire)
+    }
+
+    fn read(&self, pos: usize) -> f32 {
+        f32::from_bits(self.samples[pos % REFERENCE_CAPACITY].load(Ordering::Relaxed))
+    }
+}
+
+impl Default for EchoReference {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Acoustic echo canceller: bulk delay estimation on energy envelopes, an NLMS
+/// filter aligned to that delay, and a residual echo suppressor whose depth is
+/// set by the stage strength.
+pub struct EchoCanceller {
+    reference: Arc<EchoReference>,
+    // Absolute far-end position that lines up with the start of the next capture frame.
+    cursor: Option<usize>,
+    delay: Option<usize>,
+    weights: Vec<f32>,
+    // Far-end window for the current frame: TAPS samples of history followed by the frame.
+    far: Vec<f32>,
+    near_envelope: Vec<f32>,
+    far_envelope: Vec<f32>,
+    envelope_pos: usize,
+    envelope_filled: usize,
+    near_block: f32,
+    far_block: f32,
+    block_fill: usize,
+    blocks_since_estimate: usize,
+    double_talk_hold: usize,
+    suppression_gain: f32,
+    strength: f32,
+}
+
+impl EchoCanceller {
+    pub fn new(reference: Arc<EchoReference>) -> Self {
+        Self {
+            reference,
+            cursor: None,
+            delay: None,
+            weights: vec![0.0; TAPS],
+            far: Vec::with_capacity(TAPS + 4096),
+            near_envelope: vec![0.0; ENVELOPE_BLOCKS],
+            far_envelope: vec![0.0; ENVELOPE_BLOCKS],
+            envelope_pos: 0,
+            envelope_filled: 0,
+            near_block: 0.0,
+            far_block: 0.0,
+            block_fill: 0,
+            blocks_since_estimate: 0,
+            double_talk_hold: 0,
+            suppression_gain: 1.0,
+            strength: 0.5,
+        }
+    }
+
+    /// Keeps the far-end cursor inside the readable window. The two cpal
+    /// streams run off different clocks, so the cursor drifts; when it leaves
+    /// the window we realign and start delay estimation over.
+    fn align_cursor(&mut self, len: usize) -> Option<usize> {
+        let written = self.reference.written();
+        let max_history = TAPS + DELAY_MARGIN + MAX_LAG_BLOCKS * BLOCK;
+        let needs_realign = match self.cursor {
+            None => true,
+            Some(cursor) => {
+                cursor.wrapping_add(len) > written
+                    || written - cursor > REFERENCE_CAPACITY - max_history - len
+            }
+        };
+        if needs_realign {
+            if written < len + CURSOR_CUSHION {
+                // Nothing has been played yet.
+                return None;
+            }
+            self.cursor = Some(written - len - CURSOR_CUSHION);
+            self.delay = None;
+            self.weights.fill(0.0);
+            self.envelope_filled = 0;
+            self.blocks_since_estimate = 0;
+        }
+        self.cursor
+    }
+
+    fn push_envelope_sample(&mut self, near: f32, far: f32) {
+        self.near_block += near * near;
+        self.far_block += far * far;
+        self.block_fill += 1;
+        if self.block_fill < BLOCK {
+            return;
+        }
+        self.near_envelope[self.envelope_pos] = (self.near_block / BLOCK as f32 + 1e-10).ln();
+        self.far_envelope[self.envelope_pos] = (self.far_block / BLOCK as f32 + 1e-10).ln();
+        self.envelope_pos = (self.envelope_pos + 1) % ENVELOPE_BLOCKS;
+        self.envelope_filled = (self.envelope_filled + 1).min(ENVELOPE_BLOCKS);
+        self.near_block = 0.0;
+        self.far_block = 0.0;
+        self.block_fill = 0;
+        self.blocks_since_estimate += 1;
+        if self.blocks_since_estimate >= ESTIMATE_EVERY_BLOCKS && self.envelope_filled == ENVELOPE_BLOCKS {
+            self.blocks_since_estimate = 0;
+            self.estimate_delay();
+        }
+    }
+
+    /// Finds the lag with the highest normalized cross-correlation between the
+    /// far-end and near-end envelopes.
+    fn estimate_delay(&mut self) {
+        let window = ENVELOPE_BLOCKS - MAX_LAG_BLOCKS;
+        // Oldest block index in the circular envelope buffers.
+        let start = self.envelope_pos;
+        let near_at = |i: usize| self.near_envelope[(start + i) % ENVELOPE_BLOCKS];
+        let far_at = |i: usize| self.far_envelope[(start + i) % ENVELOPE_BLOCKS];
+
+        let near_mean = (MAX_LAG_BLOCKS..ENVELOPE_BLOCKS).map(near_at).sum::<f32>() / window as f32;
+        let near_var: f32 = (MAX_LAG_BLOCKS..ENVELOPE_BLOCKS)
+            .map(|i| (near_at(i) - near_mean).powi(2))
+            .sum();
+
+        let mut best: Option<(usize, f32)> = None;
+        for lag in 0..MAX_LAG_BLOCKS {
+            let far_range = MAX_LAG_BLOCKS - lag..ENVELOPE_BLOCKS - lag;
+            let far_mean = far_range.clone().map(far_at).sum::<f32>() / window as f32;
+            let mut cross = 0.0;
+            let mut far_var = 0.0;
+            for (n, f) in (MAX_LAG_BLOCKS..ENVELOPE_BLOCKS).zip(far_range) {
+                let fv = far_at(f) - far_mean;
+                cross += (near_at(n) - near_mean) * fv;
+                far_var += fv * fv;
+            }
+            if far_var < 1e-3 || near_var < 1e-3 {
+                continue;
+            }
+            let correlation = cross / (near_var * far_var).sqrt();
+            if best.is_none_or(|(_, c)| correlation > c) {
+                best = Some((lag, correlation));
+            }
+        }
+
+        if let Some((lag, correlation)) = best {
+            if correlation < MIN_CORRELATION {
+                return;
+            }
+            let delay = (lag * BLOCK).saturating_sub(DELAY_MARGIN);
+            let moved = self.delay.is_none_or(|current| current.abs_diff(delay) > BLOCK);
+            if moved {
+                self.delay = Some(delay);
+                self.weights.fill(0.0);
+            }
+        }
+    }
+}
+
+impl DspStage for EchoCanceller {
+    fn process(&mut self, frame: &mut [f32]) {
+        let len = frame.len();
+        let Some(cursor) = self.align_cursor(len) else {
+            return;
+        };
+        self.cursor = Some(cursor + len);
+
+        // Envelopes are always collected at zero delay; the estimate is what shifts the filter.
+        for (i, near) in frame.iter().enumerate() {
+            let far = self.reference.read(cursor + i);
+            self.push_envelope_sample(*near, far);
+        }
+
+        let Some(delay) = self.delay else {
+            return;
+        };
+
+        // far[j] lines up with near sample (j - TAPS) after the bulk delay.
+        let base = cursor.wrapping_sub(delay).wrapping_sub(TAPS);
+        self.far.clear();
+        self.far.extend((0..TAPS + len).map(|j| self.reference.read(base.wrapping_add(j))));
+        let far_peak = self.far.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+
+        let mut error_energy = 0.0;
+        let mut echo_energy = 0.0;
+        for (i, sample) in frame.iter_mut().enumerate() {
+            let x = &self.far[i + 1..i + 1 + TAPS];
+            let mut estimate = 0.0;
+            let mut power = 0.0;
+            for (w, xv) in self.weights.iter().zip(x.iter().rev()) {
+                estimate += w * xv;
+                power += xv * xv;
+            }
+            let near = *sample;
+            let error = near - estimate;
+
+            if near.abs() > DOUBLE_TALK_RATIO * far_peak {
+                self.double_talk_hold = DOUBLE_TALK_HANGOVER;
+            } else {
+                self.double_talk_hold = self.double_talk_hold.saturating_sub(1);
+            }
+            let far_active = power / TAPS as f32 > FAR_ACTIVE_POWER;
+            if far_active && self.double_talk_hold == 0 {
+                let step = STEP_SIZE * error / (power + 1e-6);
+                for (w, xv) in self.weights.iter_mut().zip(x.iter().rev()) {
+                    *w += step * xv;
+                }
+            }
+
+            error_energy += error * error;
+            echo_energy += estimate * estimate;
+            *sample = error;
+        }
+
+        // Residual suppression: duck the frame in proportion to how much of it was echo.
+        let echo_ratio = if self.double_talk_hold > 0 {
+            0.0
+        } else {
+            echo_energy / (echo_energy + error_energy + 1e-9)
+        };
+        let target = 1.0 - self.strength * echo_ratio;
+        for sample in frame.iter_mut() {
+            self.suppression_gain += 0.01 * (target - self.suppression_gain);
+            *sample *= self.suppression_gain;
+        }
+    }
+
+    fn set_strength(&mut self, strength: f32) {
+        self.strength = strength.clamp(0.0, 1.0);
+    }
+
+    fn reset(&mut self) {
+        self.cursor = None;
+        self.delay = None;
+        self.weights.fill(0.0);
+        self.envelope_filled = 0;
+        self.near_block = 0.0;
+        self.far_block = 0.0;
+        self.block_fill = 0;
+        self.blocks_since_estimate = 0;
+        self.double_talk_hold = 0;
+        self.suppression_gain = 1.0;
+    }
+}
@@ -0,0 +1,82 @@
+// src-tauri/src/audio/dsp/filter.rs
+
+use std::f32::consts::PI;
+use super::DspStage;
+
+const MIN_CUTOFF_HZ: f32 = 40.0;
+const MAX_CUTOFF_HZ: f32 = 200.0;
+
+/// Second-order Butterworth high-pass (RBJ biquad) for removing rumble, desk
+/// thumps and DC offset. Strength moves the cutoff between 40 Hz and 200 Hz.
+pub struct HighPassFilter {
+    sample_rate: f32,
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl HighPassFilter {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut filter = Self {
+            sample_rate: sample_rate as f32,
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        };
+        filter.set_cutoff(MIN_CUTOFF_HZ);
+        filter
+    }
+
+    pub fn set_cutoff(&mut self, cutoff_hz: f32) {
+        let q = std::f32::consts::FRAC_1_SQRT_2;
+        let w0 = 2.0 * PI * cutoff_hz / self.sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha;
+        self.b0 = (1.0 + cos_w0) / 2.0 / a0;
+        self.b1 = -(1.0 + cos_w0) / a0;
+        self.b2 = (1.0 + cos_w0) / 2.0 / a0;
+        self.a1 = -2.0 * cos_w0 / a0;
+        self.a2 = (1.0 - alpha) / a0;
+    }
+}
+
+impl DspStage for HighPassFilter {
+    fn process(&mut self, frame: &mut [f32]) {
+        for sample in frame.iter_mut() {
+            let x = *sample;
+            let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+                - self.a1 * self.y1
+                - self.a2 * self.y2;
+            self.x2 = self.x1;
+            self.x1 = x;
+            self.y2 = self.y1;
+            self.y1 = y;
+            *sample = y;
+        }
+    }
+
+    fn set_strength(&mut self, strength: f32) {
+        let cutoff = MIN_CUTOFF_HZ + (MAX_CUTOFF_HZ - MIN_CUTOFF_HZ) * strength.clamp(0.0, 1.0);
+        self.set_cutoff(cutoff);
+    }
+
+    fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.x2 = 0.0;
+        self.y1 = 0.0;
+        self.y2 = 0.0;
+    }
+}
@@ -0,0 +1,434 @@
+// src-tauri/src/audio/dsp/mod.rs
+
+pub mod dynamics;
+pub mod echo;
+pub mod filter;
+pub mod noise;
+
+use serde::{Serialize, Deserialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use atomic_float::AtomicF32;
+
+pub use dynamics::{AgcControl, AgcSettings, AutomaticGainControl, Limiter};
+pub use echo::{EchoCanceller, EchoReference};
+pub use filter::HighPassFilter;
+pub use noise::NoiseSuppressor;
+
+/// A single processing stage in the capture chain. Stages work in place on
+/// mono f32 frames and must not allocate in `process`, since they run inside
+/// the cpal input callback.
+pub trait DspStage: Send {
+    fn process(&mut self, frame: &mut [f32]);
+    /// Strength is normalized to 0.0..=1.0; each stage maps it onto its own
+    /// parameter range.
+    fn set_strength(&mut self, strength: f32);
+    fn reset(&mut self);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DspStageKind {
+    HighPass,
+    EchoCancellation,
+    NoiseSuppression,
+    AutoGain,
+    Limiter,
+}
+
+impl DspStageKind {
+    /// Processing order of the capture chain.
+    pub const ALL: [DspStageKind; 5] = [
+        DspStageKind::HighPass,
+        DspStageKind::EchoCancellation,
+        DspStageKind::NoiseSuppression,
+        DspStageKind::AutoGain,
+        DspStageKind::Limiter,
+    ];
+
+    fn build(self, sample_rate: u32, controls: &DspControls) -> Box<dyn DspStage> {
+        match self {
+            DspStageKind::HighPass => Box::new(HighPassFilter::new(sample_rate)),
+            DspStageKind::EchoCancellation => {
+                Box::new(EchoCanceller::new(controls.echo_reference.clone()))
+            }
+            DspStageKind::NoiseSuppression => Box::new(NoiseSuppressor::new()),
+            DspStageKind::AutoGain => {
+                Box::new(AutomaticGainControl::new(sample_rate, controls.agc.clone()))
+            }
+            DspStageKind::Limiter => Box::new(Limiter::new(sample_rate)),
+        }
+    }
+
+    /// Stages that only make sense for speech and are bypassed in music mode.
+    pub fn is_voice_processing(self) -> bool {
+        matches!(
+            self,
+            DspStageKind::EchoCancellation | DspStageKind::NoiseSuppression | DspStageKind::AutoGain
+        )
+    }
+
+    fn default_settings(self) -> (bool, f32) {
+        match self {
+            DspStageKind::HighPass => (true, 0.3),
+            DspStageKind::EchoCancellation => (true, 0.5),
+            DspStageKind::NoiseSuppression => (true, 0.5),
+            DspStageKind::AutoGain => (true, 1.0),
+            DspStageKind::Limiter => (true, 0.2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DspStageSettings {
+    pub stage: DspStageKind,
+    pub enabled: bool,
+    pub strength: f32,
+}
+
+/// Enable/strength knobs for one stage, shared between the command handlers
+/// and the audio callback.
+pub struct StageControl {
+    enabled: AtomicBool,
+    strength: AtomicF32,
+}
+
+impl StageControl {
+    fn new(enabled: bool, strength: f32) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            strength: AtomicF32::new(strength),
+        }
+    }
+}
+
+/// Settings for every stage of the chain, plus the far-end tap the echo
+/// canceller reads from. Cheap to clone, all clones see the same values.
+#[derive(Clone)]
+pub struct DspControls {
+    stages: Arc<Vec<(DspStageKind, StageControl)>>,
+    echo_reference: Arc<EchoReference>,
+    agc: Arc<AgcControl>,
+    music_mode: Arc<AtomicBool>,
+}
+
+impl Default for DspControls {
+    fn default() -> Self {
+        let stages = DspStageKind::ALL
+            .iter()
+            .map(|kind| {
+                let (enabled, strength) = kind.default_settings();
+                (*kind, StageControl::new(enabled, strength))
+            })
+            .collect();
+        Self {
+            stages: Arc::new(stages),
+            echo_reference: Arc::new(EchoReference::new()),
+            agc: Arc::new(AgcControl::new(AgcSettings::default())),
+            music_mode: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl DspControls {
+    /// Where the output callback publishes the mixed playback signal.
+    pub fn echo_reference(&self) -> Arc<EchoReference> {
+        self.echo_reference.clone()
+    }
+
+    pub fn set_agc(&self, settings: AgcSettings) {
+        self.agc.store(settings);
+    }
+
+    pub fn agc(&self) -> AgcSettings {
+        self.agc.load()
+    }
+
+    pub fn set_music_mode(&self, enabled: bool) {
+        self.music_mode.store(enabled, Ordering::Relaxed);
+    }
+
+    fn control(&self, kind: DspStageKind) -> &StageControl {
+        self.stages
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, control)| control)
+            .expect("every stage kind has a control")
+    }
+
+    pub fn set_stage(&self, kind: DspStageKind, enabled: bool, strength: f32) {
+        let control = self.control(kind);
+        control.enabled.store(enabled, Ordering::Relaxed);
+        control.strength.store(strength.clamp(0.0, 1.0), Ordering::Relaxed);
+    }
+
+    pub fn settings(&self) -> Vec<DspStageSettings> {
+        self.stages
+            .iter()
+            .map(|(kind, control)| DspStageSettings {
+                stage: *kind,
+                enabled: control.enabled.load(Ordering::Relaxed),
+                strength: control.strength.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+struct ChainSlot {
+    kind: DspStageKind,
+    stage: Box<dyn DspStage>,
+    was_enabled: bool,
+    strength: f32,
+}
+
+/// The capture-side processing chain. Owned by the input callback; settings
+/// are picked up from the shared `DspControls` at the start of every frame.
+pub struct DspChain {
+    slots: Vec<ChainSlot>,
+    controls: DspControls,
+}
+
+impl DspChain {
+    pub fn new(sample_rate: u32, controls: DspControls) -> Self {
+        let slots = DspStageKind::ALL
+            .iter()
+            .map(|kind| {
+                let mut stage = kind.build(sample_rate, &controls);
+                let strength = controls.control(*kind).strength.load(Ordering::Relaxed);
+                stage.set_strength(strength);
+                ChainSlot {
+                    kind: *kind,
+                    stage,
+                    was_enabled: false,
+                    strength,
+                }
+            })
+            .collect();
+        Self { slots, controls }
+    }
+
+    pub fn process(&mut self, frame: &mut [f32]) {
+        self.shape(frame);
+        self.limit(frame);
+    }
+
+    /// Every stage before the limiter, so anything mixed into the frame
+    /// afterwards still goes through `limit`.
+    pub fn shape(&mut self, frame: &mut [f32]) {
+        self.run(frame, |kind| kind != DspStageKind::Limiter);
+    }
+
+    /// Only the limiter stage.
+    pub fn limit(&mut self, frame: &mut [f32]) {
+        self.run(frame, |kind| kind == DspStageKind::Limiter);
+    }
+
+    fn run(&mut self, frame: &mut [f32], include: impl Fn(DspStageKind) -> bool) {
+        let music_mode = self.controls.music_mode.load(Ordering::Relaxed);
+        for slot in self.slots.iter_mut().filter(|slot| include(slot.kind)) {
+            let control = self.controls.control(slot.kind);
+            let bypassed = music_mode && slot.kind.is_voice_processing();
+            if bypassed || !control.enabled.load(Ordering::Relaxed) {
+                slot.was_enabled = false;
+                continue;
+            }
+            if !slot.was_enabled {
+                // Don't carry filter state across a disabled period.
+                slot.stage.reset();
+                slot.was_enabled = true;
+            }
+            let strength = control.strength.load(Ordering::Relaxed);
+            if strength != slot.strength {
+                slot.stage.set_strength(strength);
+                slot.strength = strength;
+            }
+            slot.stage.process(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const FRAME: usize = 480;
+
+    fn sine(frequency: f32, amplitude: f32, start: usize, len: usize) -> Vec<f32> {
+        (start..start + len)
+            .map(|n| amplitude * (2.0 * PI * frequency * n as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    fn level_dbfs(frame: &[f32]) -> f32 {
+        let energy = frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32;
+        10.0 * energy.max(1e-12).log10()
+    }
+
+    /// Deterministic white noise in -1.0..1.0.
+    struct Noise(u32);
+
+    impl Noise {
+        fn next(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as f32 / u32::MAX as f32 * 2.0 - 1.0
+        }
+    }
+
+    /// Level change, in dB, of a sine at `frequency` once the stage has settled.
+    fn response_db(stage: &mut dyn DspStage, frequency: f32) -> f32 {
+        let mut last = Vec::new();
+        for frame in 0..100 {
+            last = sine(frequency, 0.5, frame * FRAME, FRAME);
+            stage.process(&mut last);
+        }
+        level_dbfs(&last) - level_dbfs(&sine(frequency, 0.5, 0, FRAME))
+    }
+
+    /// A chain with every stage off except `kind`.
+    fn only(kind: DspStageKind, strength: f32) -> (DspChain, DspControls) {
+        let controls = DspControls::default();
+        for other in DspStageKind::ALL {
+            controls.set_stage(other, other == kind, strength);
+        }
+        (DspChain::new(SAMPLE_RATE, controls.clone()), controls)
+    }
+
+    #[test]
+    fn high_pass_removes_rumble_and_keeps_speech() {
+        let mut filter = HighPassFilter::new(SAMPLE_RATE);
+        filter.set_strength(0.3);
+        assert!(response_db(&mut filter, 20.0) < -20.0);
+        assert!(response_db(&mut filter, 1000.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn high_pass_strength_raises_the_cutoff() {
+        let mut gentle = HighPassFilter::new(SAMPLE_RATE);
+        gentle.set_strength(0.0);
+        let mut strong = HighPassFilter::new(SAMPLE_RATE);
+        strong.set_strength(1.0);
+        assert!(response_db(&mut gentle, 100.0) > -1.0);
+        assert!(response_db(&mut strong, 100.0) < -6.0);
+    }
+
+    #[test]
+    fn limiter_never_exceeds_its_ceiling() {
+        for (strength, ceiling_db) in [(0.0, -0.1), (1.0, -6.0)] {
+            let ceiling = 10f32.powf(ceiling_db / 20.0);
+            let mut limiter = Limiter::new(SAMPLE_RATE);
+            limiter.set_strength(strength);
+            let mut noise = Noise(1);
+            for frame in 0..50 {
+                // Bursts well past full scale, with a sudden peak in each frame.
+                let mut samples: Vec<f32> = (0..FRAME).map(|_| noise.next() * (frame % 4) as f32).collect();
+                samples[FRAME / 2] = 8.0;
+                limiter.process(&mut samples);
+                assert!(
+                    samples.iter().all(|s| s.abs() <= ceiling),
+                    "strength {} frame {}",
+                    strength,
+                    frame
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn agc_brings_a_quiet_talker_to_the_target() {
+        let settings = AgcSettings::default();
+        let mut agc = AutomaticGainControl::new(SAMPLE_RATE, Arc::new(AgcControl::new(settings)));
+        let mut last = Vec::new();
+        for frame in 0..200 {
+            last = sine(440.0, 0.02, frame * FRAME, FRAME);
+            agc.process(&mut last);
+        }
+        assert!(level_dbfs(&sine(440.0, 0.02, 0, FRAME)) < settings.target_dbfs - 10.0);
+        assert!((level_dbfs(&last) - settings.target_dbfs).abs() < 1.0);
+    }
+
+    #[test]
+    fn agc_strength_scales_the_correction() {
+        let mut agc = AutomaticGainControl::new(SAMPLE_RATE, Arc::new(AgcControl::new(AgcSettings::default())));
+        agc.set_strength(0.0);
+        assert!(response_db(&mut agc, 440.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn echo_canceller_removes_a_delayed_copy_of_the_far_end() {
+        // 100 ms of room delay: 50 ms of it is the gap the canceller keeps
+        // between the two callbacks, the rest is what it has to find.
+        const ECHO_DELAY: usize = 960 + 4800;
+        let reference = Arc::new(EchoReference::new());
+        let mut canceller = EchoCanceller::new(reference.clone());
+        canceller.set_strength(0.0);
+
+        let mut noise = Noise(7);
+        let mut far = Vec::new();
+        let mut before = 0.0;
+        let mut after = 0.0;
+        for frame in 0..400 {
+            // Noise bursts give the envelopes something to line up on.
+            let loudness = if (frame / 3) % 2 == 0 { 0.3 } else { 0.05 };
+            let played: Vec<f32> = (0..FRAME).map(|_| noise.next() * loudness).collect();
+            reference.push(&played);
+            far.extend_from_slice(&played);
+
+            let start = frame * FRAME;
+            let mut near: Vec<f32> = (start..start + FRAME)
+                .map(|n| n.checked_sub(ECHO_DELAY).map_or(0.0, |at| 0.5 * far[at]))
+                .collect();
+            let echo = level_dbfs(&near);
+            canceller.process(&mut near);
+            if frame >= 350 {
+                before += echo;
+                after += level_dbfs(&near);
+            }
+        }
+        assert!((after - before) / 50.0 < -20.0, "{} dB", (after - before) / 50.0);
+    }
+
+    #[test]
+    fn disabled_stages_leave_the_signal_alone() {
+        let controls = DspControls::default();
+        for kind in DspStageKind::ALL {
+            controls.set_stage(kind, false, 1.0);
+        }
+        let mut chain = DspChain::new(SAMPLE_RATE, controls);
+        let input = sine(30.0, 2.0, 0, FRAME);
+        let mut frame = input.clone();
+        chain.process(&mut frame);
+        assert_eq!(frame, input);
+    }
+
+    #[test]
+    fn chain_picks_up_enable_and_strength_changes() {
+        let (mut chain, controls) = only(DspStageKind::Limiter, 0.0);
+        let mut frame = vec![2.0; FRAME];
+        chain.process(&mut frame);
+        assert!(frame.iter().all(|s| *s <= 1.0));
+
+        controls.set_stage(DspStageKind::Limiter, true, 1.0);
+        let mut frame = vec![2.0; FRAME];
+        chain.process(&mut frame);
+        assert!(frame.iter().all(|s| *s <= 0.51));
+
+        controls.set_stage(DspStageKind::Limiter, false, 1.0);
+        let mut frame = vec![2.0; FRAME];
+        chain.process(&mut frame);
+        assert!(frame.iter().all(|s| *s == 2.0));
+    }
+
+    #[test]
+    fn shape_and_limit_split_the_chain_at_the_limiter() {
+        let (mut chain, _) = only(DspStageKind::Limiter, 1.0);
+        let mut frame = vec![2.0; FRAME];
+        chain.shape(&mut frame);
+        assert!(frame.iter().all(|s| *s == 2.0));
+        chain.limit(&mut frame);
+        assert!(frame.iter().all(|s| *s <= 0.51));
+    }
+}
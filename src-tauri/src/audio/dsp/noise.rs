@@ -0,0 +1,157 @@
+// src-tauri/src/audio/dsp/noise.rs
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use super::DspStage;
+
+// 10 ms analysis window with 50% overlap at 48 kHz, so the stage adds 5 ms of latency.
+const FRAME_LEN: usize = 480;
+const HOP: usize = FRAME_LEN / 2;
+
+// Per-block growth of the noise floor estimate (~3.5 dB/s at a 5 ms hop), so it
+// follows slowly rising noise but doesn't chase speech.
+const NOISE_RISE: f32 = 1.002;
+const POWER_SMOOTHING: f32 = 0.8;
+const GAIN_SMOOTHING: f32 = 0.5;
+// Blocks (100 ms) during which the noise floor simply follows the input.
+const WARMUP_BLOCKS: usize = 20;
+
+/// Spectral subtraction noise suppressor. Tracks a per-bin noise floor with a
+/// minimum-follower and attenuates bins that sit close to it. Strength raises
+/// both the over-subtraction factor and the maximum attenuation.
+pub struct NoiseSuppressor {
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    input: Vec<f32>,
+    overlap: Vec<f32>,
+    ready: Vec<f32>,
+    fill: usize,
+    spectrum: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    smoothed_power: Vec<f32>,
+    noise_power: Vec<f32>,
+    gains: Vec<f32>,
+    warmup_blocks: usize,
+    over_subtraction: f32,
+    gain_floor: f32,
+}
+
+impl NoiseSuppressor {
+    pub fn new() -> Self {
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(FRAME_LEN);
+        let ifft = planner.plan_fft_inverse(FRAME_LEN);
+        let scratch_len = fft
+            .get_inplace_scratch_len()
+            .max(ifft.get_inplace_scratch_len());
+        // Periodic sqrt-Hann on both analysis and synthesis, which sums to unity at 50% overlap.
+        let window = (0..FRAME_LEN)
+            .map(|i| (0.5 - 0.5 * (2.0 * PI * i as f32 / FRAME_LEN as f32).cos()).sqrt())
+            .collect();
+        let bins = FRAME_LEN / 2 + 1;
+        let mut suppressor = Self {
+            fft,
+            ifft,
+            window,
+            input: vec![0.0; FRAME_LEN],
+            overlap: vec![0.0; FRAME_LEN],
+            ready: vec![0.0; HOP],
+            fill: 0,
+            spectrum: vec![Complex::new(0.0, 0.0); FRAME_LEN],
+            scratch: vec![Complex::new(0.0, 0.0); scratch_len],
+            smoothed_power: vec![0.0; bins],
+            noise_power: vec![0.0; bins],
+            gains: vec![1.0; bins],
+            warmup_blocks: WARMUP_BLOCKS,
+            over_subtraction: 1.0,
+            gain_floor: 1.0,
+        };
+        suppressor.set_strength(0.5);
+        suppressor
+    }
+
+    fn process_block(&mut self) {
+        for (bin, (sample, w)) in self.spectrum.iter_mut().zip(self.input.iter().zip(&self.window)) {
+            *bin = Complex::new(sample * w, 0.0);
+        }
+        self.fft.process_with_scratch(&mut self.spectrum, &mut self.scratch);
+
+        let bins = FRAME_LEN / 2 + 1;
+        for k in 0..bins {
+            let power = self.spectrum[k].norm_sqr();
+            self.smoothed_power[k] =
+                POWER_SMOOTHING * self.smoothed_power[k] + (1.0 - POWER_SMOOTHING) * power;
+            if self.warmup_blocks > 0 {
+                self.noise_power[k] = self.smoothed_power[k];
+            } else {
+                let risen = self.noise_power[k] * NOISE_RISE + f32::MIN_POSITIVE;
+                self.noise_power[k] = self.smoothed_power[k].min(risen);
+            }
+
+            let ratio = if power > f32::EPSILON {
+                self.over_subtraction * self.noise_power[k] / power
+            } else {
+                1.0
+            };
+            let gain = (1.0 - ratio).max(self.gain_floor * self.gain_floor).sqrt();
+            self.gains[k] = GAIN_SMOOTHING * self.gains[k] + (1.0 - GAIN_SMOOTHING) * gain;
+
+            self.spectrum[k] *= self.gains[k];
+            if k != 0 && k != FRAME_LEN / 2 {
+                self.spectrum[FRAME_LEN - k] *= self.gains[k];
+            }
+        }
+        self.warmup_blocks = self.warmup_blocks.saturating_sub(1);
+
+        self.ifft.process_with_scratch(&mut self.spectrum, &mut self.scratch);
+        let scale = 1.0 / FRAME_LEN as f32;
+        for (i, bin) in self.spectrum.iter().enumerate() {
+            self.overlap[i] += bin.re * self.window[i] * scale;
+        }
+
+        self.ready.copy_from_slice(&self.overlap[..HOP]);
+        self.overlap.copy_within(HOP.., 0);
+        self.overlap[FRAME_LEN - HOP..].fill(0.0);
+        self.input.copy_within(HOP.., 0);
+    }
+}
+
+impl Default for NoiseSuppressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DspStage for NoiseSuppressor {
+    fn process(&mut self, frame: &mut [f32]) {
+        for sample in frame.iter_mut() {
+            self.input[FRAME_LEN - HOP + self.fill] = *sample;
+            *sample = self.ready[self.fill];
+            self.fill += 1;
+            if self.fill == HOP {
+                self.fill = 0;
+                self.process_block();
+            }
+        }
+    }
+
+    fn set_strength(&mut self, strength: f32) {
+        let strength = strength.clamp(0.0, 1.0);
+        self.over_subtraction = 1.0 + 3.0 * strength;
+        // Maximum attenuation from -6 dB up to -30 dB.
+        self.gain_floor = 10f32.powf(-(6.0 + 24.0 * strength) / 20.0);
+    }
+
+    fn reset(&mut self) {
+        self.input.fill(0.0);
+        self.overlap.fill(0.0);
+        self.ready.fill(0.0);
+        self.fill = 0;
+        self.smoothed_power.fill(0.0);
+        self.noise_power.fill(0.0);
+        self.gains.fill(1.0);
+        self.warmup_blocks = WARMUP_BLOCKS;
+    }
+}
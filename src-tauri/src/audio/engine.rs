@@ -0,0 +1,256 @@
+// src-tauri/src/audio/engine.rs
+//
+// cpal streams are not `Send`, so they never leave the thread that built them.
+// The engine thread creates, replaces and drops every stream in response to
+// commands sent from `AudioProcessor` handles. Without a sound card the same
+// callbacks can be driven by virtual devices instead.
+
+use atomic_float::AtomicF32;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use ringbuf::HeapRb;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use tokio::sync::{mpsc as tokio_mpsc, oneshot};
+use super::capture::spawn_capture_worker;
+use super::codec::{EncoderSettings, SAMPLE_RATE};
+use super::dsp::DspControls;
+use super::loopback::Loopback;
+use super::recorder::Recorder;
+use super::soundboard::Soundboard;
+use super::mixer::{MixerCommand, MixerHandle};
+use super::realtime::{CallbackTimer, CaptureCallback, PlaybackCallback, Worker};
+use super::virtual_device::{spawn_virtual_input, spawn_virtual_output, TestSignal, VirtualDevices};
+
+// Capture and playback rings hold 100 ms per channel.
+const RING_SAMPLES: usize = 4800;
+// How often an idle engine checks whether it has been asked to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub type Reply = oneshot::Sender<Result<(), String>>;
+
+pub enum EngineCommand {
+    StartOutput(Reply),
+    StartCapture(Reply),
+    /// Rebuilds whichever streams the new profile affects.
+    SetEncoderSettings(EncoderSettings, Reply),
+    /// Picks the capture device by name, `None` for the system default.
+    SetInputDevice(Option<String>, Reply),
+    /// Runs on virtual devices instead of the sound card from the next
+    /// stream start on.
+    UseVirtualDevices(VirtualDevices, Reply),
+    Stop(Reply),
+}
+
+/// State shared between the engine thread and every processor handle.
+#[derive(Clone)]
+pub struct EngineShared {
+    pub tx: tokio_mpsc::Sender<Vec<u8>>,
+    pub mixer: MixerHandle,
+    pub is_muted: Arc<AtomicBool>,
+    /// Gain applied to the microphone before the DSP chain.
+    pub input_volume: Arc<AtomicF32>,
+    pub dsp: DspControls,
+    pub loopback: Loopback,
+    pub recorder: Recorder,
+    pub soundboard: Soundboard,
+    pub input_timer: Arc<CallbackTimer>,
+    pub output_timer: Arc<CallbackTimer>,
+}
+
+/// A running stream, only held so dropping it stops it.
+enum Stream {
+    Device { _stream: cpal::Stream },
+    Virtual { _worker: Worker },
+}
+
+struct Engine {
+    shared: EngineShared,
+    encoder_settings: EncoderSettings,
+    input_device: Option<String>,
+    virtual_devices: Option<VirtualDevices>,
+    input_stream: Option<Stream>,
+    output_stream: Option<Stream>,
+    capture_worker: Option<Worker>,
+}
+
+impl Engine {
+    fn handle(&mut self, command: EngineCommand) {
+        match command {
+            EngineCommand::StartOutput(reply) => {
+                let _ = reply.send(self.start_output());
+            }
+            EngineCommand::StartCapture(reply) => {
+                let _ = reply.send(self.start_capture());
+            }
+            EngineCommand::SetEncoderSettings(settings, reply) => {
+                let _ = reply.send(self.set_encoder_settings(settings));
+            }
+            EngineCommand::SetInputDevice(device, reply) => {
+                self.input_device = device;
+                let result = if self.input_stream.is_some() { self.start_capture() } else { Ok(()) };
+                let _ = reply.send(result);
+            }
+            EngineCommand::UseVirtualDevices(devices, reply) => {
+                self.virtual_devices = Some(devices);
+                let _ = reply.send(Ok(()));
+            }
+            EngineCommand::Stop(reply) => {
+                self.stop_capture();
+                self.output_stream = None;
+                let _ = reply.send(Ok(()));
+            }
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.encoder_settings.channels()
+    }
+
+    fn start_output(&mut self) -> Result<(), String> {
+        self.output_stream = None;
+        let device = match self.virtual_devices {
+            Some(_) => None,
+            None => Some(cpal::default_host().default_output_device().ok_or("No output device available")?),
+        };
+        let config = cpal::StreamConfig {
+            channels: self.channels(),
+            sample_rate: cpal::SampleRate(SAMPLE_RATE),
+            buffer_size: cpal::BufferSize::Fixed(480),
+        };
+
+        let channels = self.channels() as usize;
+        let (producer, consumer) = HeapRb::<f32>::new(RING_SAMPLES * channels).split();
+        self.shared.mixer.send(MixerCommand::SetOutput { producer, channels });
+        let mut callback = PlaybackCallback::new(
+            consumer,
+            channels,
+            SAMPLE_RATE,
+            self.shared.is_muted.clone(),
+            self.shared.dsp.echo_reference(),
+            self.shared.output_timer.clone(),
+        );
+
+        let Some(device) = device else {
+            let record = self.virtual_devices.as_ref().and_then(|devices| devices.record.clone());
+            let worker = spawn_virtual_output(record, channels, callback).map_err(|e| e.to_string())?;
+            self.output_stream = Some(Stream::Virtual { _worker: worker });
+            return Ok(());
+        };
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| callback.process(data),
+                |err| eprintln!("Output error: {}", err),
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+        self.output_stream = Some(Stream::Device { _stream: stream });
+        Ok(())
+    }
+
+    fn start_capture(&mut self) -> Result<(), String> {
+        self.stop_capture();
+        let device = match self.virtual_devices {
+            Some(_) => None,
+            None => Some(self.find_input_device()?),
+        };
+        let config = cpal::StreamConfig {
+            channels: self.channels(),
+            sample_rate: cpal::SampleRate(SAMPLE_RATE),
+            buffer_size: cpal::BufferSize::Fixed(480),
+        };
+        let channels = self.channels() as usize;
+        let (producer, consumer) = HeapRb::<f32>::new(RING_SAMPLES * channels).split();
+        let mut callback = CaptureCallback::new(producer, channels, SAMPLE_RATE, self.shared.input_timer.clone());
+        let worker = spawn_capture_worker(consumer, self.encoder_settings.clone(), self.shared.clone())
+            .map_err(|e| e.to_string())?;
+
+        let Some(device) = device else {
+            let signal = self.virtual_devices.as_ref().map_or(TestSignal::Silence, |devices| devices.input.clone());
+            let input = spawn_virtual_input(signal, channels, callback).map_err(|e| e.to_string())?;
+            self.input_stream = Some(Stream::Virtual { _worker: input });
+            self.capture_worker = Some(worker);
+            return Ok(());
+        };
+        let stream = device
+            .build_input_stream(
+                &config,
+                move |data: &[f32], _: &_| callback.process(data),
+                |err| eprintln!("Audio capture error: {}", err),
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+        self.input_stream = Some(Stream::Device { _stream: stream });
+        self.capture_worker = Some(worker);
+        Ok(())
+    }
+
+    fn find_input_device(&self) -> Result<cpal::Device, String> {
+        let host = cpal::default_host();
+        match self.input_device.as_deref() {
+            Some(name) => host
+                .input_devices()
+                .map_err(|e| e.to_string())?
+                .find(|device| device.name().is_ok_and(|n| n == name))
+                .ok_or_else(|| format!("Input device '{}' not found", name)),
+            None => host
+                .default_input_device()
+                .ok_or_else(|| "No input device available".to_string()),
+        }
+    }
+
+    /// Drops the input stream before its worker so the callback never writes
+    /// into a ring nobody drains.
+    fn stop_capture(&mut self) {
+        self.input_stream = None;
+        self.capture_worker = None;
+    }
+
+    fn set_encoder_settings(&mut self, settings: EncoderSettings) -> Result<(), String> {
+        settings.validate()?;
+        if settings == self.encoder_settings {
+            return Ok(());
+        }
+        let channels_changed = settings.channels() != self.channels();
+        self.encoder_settings = settings;
+
+        if self.output_stream.is_some() && channels_changed {
+            self.start_output()?;
+        }
+        if self.input_stream.is_some() {
+            self.start_capture()?;
+        }
+        Ok(())
+    }
+}
+
+/// Starts the engine thread. It exits when told to stop or when every
+/// command sender is gone, dropping any streams it still owns.
+pub fn spawn_engine(
+    shared: EngineShared,
+    commands: mpsc::Receiver<EngineCommand>,
+) -> std::io::Result<Worker> {
+    Worker::spawn("llas-engine", move |stop: Arc<AtomicBool>| {
+        let mut engine = Engine {
+            shared,
+            encoder_settings: EncoderSettings::default(),
+            input_device: None,
+            virtual_devices: None,
+            input_stream: None,
+            output_stream: None,
+            capture_worker: None,
+        };
+        while !stop.load(Ordering::Relaxed) {
+            match commands.recv_timeout(POLL_INTERVAL) {
+                Ok(command) => engine.handle(command),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        engine.stop_capture();
+    })
+}
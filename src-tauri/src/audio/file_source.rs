@@ -0,0 +1,114 @@
+// src-tauri/src/audio/file_source.rs
+
+use rubato::{FftFixedIn, Resampler};
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use super::codec::SAMPLE_RATE;
+
+// Input frames per resampler pass.
+const RESAMPLE_CHUNK: usize = 1024;
+
+/// A decoded sound, stereo interleaved at 48 kHz.
+pub type Clip = Arc<[f32]>;
+
+/// Decodes a WAV, Ogg (Vorbis) or FLAC file into a clip. Mono files are
+/// duplicated to both channels and anything wider than stereo keeps its
+/// first two.
+pub fn load_clip(path: &Path) -> Result<Clip, Box<dyn std::error::Error + Send + Sync>> {
+    let file = File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+    let track = format.default_track().ok_or("File has no audio track")?;
+    let track_id = track.id;
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or(SAMPLE_RATE);
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut left = Vec::new();
+    let mut right = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet costs a few milliseconds, not the whole clip.
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        sample_rate = spec.rate;
+        let channels = spec.channels.count();
+        let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        samples.copy_interleaved_ref(decoded);
+        for frame in samples.samples().chunks_exact(channels) {
+            left.push(frame[0]);
+            right.push(frame[channels.min(2) - 1]);
+        }
+    }
+    if left.is_empty() {
+        return Err("File contains no audio".into());
+    }
+
+    if sample_rate != SAMPLE_RATE {
+        (left, right) = resample(left, right, sample_rate)?;
+    }
+    Ok(left.iter().zip(&right).flat_map(|(&l, &r)| [l, r]).collect())
+}
+
+fn resample(
+    left: Vec<f32>,
+    right: Vec<f32>,
+    from_rate: u32,
+) -> Result<(Vec<f32>, Vec<f32>), Box<dyn std::error::Error + Send + Sync>> {
+    let mut resampler = FftFixedIn::<f32>::new(from_rate as usize, SAMPLE_RATE as usize, RESAMPLE_CHUNK, 2, 2)?;
+    let expected = (left.len() as u64 * SAMPLE_RATE as u64 / from_rate as u64) as usize;
+    let mut out = [Vec::with_capacity(expected), Vec::with_capacity(expected)];
+    fn append(out: &mut [Vec<f32>; 2], chunk: Vec<Vec<f32>>) {
+        for (channel, samples) in out.iter_mut().zip(chunk) {
+            channel.extend(samples);
+        }
+    }
+
+    let mut position = 0;
+    while left.len() - position >= RESAMPLE_CHUNK {
+        let end = position + RESAMPLE_CHUNK;
+        append(&mut out, resampler.process(&[&left[position..end], &right[position..end]], None)?);
+        position = end;
+    }
+    append(&mut out, resampler.process_partial(Some(&[&left[position..], &right[position..]]), None)?);
+    // Flush what the filter is still holding back.
+    let delay = resampler.output_delay();
+    while out[0].len() < expected + delay {
+        append(&mut out, resampler.process_partial(None::<&[&[f32]]>, None)?);
+    }
+
+    let [mut left, mut right] = out;
+    for channel in [&mut left, &mut right] {
+        channel.drain(..delay);
+        channel.truncate(expected);
+    }
+    Ok((left, right))
+}
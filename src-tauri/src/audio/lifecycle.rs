@@ -0,0 +1,126 @@
+// src-tauri/src/audio/lifecycle.rs
+
+use serde::{Serialize, Deserialize};
+use parking_lot::Mutex;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EngineState {
+    Idle,
+    Starting,
+    Running,
+    Stopping,
+}
+
+/// What a caller of `begin_start` should do next.
+pub enum StartRequest {
+    /// The engine was idle; start it and cancel this token on teardown.
+    Start(CancellationToken),
+    /// Already starting or running; nothing to do.
+    AlreadyRunning,
+}
+
+/// Tracks the streaming engine through Idle → Starting → Running → Stopping
+/// and owns the cancellation token every task of the current session is
+/// spawned with.
+pub struct EngineLifecycle {
+    state: watch::Sender<EngineState>,
+    cancel: Mutex<CancellationToken>,
+}
+
+impl EngineLifecycle {
+    pub fn new() -> Self {
+        let (state, _) = watch::channel(EngineState::Idle);
+        Self {
+            state,
+            cancel: Mutex::new(CancellationToken::new()),
+        }
+    }
+
+    pub fn state(&self) -> EngineState {
+        *self.state.borrow()
+    }
+
+    /// Receives every state change.
+    pub fn subscribe(&self) -> watch::Receiver<EngineState> {
+        self.state.subscribe()
+    }
+
+    /// Moves Idle → Starting and hands out a fresh token for the session.
+    pub fn begin_start(&self) -> Result<StartRequest, String> {
+        let mut current = EngineState::Idle;
+        let started = self.state.send_if_modified(|state| {
+            current = *state;
+            if *state == EngineState::Idle {
+                *state = EngineState::Starting;
+                true
+            } else {
+                false
+            }
+        });
+        if started {
+            let token = CancellationToken::new();
+            *self.cancel.lock() = token.clone();
+            return Ok(StartRequest::Start(token));
+        }
+        match current {
+            EngineState::Starting | EngineState::Running => Ok(StartRequest::AlreadyRunning),
+            _ => Err("Audio engine is stopping".to_string()),
+        }
+    }
+
+    /// Starting → Running. Fails if a stop arrived while starting.
+    pub fn finish_start(&self) -> Result<(), String> {
+        let running = self.state.send_if_modified(|state| {
+            if *state == EngineState::Starting {
+                *state = EngineState::Running;
+                true
+            } else {
+                false
+            }
+        });
+        if running {
+            Ok(())
+        } else {
+            Err("Audio engine was stopped while starting".to_string())
+        }
+    }
+
+    /// Moves Starting/Running → Stopping and cancels the session's tasks.
+    /// Returns false if there is nothing to stop.
+    pub fn begin_stop(&self) -> bool {
+        let stopping = self.state.send_if_modified(|state| {
+            if matches!(*state, EngineState::Starting | EngineState::Running) {
+                *state = EngineState::Stopping;
+                true
+            } else {
+                false
+            }
+        });
+        if stopping {
+            self.cancel.lock().cancel();
+        }
+        stopping
+    }
+
+    /// Stopping (or a failed start) → Idle.
+    pub fn finish_stop(&self) {
+        self.cancel.lock().cancel();
+        self.state.send_if_modified(|state| {
+            if *state == EngineState::Idle {
+                false
+            } else {
+                *state = EngineState::Idle;
+                true
+            }
+        });
+    }
+}
+
+impl Default for EngineLifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
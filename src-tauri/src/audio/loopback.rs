@@ -0,0 +1,100 @@
+// src-tauri/src/audio/loopback.rs
+
+use serde::{Serialize, Deserialize};
+use parking_lot::Mutex;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::time::{Duration, Instant};
+use super::mixer::{MixerCommand, MixerHandle};
+use super::packet::{build_audio_packet, PacketHeader};
+use super::realtime::Worker;
+
+// The mixer treats our own stream like any other peer, keyed by this address.
+const LOOPBACK_ADDR: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+const PACKET_QUEUE: usize = 64;
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Impairments applied to looped-back packets so users can hear how their
+/// setup holds up on a bad connection.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LoopbackSettings {
+    /// Fraction of packets dropped, 0.0 to 1.0.
+    pub packet_loss: f32,
+    /// Fixed one-way delay in milliseconds.
+    pub latency_ms: u32,
+    /// Extra random delay of up to this many milliseconds per packet.
+    pub jitter_ms: u32,
+}
+
+struct Session {
+    packets: SyncSender<Vec<u8>>,
+    _worker: Worker,
+}
+
+/// Routes our own encoded packets back to the mixer through a simulated
+/// network. Packets go through the same header build/parse as a real
+/// datagram, so what plays back is what peers receive.
+#[derive(Clone, Default)]
+pub struct Loopback {
+    session: Arc<Mutex<Option<Session>>>,
+}
+
+impl Loopback {
+    pub fn start(&self, settings: LoopbackSettings, mixer: MixerHandle) -> std::io::Result<()> {
+        let packet_loss = settings.packet_loss.clamp(0.0, 1.0);
+        let (packets, rx) = mpsc::sync_channel::<Vec<u8>>(PACKET_QUEUE);
+        let worker = Worker::spawn("llas-loopback", move |stop: Arc<AtomicBool>| {
+            let mut in_flight = BinaryHeap::new();
+            let mut sequence: u32 = 0;
+            while !stop.load(Ordering::Relaxed) {
+                match rx.recv_timeout(POLL_INTERVAL) {
+                    Ok(payload) => {
+                        let datagram = build_audio_packet(sequence, 0, &payload).to_vec();
+                        sequence = sequence.wrapping_add(1);
+                        if rand::random::<f32>() >= packet_loss {
+                            let jitter = rand::random_range(0..=settings.jitter_ms);
+                            let delay = Duration::from_millis((settings.latency_ms + jitter) as u64);
+                            in_flight.push(Reverse((Instant::now() + delay, sequence, datagram)));
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+
+                let now = Instant::now();
+                while in_flight.peek().is_some_and(|Reverse((at, _, _))| *at <= now) {
+                    let Some(Reverse((_, _, datagram))) = in_flight.pop() else {
+                        break;
+                    };
+                    if let Some((header, payload)) = PacketHeader::parse(&datagram) {
+                        mixer.send(MixerCommand::Packet {
+                            from: LOOPBACK_ADDR,
+                            payload: payload.to_vec(),
+                            stereo: header.is_stereo(),
+                            whisper: false,
+                        });
+                    }
+                }
+            }
+            mixer.send(MixerCommand::RemovePeer(LOOPBACK_ADDR));
+        })?;
+        // Replacing a running session stops it first.
+        *self.session.lock() = Some(Session { packets, _worker: worker });
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.session.lock().take();
+    }
+
+    /// Called by the capture worker with every encoded packet.
+    pub fn tap(&self, packet: &[u8]) {
+        if let Some(session) = self.session.lock().as_ref() {
+            let _ = session.packets.try_send(packet.to_vec());
+        }
+    }
+}
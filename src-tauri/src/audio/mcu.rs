@@ -0,0 +1,195 @@
+// src-tauri/src/audio/mcu.rs
+//
+// Server-side mixing for clients that can't take a stream per participant.
+// The forwarding server decodes everyone in the room with the same decoder
+// and queue the local mixer uses, and every mix tick builds one frame per
+// listener from everyone but that listener, encodes it with Opus and seals
+// it under the room key. Unlike plain forwarding this needs the room key,
+// so only clients that opt in hand it over (wrapped for the server's
+// identity), and a room is only mixed once one of them has.
+
+use bytes::BytesMut;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use super::codec::{BitrateMode, Encoder, EncoderApplication, EncoderBandwidth, EncoderSettings, StreamDecoder, MAX_FRAME_SAMPLES, MAX_PACKET_SIZE, SAMPLE_RATE};
+use super::crypto::{OpenError, PacketOpener, PacketSealer, RoomKey};
+use super::dsp::Limiter;
+use super::mixer::StreamQueue;
+use super::packet::build_audio_packet;
+
+/// Profile of the mixed streams: mono voice in 20 ms frames at a bitrate a
+/// mobile hotspot can carry.
+pub const MIX_SETTINGS: EncoderSettings = EncoderSettings {
+    application: EncoderApplication::Voip,
+    bitrate: Some(24000),
+    bitrate_mode: BitrateMode::Vbr,
+    complexity: 10,
+    frame_size: 200,
+    stereo: false,
+    bandwidth: EncoderBandwidth::Auto,
+};
+
+/// How often each listener gets a mixed frame.
+pub const MIX_INTERVAL: Duration = Duration::from_millis(20);
+
+/// One participant's decoded audio, waiting to be mixed.
+struct Source {
+    decoder: StreamDecoder,
+    queue: StreamQueue,
+    // Set while the participant whispers: only these listeners hear it.
+    whisper_targets: Option<Vec<SocketAddr>>,
+    frame: Vec<f32>,
+    active: bool,
+}
+
+/// One client receiving a mix.
+struct Listener {
+    encoder: Encoder,
+    sealer: PacketSealer,
+    sequence: u32,
+    limiter: Limiter,
+}
+
+/// Mixing state of one room.
+pub struct MixRoom {
+    key: RoomKey,
+    opener: PacketOpener,
+    sources: HashMap<SocketAddr, Source>,
+    listeners: HashMap<SocketAddr, Listener>,
+    pcm: Vec<f32>,
+    mix: Vec<f32>,
+    encoded: Vec<u8>,
+}
+
+impl MixRoom {
+    pub fn new(key: RoomKey) -> Self {
+        Self {
+            opener: PacketOpener::new(&key),
+            key,
+            sources: HashMap::new(),
+            listeners: HashMap::new(),
+            pcm: vec![0.0; MAX_FRAME_SAMPLES * 2],
+            mix: vec![0.0; MIX_SETTINGS.frame_samples()],
+            encoded: vec![0u8; MAX_PACKET_SIZE],
+        }
+    }
+
+    pub fn key(&self) -> &RoomKey {
+        &self.key
+    }
+
+    /// Decodes a sealed packet from `from` into its queue. `whisper_targets`
+    /// is empty unless the packet is for only some of the room. Fails if the
+    /// packet doesn't open under the room key; that's the caller's to judge,
+    /// as a packet a second is no reason to log.
+    pub fn push(&mut self, from: SocketAddr, whisper_targets: &[SocketAddr], packet: &[u8]) -> Result<(), OpenError> {
+        let (header, payload) = self.opener.open(from, packet)?;
+        if header.is_bye() {
+            self.sources.remove(&from);
+            return Ok(());
+        }
+        if header.is_keepalive() || header.is_control() {
+            return Ok(());
+        }
+        let source = match self.sources.entry(from) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => match StreamDecoder::new(header.is_stereo()) {
+                Ok(decoder) => entry.insert(Source {
+                    decoder,
+                    queue: StreamQueue::default(),
+                    whisper_targets: None,
+                    frame: vec![0.0; MIX_SETTINGS.frame_samples()],
+                    active: false,
+                }),
+                Err(e) => {
+                    eprintln!("Error creating decoder for {}: {}", from, e);
+                    return Ok(());
+                }
+            },
+        };
+        let decoded = match source.decoder.decode(&payload, header.is_stereo(), &mut self.pcm) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                eprintln!("Error decoding audio from {}: {}", from, e);
+                return Ok(());
+            }
+        };
+        let channels = if header.is_stereo() { 2 } else { 1 };
+        source.queue.push(&self.pcm[..decoded * channels], channels, 1);
+        source.whisper_targets = (!whisper_targets.is_empty()).then(|| whisper_targets.to_vec());
+        Ok(())
+    }
+
+    /// Builds the next frame for each of `listeners`: everyone else in the
+    /// room but the members that listener muted, plus whispers aimed at
+    /// them. Returns the sealed packets; a listener with nobody to hear gets
+    /// nothing this tick.
+    pub fn render(&mut self, listeners: &[(SocketAddr, &[SocketAddr])]) -> Vec<(SocketAddr, BytesMut)> {
+        self.listeners.retain(|addr, _| listeners.iter().any(|(listener, _)| listener == addr));
+        for source in self.sources.values_mut() {
+            source.frame.fill(0.0);
+            source.active = source.queue.mix_into(&mut source.frame, 1.0);
+        }
+
+        let mut packets = Vec::new();
+        for &(addr, muted) in listeners {
+            self.mix.fill(0.0);
+            let mut heard = false;
+            for (from, source) in &self.sources {
+                let audible = match &source.whisper_targets {
+                    Some(targets) => targets.contains(&addr),
+                    None => true,
+                };
+                if *from == addr || !source.active || !audible || muted.contains(from) {
+                    continue;
+                }
+                for (out, sample) in self.mix.iter_mut().zip(&source.frame) {
+                    *out += sample;
+                }
+                heard = true;
+            }
+            if !heard {
+                continue;
+            }
+
+            let listener = match self.listeners.entry(addr) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => match MIX_SETTINGS.build_encoder() {
+                    Ok(encoder) => entry.insert(Listener {
+                        encoder,
+                        sealer: PacketSealer::new(&self.key),
+                        sequence: 0,
+                        limiter: Limiter::new(SAMPLE_RATE),
+                    }),
+                    Err(e) => {
+                        eprintln!("Error creating mix encoder for {}: {}", addr, e);
+                        continue;
+                    }
+                },
+            };
+            for sample in self.mix.iter_mut() {
+                *sample = listener.limiter.process_sample(*sample);
+            }
+            let size = match listener.encoder.encode_float(&self.mix, &mut self.encoded) {
+                Ok(size) => size,
+                Err(e) => {
+                    eprintln!("Error encoding mix for {}: {}", addr, e);
+                    continue;
+                }
+            };
+            match listener.sealer.seal(build_audio_packet(listener.sequence, 0, &self.encoded[..size])) {
+                Ok(packet) => packets.push((addr, packet)),
+                Err(e) => eprintln!("Error sealing mix for {}: {}", addr, e),
+            }
+            listener.sequence = listener.sequence.wrapping_add(1);
+        }
+        packets
+    }
+
+    /// Drops state for anyone `keep` rejects.
+    pub fn retain(&mut self, keep: impl Fn(&SocketAddr) -> bool) {
+        self.sources.retain(|addr, _| keep(addr));
+        self.listeners.retain(|addr, _| keep(addr));
+    }
+}
@@ -0,0 +1,345 @@
+// src-tauri/src/audio/mixer.rs
+
+use ringbuf::HeapProducer;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender, SyncSender, TryRecvError, TrySendError};
+use std::time::Duration;
+use atomic_float::AtomicF32;
+use super::codec::{StreamDecoder, MAX_FRAME_SAMPLES, SAMPLE_RATE};
+use super::dsp::Limiter;
+use super::realtime::Worker;
+use super::spatial::{ListenerPose, Position, SpatialSettings, Spatializer};
+
+// The mixer produces 10 ms frames and keeps the playback ring ~30 ms ahead of the device.
+const FRAME_SAMPLES: usize = 480;
+const TARGET_FILL_FRAMES: usize = 3;
+// A peer starts (and restarts after an underrun) once this much audio is queued.
+const PREBUFFER_FRAMES: usize = 2;
+// Anything queued beyond this is stale; drop the oldest audio.
+const MAX_QUEUE_FRAMES: usize = 20;
+const COMMAND_QUEUE: usize = 256;
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+// Two short rising blips played when a peer starts whispering to us.
+const CUE_TONES_HZ: [f32; 2] = [880.0, 1320.0];
+const CUE_TONE_MS: usize = 60;
+const CUE_GAP_MS: usize = 20;
+const CUE_LEVEL: f32 = 0.15;
+
+pub enum MixerCommand {
+    Packet { from: SocketAddr, payload: Vec<u8>, stereo: bool, whisper: bool },
+    SetOutput { producer: HeapProducer<f32>, channels: usize },
+    RemovePeer(SocketAddr),
+    /// Kept until changed, even for peers that haven't sent anything yet.
+    SetPeerVolume { addr: SocketAddr, volume: f32 },
+    /// Soundboard audio we inject into our own stream, played back locally.
+    Monitor { samples: Vec<f32>, channels: usize },
+    /// Where a peer is in the game world; `None` plays them unplaced.
+    SetPeerPosition { addr: SocketAddr, position: Option<Position> },
+    SetListener(ListenerPose),
+    SetSpatial(SpatialSettings),
+}
+
+/// Audio waiting to be mixed, in the output channel layout.
+#[derive(Default)]
+pub(crate) struct StreamQueue {
+    samples: VecDeque<f32>,
+    playing: bool,
+}
+
+impl StreamQueue {
+    pub(crate) fn clear(&mut self) {
+        self.samples.clear();
+        self.playing = false;
+    }
+
+    /// Appends interleaved audio, converting its channel layout to the output's.
+    pub(crate) fn push(&mut self, samples: &[f32], from_channels: usize, to_channels: usize) {
+        match (from_channels, to_channels) {
+            (1, 2) => {
+                for &sample in samples {
+                    self.samples.push_back(sample);
+                    self.samples.push_back(sample);
+                }
+            }
+            (2, 1) => {
+                for pair in samples.chunks_exact(2) {
+                    self.samples.push_back((pair[0] + pair[1]) * 0.5);
+                }
+            }
+            _ => self.samples.extend(samples),
+        }
+
+        let max_len = MAX_QUEUE_FRAMES * FRAME_SAMPLES * to_channels;
+        if self.samples.len() > max_len {
+            let excess = self.samples.len() - max_len;
+            self.samples.drain(..excess);
+        }
+    }
+
+    /// Adds the next `mix.len()` samples into `mix`. Returns whether there
+    /// was anything to add.
+    pub(crate) fn mix_into(&mut self, mix: &mut [f32], gain: f32) -> bool {
+        if !self.playing && self.samples.len() >= PREBUFFER_FRAMES * mix.len() {
+            self.playing = true;
+        }
+        if !self.playing {
+            return false;
+        }
+        let take = mix.len().min(self.samples.len());
+        for (out, sample) in mix.iter_mut().zip(self.samples.drain(..take)) {
+            *out += sample * gain;
+        }
+        if self.samples.is_empty() {
+            // Underrun: wait for the queue to refill before resuming.
+            self.playing = false;
+        }
+        take > 0
+    }
+}
+
+struct PeerStream {
+    decoder: StreamDecoder,
+    queue: StreamQueue,
+    // Whether the last packet was a whisper.
+    whispering: bool,
+    spatializer: Spatializer,
+}
+
+/// Mono cue announcing a whisper, with short fades so it doesn't click.
+fn whisper_cue() -> Vec<f32> {
+    let rate = SAMPLE_RATE as usize;
+    let tone_len = rate * CUE_TONE_MS / 1000;
+    let fade_len = tone_len / 6;
+    let mut cue = Vec::new();
+    for (i, frequency) in CUE_TONES_HZ.iter().enumerate() {
+        if i > 0 {
+            cue.resize(cue.len() + rate * CUE_GAP_MS / 1000, 0.0);
+        }
+        for n in 0..tone_len {
+            let fade = (n.min(tone_len - 1 - n) as f32 / fade_len as f32).min(1.0);
+            let phase = 2.0 * std::f32::consts::PI * frequency * n as f32 / SAMPLE_RATE as f32;
+            cue.push(phase.sin() * CUE_LEVEL * fade);
+        }
+    }
+    cue
+}
+
+struct Output {
+    producer: HeapProducer<f32>,
+    channels: usize,
+}
+
+struct Mixer {
+    peers: HashMap<SocketAddr, PeerStream>,
+    peer_volumes: HashMap<SocketAddr, f32>,
+    monitor: StreamQueue,
+    cues: StreamQueue,
+    whisper_cue: Vec<f32>,
+    output: Option<Output>,
+    volume: Arc<AtomicF32>,
+    limiter: Limiter,
+    spatial: SpatialSettings,
+    listener: ListenerPose,
+    positions: HashMap<SocketAddr, Position>,
+    pcm: Vec<f32>,
+    mono: Vec<f32>,
+    placed: Vec<f32>,
+    mix: Vec<f32>,
+}
+
+impl Mixer {
+    fn handle(&mut self, command: MixerCommand) {
+        match command {
+            MixerCommand::Packet { from, payload, stereo, whisper } => self.decode_packet(from, &payload, stereo, whisper),
+            MixerCommand::SetOutput { producer, channels } => {
+                // Queued audio is in the old layout.
+                for peer in self.peers.values_mut() {
+                    peer.queue.clear();
+                }
+                self.monitor.clear();
+                self.cues.clear();
+                self.output = Some(Output { producer, channels });
+            }
+            MixerCommand::RemovePeer(addr) => {
+                self.peers.remove(&addr);
+            }
+            MixerCommand::SetPeerVolume { addr, volume } => {
+                self.peer_volumes.insert(addr, volume);
+            }
+            MixerCommand::Monitor { samples, channels } => {
+                if let Some(output) = self.output.as_ref() {
+                    self.monitor.push(&samples, channels, output.channels);
+                }
+            }
+            MixerCommand::SetPeerPosition { addr, position } => match position {
+                Some(position) => {
+                    self.positions.insert(addr, position);
+                }
+                None => {
+                    self.positions.remove(&addr);
+                    // Start from the next placement rather than gliding from a stale one.
+                    if let Some(peer) = self.peers.get_mut(&addr) {
+                        peer.spatializer = Spatializer::default();
+                    }
+                }
+            },
+            MixerCommand::SetListener(pose) => self.listener = pose,
+            MixerCommand::SetSpatial(settings) => self.spatial = settings,
+        }
+    }
+
+    fn decode_packet(&mut self, from: SocketAddr, payload: &[u8], stereo: bool, whisper: bool) {
+        let Some(channels) = self.output.as_ref().map(|o| o.channels) else {
+            return;
+        };
+        let peer = match self.peers.entry(from) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => match StreamDecoder::new(stereo) {
+                Ok(decoder) => entry.insert(PeerStream {
+                    decoder,
+                    queue: StreamQueue::default(),
+                    whispering: false,
+                    spatializer: Spatializer::default(),
+                }),
+                Err(e) => {
+                    eprintln!("Error creating decoder for {}: {}", from, e);
+                    return;
+                }
+            },
+        };
+        let decoded = match peer.decoder.decode(payload, stereo, &mut self.pcm) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                eprintln!("Error decoding audio from {}: {}", from, e);
+                return;
+            }
+        };
+
+        let from_channels = if stereo { 2 } else { 1 };
+        let pcm = &self.pcm[..decoded * from_channels];
+        match self.positions.get(&from).filter(|_| self.spatial.enabled) {
+            Some(&position) => {
+                // Placement works on mono; a stereo sender's own image is dropped.
+                self.mono.clear();
+                if stereo {
+                    self.mono.extend(pcm.chunks_exact(2).map(|pair| (pair[0] + pair[1]) * 0.5));
+                } else {
+                    self.mono.extend_from_slice(pcm);
+                }
+                peer.spatializer.process(&self.mono, position, &self.listener, &self.spatial, channels, &mut self.placed);
+                peer.queue.push(&self.placed, channels, channels);
+            }
+            None => peer.queue.push(pcm, from_channels, channels),
+        }
+        if whisper && !peer.whispering {
+            self.cues.push(&self.whisper_cue, 1, channels);
+        }
+        peer.whispering = whisper;
+    }
+
+    /// Tops the playback ring up to the target fill, one frame at a time.
+    fn fill_output(&mut self) {
+        let Some(output) = self.output.as_mut() else {
+            return;
+        };
+        let frame_len = FRAME_SAMPLES * output.channels;
+        while output.producer.len() < TARGET_FILL_FRAMES * frame_len
+            && output.producer.free_len() >= frame_len
+        {
+            self.mix.clear();
+            self.mix.resize(frame_len, 0.0);
+            for (addr, peer) in self.peers.iter_mut() {
+                let gain = self.peer_volumes.get(addr).copied().unwrap_or(1.0);
+                peer.queue.mix_into(&mut self.mix, gain);
+            }
+            self.monitor.mix_into(&mut self.mix, 1.0);
+            self.cues.mix_into(&mut self.mix, 1.0);
+
+            let volume = self.volume.load(Ordering::Relaxed);
+            for sample in self.mix.iter_mut() {
+                // Keeps the summed peers from clipping the output device.
+                *sample = self.limiter.process_sample(*sample * volume);
+            }
+            output.producer.push_slice(&self.mix);
+        }
+    }
+}
+
+/// Handle to the decode/mix thread. Cheap to clone; the thread stops when the
+/// last handle is dropped.
+#[derive(Clone)]
+pub struct MixerHandle {
+    // Audio, which can be dropped when the mixer falls behind.
+    commands: SyncSender<MixerCommand>,
+    // Everything else, which must not be lost.
+    control: Sender<MixerCommand>,
+    _worker: Arc<Worker>,
+}
+
+impl MixerHandle {
+    pub fn spawn(volume: Arc<AtomicF32>) -> std::io::Result<Self> {
+        let (commands, rx) = mpsc::sync_channel(COMMAND_QUEUE);
+        let (control, control_rx) = mpsc::channel();
+        let worker = Worker::spawn("llas-mixer", move |stop: Arc<AtomicBool>| {
+            let mut mixer = Mixer {
+                peers: HashMap::new(),
+                peer_volumes: HashMap::new(),
+                monitor: StreamQueue::default(),
+                cues: StreamQueue::default(),
+                whisper_cue: whisper_cue(),
+                output: None,
+                volume,
+                limiter: Limiter::new(SAMPLE_RATE),
+                spatial: SpatialSettings::default(),
+                listener: ListenerPose::default(),
+                positions: HashMap::new(),
+                pcm: vec![0.0; MAX_FRAME_SAMPLES * 2],
+                mono: Vec::with_capacity(MAX_FRAME_SAMPLES),
+                placed: Vec::with_capacity(MAX_FRAME_SAMPLES * 2),
+                mix: Vec::with_capacity(FRAME_SAMPLES * 2),
+            };
+            while !stop.load(Ordering::Relaxed) {
+                // Settings go first, so audio is decoded and mixed with them.
+                loop {
+                    match control_rx.try_recv() {
+                        Ok(command) => mixer.handle(command),
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => return,
+                    }
+                }
+                match rx.recv_timeout(POLL_INTERVAL) {
+                    Ok(command) => {
+                        mixer.handle(command);
+                        while let Ok(command) = rx.try_recv() {
+                            mixer.handle(command);
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                mixer.fill_output();
+            }
+        })?;
+        Ok(Self {
+            commands,
+            control,
+            _worker: Arc::new(worker),
+        })
+    }
+
+    /// Never blocks. Audio (`Packet` and `Monitor`) is dropped if the mixer
+    /// is backed up; every other command is queued until the mixer gets to
+    /// it. Returns false if the command was dropped.
+    pub fn send(&self, command: MixerCommand) -> bool {
+        match command {
+            MixerCommand::Packet { .. } | MixerCommand::Monitor { .. } => match self.commands.try_send(command) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+            },
+            _ => self.control.send(command).is_ok(),
+        }
+    }
+}
@@ -1,8 +1,29 @@
 // src/audio/mod.rs
 
+pub mod capture;
+pub mod codec;
+pub mod control;
+pub mod crypto;
+pub mod dsp;
+pub mod engine;
+pub mod file_source;
+pub mod lifecycle;
+pub mod loopback;
+pub mod mcu;
+pub mod mixer;
 pub mod network;
+pub mod packet;
+pub mod presence;
 pub mod processor;
+pub mod realtime;
+pub mod reconnect;
+pub mod recorder;
+pub mod sfu;
+pub mod soundboard;
+pub mod spatial;
+pub mod virtual_device;
 
 // Re-export the key types for easier use elsewhere in your crate.
+pub use lifecycle::{EngineLifecycle, EngineState};
 pub use network::AudioNetwork;
 pub use processor::AudioProcessor;
@@ -1,13 +1,21 @@
 // src-tauri/src/audio/network.rs
 
 use tokio::net::UdpSocket;
-use tokio::sync::{mpsc, broadcast};
+use tokio::sync::{mpsc, broadcast, watch};
+use tokio_util::sync::CancellationToken;
 use parking_lot::Mutex;
-use bytes::{BytesMut, BufMut};
 use std::net::{SocketAddr, IpAddr, Ipv4Addr};
 use std::sync::Arc;
-use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
 use super::processor::AudioProcessor;
+use super::control::{ChatInbox, ChatOutbox, ControlMessage, RETRANSMIT_AFTER};
+use super::crypto::{OpenError, PacketOpener, PacketSealer, RoomKey};
+use super::packet::{build_audio_packet, PacketHeader, FLAG_BYE, FLAG_CONTROL, FLAG_KEEPALIVE, FLAG_RECORDING, FLAG_WHISPER};
+use super::presence::{PeerPresence, PeerState, PresenceTracker, KEEPALIVE_INTERVAL};
+use super::reconnect::{is_link_failure, route_to, Backoff, LinkEvent, LinkMonitor, ATTEMPT_TIMEOUT, ROUTE_CHECK_INTERVAL};
+use super::sfu::{client_frame, key_frame, muted_frame, parse_relay_frame, SfuRoute, RELAY_MIXED, RELAY_RESUMED};
 use crate::config::TurnConfig;
 use std::io::Write;
 use byteorder::{BigEndian, WriteBytesExt};
@@ -21,6 +29,65 @@ const LIFETIME: u16 = 0x000D;
 const REALM_ATTR: u16 = 0x0014;
 const NONCE_ATTR: u16 = 0x0015;
 
+const NO_ROOM_KEY: &str = "No key for this room; rejoin it to receive one";
+// How often peers' presence is checked.
+const PRESENCE_SWEEP_INTERVAL: Duration = Duration::from_millis(500);
+// A whisper without audio for this long has ended.
+const WHISPER_IDLE: Duration = Duration::from_millis(500);
+// How often a mixing server is reminded of the room key, in case it
+// restarted or the last reminder was lost.
+const MIX_KEY_INTERVAL: Duration = Duration::from_secs(5);
+// After a reconnect the room is told our old address this many times, this
+// far apart; nothing acknowledges it.
+const MOVE_ANNOUNCEMENTS: u32 = 3;
+const MOVE_ANNOUNCE_INTERVAL: Duration = Duration::from_millis(250);
+
+// A control message and the peer it is for.
+type ControlSender = mpsc::UnboundedSender<(SocketAddr, Vec<u8>)>;
+
+/// Incoming packets dropped before playback, by reason.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DroppedPackets {
+    /// Sent from an address that isn't a member of the room.
+    pub unknown_source: u64,
+    /// From a member the room has silenced.
+    pub muted_source: u64,
+    pub malformed: u64,
+    /// Unencrypted, or failed authentication under the room key.
+    pub unauthenticated: u64,
+    pub replayed: u64,
+}
+
+#[derive(Default)]
+struct DropCounters {
+    unknown_source: AtomicU64,
+    muted_source: AtomicU64,
+    malformed: AtomicU64,
+    unauthenticated: AtomicU64,
+    replayed: AtomicU64,
+}
+
+impl DropCounters {
+    fn count(&self, error: OpenError) {
+        let counter = match error {
+            OpenError::Malformed => &self.malformed,
+            OpenError::Unencrypted | OpenError::Unauthenticated => &self.unauthenticated,
+            OpenError::Replayed => &self.replayed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> DroppedPackets {
+        DroppedPackets {
+            unknown_source: self.unknown_source.load(Ordering::Relaxed),
+            muted_source: self.muted_source.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+            unauthenticated: self.unauthenticated.load(Ordering::Relaxed),
+            replayed: self.replayed.load(Ordering::Relaxed),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct NetworkStats {
     pub latency: Duration,
@@ -61,7 +128,11 @@ impl QualityMonitor {
     }
 
     fn update(&mut self, sequence: u32, received_time: Instant) {
-        if self.last_sequence != 0 {
+        if self.packets_received > 0 {
+            if sequence <= self.last_sequence {
+                // Late or duplicated; it was already counted as lost.
+                return;
+            }
             let expected = sequence - self.last_sequence;
             if expected > 1 {
                 self.packets_lost += expected - 1;
@@ -88,6 +159,14 @@ impl QualityMonitor {
         self.last_packet_time = received_time;
     }
 
+    /// Starts counting from `sequence` again, for when earlier packets were
+    /// held back on purpose rather than lost.
+    fn resume_at(&mut self, sequence: u32) {
+        if self.packets_received > 0 {
+            self.last_sequence = sequence.wrapping_sub(1);
+        }
+    }
+
     fn get_stats(&self) -> NetworkStats {
         let avg_latency = self.calculate_average_latency();
         let packet_loss = self.calculate_packet_loss();
@@ -191,55 +270,127 @@ impl JitterBuffer {
 
 pub struct AudioNetwork {
     socket: Arc<UdpSocket>,
-    turn_socket: Arc<UdpSocket>,
-    peers: Vec<SocketAddr>,
+    // Replaced when the network changes; the streaming tasks follow it.
+    turn_socket: Arc<watch::Sender<Arc<UdpSocket>>>,
+    bind_addr: String,
+    turn_config: TurnConfig,
+    link: Arc<LinkMonitor>,
+    link_tx: broadcast::Sender<LinkEvent>,
+    // The room's members. Shared with the send and receive tasks so they
+    // follow joins and leaves while streaming.
+    peers: Arc<Mutex<Vec<SocketAddr>>>,
+    // Members whose audio the room has silenced (force-muted or listeners).
+    muted_peers: Arc<Mutex<HashSet<SocketAddr>>>,
     buffer_size: usize,
     sequence: std::sync::atomic::AtomicU32,
-    audio_tx: broadcast::Sender<(Vec<u8>, SocketAddr)>,
-    jitter_buffers: HashMap<SocketAddr, JitterBuffer>,
-    quality_monitors: HashMap<SocketAddr, QualityMonitor>,
+    audio_tx: broadcast::Sender<(Vec<u8>, SocketAddr, PacketHeader)>,
+    jitter_buffers: Arc<Mutex<HashMap<SocketAddr, JitterBuffer>>>,
+    quality_monitors: Arc<Mutex<HashMap<SocketAddr, QualityMonitor>>>,
     stats_tx: broadcast::Sender<(SocketAddr, NetworkStats)>,
+    presence: Arc<Mutex<PresenceTracker>>,
+    presence_tx: broadcast::Sender<(SocketAddr, PeerState)>,
+    // Set while we record; flagged on every packet we send.
+    recording: Arc<std::sync::atomic::AtomicBool>,
+    recording_tx: broadcast::Sender<(SocketAddr, bool)>,
+    // While set, our audio only goes to these members.
+    whisper_targets: Arc<Mutex<Option<Vec<SocketAddr>>>>,
+    whisper_tx: broadcast::Sender<(SocketAddr, bool)>,
+    // Seals everything we send and authenticates everything we receive.
+    // Running streams switch over whenever it changes.
+    room_key: watch::Sender<Option<RoomKey>>,
+    // When set, we send everything once to this forwarding server and
+    // receive the room from it, instead of talking to each peer.
+    sfu: watch::Sender<Option<SfuRoute>>,
+    dropped: Arc<DropCounters>,
+    // Chat we sent that peers haven't acknowledged, and chat from peers
+    // waiting for what was sent before it.
+    chat_outbox: Arc<Mutex<ChatOutbox>>,
+    chat_inbox: Arc<Mutex<ChatInbox>>,
+    chat_tx: broadcast::Sender<(SocketAddr, String)>,
+    // Control packets for the streaming task to seal and send, and who to;
+    // set while streaming.
+    control_tx: Arc<Mutex<Option<ControlSender>>>,
+    // Peers that reconnected from a new address: (old, new).
+    moved_tx: broadcast::Sender<(SocketAddr, SocketAddr)>,
 }
 
 impl AudioNetwork {
     pub async fn new(bind_addr: &str, turn_config: TurnConfig) -> Result<Self, Box<dyn std::error::Error>> {
-        // Bind a UDP socket.
-        let socket = UdpSocket::bind(bind_addr).await?;
-        socket.set_ttl(32)?;
-
-        // Convert the socket to its std version to clone it.
-        let std_socket = socket.into_std()?;
-        let std_socket_clone = std_socket.try_clone()?;
-        let socket = UdpSocket::from_std(std_socket)?;
-        let turn_socket = Self::setup_turn_connection(&turn_config, UdpSocket::from_std(std_socket_clone)?).await?;
+        let (socket, turn_socket) = Self::bind(bind_addr, &turn_config).await?;
 
         let (audio_tx, _) = broadcast::channel(100);
         let (stats_tx, _) = broadcast::channel(100);
+        let (recording_tx, _) = broadcast::channel(100);
+        let (presence_tx, _) = broadcast::channel(100);
+        let (link_tx, _) = broadcast::channel(100);
+        let (whisper_tx, _) = broadcast::channel(100);
+        let (chat_tx, _) = broadcast::channel(100);
+        let (moved_tx, _) = broadcast::channel(100);
 
         Ok(Self {
             socket: Arc::new(socket),
-            turn_socket: Arc::new(turn_socket),
-            peers: Vec::new(),
+            turn_socket: Arc::new(watch::channel(Arc::new(turn_socket)).0),
+            bind_addr: bind_addr.to_string(),
+            turn_config,
+            link: Arc::new(LinkMonitor::default()),
+            link_tx,
+            peers: Arc::new(Mutex::new(Vec::new())),
+            muted_peers: Arc::new(Mutex::new(HashSet::new())),
             buffer_size: 480,
             sequence: std::sync::atomic::AtomicU32::new(0),
             audio_tx,
-            jitter_buffers: HashMap::new(),
-            quality_monitors: HashMap::new(),
+            jitter_buffers: Arc::new(Mutex::new(HashMap::new())),
+            quality_monitors: Arc::new(Mutex::new(HashMap::new())),
             stats_tx,
+            presence: Arc::new(Mutex::new(PresenceTracker::default())),
+            presence_tx,
+            recording: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            recording_tx,
+            whisper_targets: Arc::new(Mutex::new(None)),
+            whisper_tx,
+            room_key: watch::channel(None).0,
+            sfu: watch::channel(None).0,
+            dropped: Arc::new(DropCounters::default()),
+            chat_outbox: Arc::new(Mutex::new(ChatOutbox::default())),
+            chat_inbox: Arc::new(Mutex::new(ChatInbox::default())),
+            chat_tx,
+            control_tx: Arc::new(Mutex::new(None)),
+            moved_tx,
         })
     }
 
+    /// Binds a UDP socket and allocates a TURN relay on a clone of it.
+    async fn bind(bind_addr: &str, turn_config: &TurnConfig) -> Result<(UdpSocket, UdpSocket), Box<dyn std::error::Error>> {
+        // Bind a UDP socket. An unspecified address becomes the interface
+        // that routes to the TURN server, so our local address is the one
+        // peers can reach us at.
+        let mut bind_addr: SocketAddr = bind_addr.parse()?;
+        if bind_addr.ip().is_unspecified() {
+            let server: SocketAddr = turn_config.url.trim_start_matches("turn:").parse()?;
+            bind_addr.set_ip(route_to(server).await?);
+        }
+        let socket = UdpSocket::bind(bind_addr).await?;
+        socket.set_ttl(32)?;
+
+        // Convert the socket to its std version to clone it.
+        let std_socket = socket.into_std()?;
+        let std_socket_clone = std_socket.try_clone()?;
+        let socket = UdpSocket::from_std(std_socket)?;
+        let turn_socket = Self::setup_turn_connection(turn_config, UdpSocket::from_std(std_socket_clone)?).await?;
+        Ok((socket, turn_socket))
+    }
+
     async fn setup_turn_connection(
         config: &TurnConfig,
         local_socket: UdpSocket
     ) -> Result<UdpSocket, Box<dyn std::error::Error>> {
-        println!("Starting TURN connection setup...");
+        eprintln!("Starting TURN connection setup...");
         // Remove "turn:" prefix if present.
         let url = config.url.trim_start_matches("turn:");
         let server_addr: SocketAddr = url.parse()?;
-        println!("Connecting to TURN server at: {}", server_addr);
-        local_socket.connect(server_addr).await?;
-        println!("Connected to TURN server");
+        // Not connected: the same socket carries audio to and from peers,
+        // and a connected UDP socket would only receive from the server.
+        eprintln!("Using TURN server at: {}", server_addr);
 
         // Create TURN allocation request.
         let mut request = Vec::new();
@@ -265,7 +416,7 @@ impl AudioNetwork {
         // Add credentials.
         let username = config.username.as_bytes();
         let credential = config.credential.as_bytes();
-        println!("Adding credentials - Username length: {}", username.len());
+        eprintln!("Adding credentials - Username length: {}", username.len());
         request.write_u16::<BigEndian>(0x0006)?; // Username type
         request.write_u16::<BigEndian>(username.len() as u16)?;
         request.write_all(username)?;
@@ -282,146 +433,463 @@ impl AudioNetwork {
         let message_length = (request.len() - 20) as u16;
         request[2..4].copy_from_slice(&message_length.to_be_bytes());
 
-        println!("Sending TURN allocation request...");
-        println!("Request bytes: {:02x?}", request);
-        local_socket.send(&request).await?;
-        println!("Request sent, waiting for response...");
+        eprintln!("Sending TURN allocation request...");
+        eprintln!("Request bytes: {:02x?}", request);
+        local_socket.send_to(&request, server_addr).await?;
+        eprintln!("Request sent, waiting for response...");
 
         let mut response = vec![0u8; 1024];
-        let size = local_socket.recv(&mut response).await?;
-        println!("Received response of {} bytes", size);
-        println!("Response bytes: {:02x?}", &response[..size]);
+        let size = loop {
+            let (size, from) = local_socket.recv_from(&mut response).await?;
+            if from == server_addr {
+                break size;
+            }
+        };
+        eprintln!("Received response of {} bytes", size);
+        eprintln!("Response bytes: {:02x?}", &response[..size]);
 
         if size >= 2 {
             let message_type = u16::from_be_bytes([response[0], response[1]]);
-            println!("Response message type: 0x{:04x}", message_type);
+            eprintln!("Response message type: 0x{:04x}", message_type);
         }
 
         if let Some(relayed_address) = process_turn_response(&response[..size])? {
-            println!("TURN allocation successful. Relayed address: {}", relayed_address);
+            eprintln!("TURN allocation successful. Relayed address: {}", relayed_address);
             Ok(local_socket)
         } else {
-            println!("Failed to get relayed address from response");
+            eprintln!("Failed to get relayed address from response");
             Err("Failed to get relayed address from TURN server".into())
         }
     }
 
     pub async fn send_audio(&mut self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let sealer = PacketSealer::new(self.room_key.borrow().as_ref().ok_or(NO_ROOM_KEY)?);
         let sequence = self.sequence.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        let mut packet = Vec::with_capacity(data.len() + 4);
-        packet.extend_from_slice(&sequence.to_be_bytes());
-        packet.extend_from_slice(data);
+        let packet = sealer.seal(build_audio_packet(sequence, self.packet_flags(), data))?;
 
         // Send to all peers through TURN server
-        let peers = self.peers.clone();
-        if peers.is_empty() {
-            println!("No peers to send audio to");
-            return Ok(());
-        }
-
+        let peers = self.peers.lock().clone();
+        let socket = self.turn_socket.borrow().clone();
         for peer in peers {
-            println!("Sending {} bytes of audio data to peer {}", packet.len(), peer);
-            self.turn_socket.send_to(&packet, peer).await?;
+            socket.send_to(&packet, peer).await?;
         }
         Ok(())
     }
 
     pub fn add_peer(&mut self, addr: SocketAddr) {
-        if !self.peers.contains(&addr) {
-            self.peers.push(addr);
-            self.jitter_buffers.insert(addr, JitterBuffer::new(20, 50));
-            self.quality_monitors.insert(addr, QualityMonitor::new());
+        let mut peers = self.peers.lock();
+        if !peers.contains(&addr) {
+            peers.push(addr);
+            self.jitter_buffers.lock().insert(addr, JitterBuffer::new(20, 50));
+            self.quality_monitors.lock().insert(addr, QualityMonitor::new());
         }
     }
 
     pub fn remove_peer(&mut self, addr: &SocketAddr) {
-        self.peers.retain(|x| x != addr);
-        self.jitter_buffers.remove(addr);
+        self.peer_tables().remove(addr);
     }
 
-    pub async fn start_streaming(&mut self, mut rx: mpsc::Receiver<Vec<u8>>) {
-        let socket = self.turn_socket.clone();
+    fn peer_tables(&self) -> PeerTables {
+        PeerTables {
+            peers: self.peers.clone(),
+            muted_peers: self.muted_peers.clone(),
+            jitter_buffers: self.jitter_buffers.clone(),
+            quality_monitors: self.quality_monitors.clone(),
+            presence: self.presence.clone(),
+            chat_outbox: self.chat_outbox.clone(),
+            chat_inbox: self.chat_inbox.clone(),
+        }
+    }
+
+    /// Sends `text` to everyone in the room as chat, again and again until
+    /// each of them acknowledges it. Fails while we aren't streaming.
+    pub fn send_chat(&self, text: &str) -> Result<(), String> {
+        let control_tx = self.control_tx.lock();
+        let control_tx = control_tx.as_ref().ok_or("Not streaming to the room")?;
+        let peers = self.peers.lock().clone();
+        for packet in self.chat_outbox.lock().send(text, &peers, Instant::now()) {
+            let _ = control_tx.send(packet);
+        }
+        Ok(())
+    }
+
+    /// Chat texts from peers, each whole and in the order its sender sent them.
+    pub fn subscribe_to_chat(&self) -> broadcast::Receiver<(SocketAddr, String)> {
+        self.chat_tx.subscribe()
+    }
+
+    /// Drops (or resumes playing) everything `addr` sends.
+    pub fn set_peer_muted(&self, addr: SocketAddr, muted: bool) {
+        let mut muted_peers = self.muted_peers.lock();
+        if muted {
+            muted_peers.insert(addr);
+        } else {
+            muted_peers.remove(&addr);
+        }
+    }
+
+    /// Sets the key of the room we stream in. Streaming refuses to start
+    /// without one; a running stream seals and opens everything after this
+    /// under the new key.
+    pub fn set_room_key(&mut self, key: Option<RoomKey>) {
+        self.room_key.send_replace(key);
+    }
+
+    /// Streams through a forwarding server, or directly to every peer for
+    /// `None`. With a `mix_key` the server sends us the room as one mixed
+    /// stream. Set it before a new room key, so the server is handed the
+    /// key it mixes under along with it.
+    pub fn set_sfu(&mut self, route: Option<SfuRoute>) {
+        self.sfu.send_replace(route);
+    }
+
+    /// Sends encoded frames from `rx` to every peer, or only the whisper
+    /// targets while there are some, until `cancel` fires. Everyone gets a
+    /// keepalive whenever they haven't heard from us for a while. Peers are
+    /// told when we stop.
+    pub async fn start_streaming(&mut self, mut rx: mpsc::Receiver<Vec<u8>>, cancel: CancellationToken) -> Result<(), String> {
+        let mut room_key = self.room_key.subscribe();
+        let sealer = PacketSealer::new(room_key.borrow_and_update().as_ref().ok_or(NO_ROOM_KEY)?);
+        let mut outbound = Outbound {
+            socket: self.turn_socket.subscribe(),
+            link: self.link.clone(),
+            room_key,
+            sealer,
+            sfu: self.sfu.subscribe(),
+            muted_peers: self.muted_peers.clone(),
+            muted_sent: HashSet::new(),
+        };
         let peers = self.peers.clone();
+        let mut sequence = self.sequence.load(std::sync::atomic::Ordering::SeqCst);
+        let recording = self.recording.clone();
+        let whisper_targets = self.whisper_targets.clone();
+        // Control packets share the audio's sequence numbers and sealer, so
+        // they are sent from here too.
+        let (control_tx, mut control_rx) = mpsc::unbounded_channel();
+        *self.control_tx.lock() = Some(control_tx.clone());
+        let control_slot = self.control_tx.clone();
+        let chat_outbox = self.chat_outbox.clone();
         tokio::spawn(async move {
-            while let Some(audio_data) = rx.recv().await {
-                for peer in &peers {
-                    let mut packet = BytesMut::with_capacity(audio_data.len() + 12);
-                    packet.put_u32(0);
-                    packet.put_u64(std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64);
-                    packet.put_slice(&audio_data);
-                    if let Err(e) = socket.send_to(&packet, peer).await {
-                        eprintln!("Error sending audio to peer {}: {}", peer, e);
+            let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+            let mut retransmit = tokio::time::interval(RETRANSMIT_AFTER / 2);
+            let mut last_broadcast = Instant::now();
+            let mut last_key: Option<Instant> = None;
+            loop {
+                let (flags, audio_data, control_target) = tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    data = rx.recv() => match data {
+                        Some(data) => (0, data, None),
+                        None => break,
+                    },
+                    _ = keepalive.tick() => {
+                        if last_broadcast.elapsed() < KEEPALIVE_INTERVAL {
+                            continue;
+                        }
+                        (FLAG_KEEPALIVE, Vec::new(), None)
+                    }
+                    Some((peer, message)) = control_rx.recv() => (FLAG_CONTROL, message, Some(peer)),
+                    _ = retransmit.tick() => {
+                        for packet in chat_outbox.lock().due(Instant::now()) {
+                            let _ = control_tx.send(packet);
+                        }
+                        continue;
                     }
+                };
+                let mut flags = if recording.load(std::sync::atomic::Ordering::Relaxed) { flags | FLAG_RECORDING } else { flags };
+                let whisper = if flags & (FLAG_KEEPALIVE | FLAG_CONTROL) == 0 { whisper_targets.lock().clone() } else { None };
+                let targets: Vec<SocketAddr> = match (control_target, &whisper) {
+                    (Some(peer), _) => vec![peer],
+                    (None, Some(whisper)) => {
+                        flags |= FLAG_WHISPER;
+                        peers.lock().iter().filter(|peer| whisper.contains(peer)).copied().collect()
+                    }
+                    (None, None) => peers.lock().clone(),
+                };
+                if outbound.follow_room_key() || outbound.muted_changed() {
+                    // A mixing server needs the new key before it can mix us,
+                    // and our mutes as soon as they change.
+                    last_key = None;
+                }
+                outbound.send(&targets, sequence, flags, &audio_data).await;
+                sequence = sequence.wrapping_add(1);
+                // After a packet, so a mixing server already knows us as a
+                // member of the room when the key arrives.
+                if last_key.is_none_or(|last| last.elapsed() >= MIX_KEY_INTERVAL) {
+                    outbound.send_key().await;
+                    last_key = Some(Instant::now());
+                }
+                // A forwarding server may hold our audio back from the room
+                // while others are louder, so there audio doesn't count.
+                if whisper.is_none() && control_target.is_none() && outbound.sfu.borrow().is_none() {
+                    last_broadcast = Instant::now();
                 }
             }
+            {
+                let mut control_slot = control_slot.lock();
+                if control_slot.as_ref().is_some_and(|slot| slot.same_channel(&control_tx)) {
+                    *control_slot = None;
+                }
+            }
+            let targets = peers.lock().clone();
+            outbound.send(&targets, sequence, FLAG_BYE, &[]).await;
         });
+        Ok(())
     }
 
-    pub async fn handle_incoming(&mut self, processor: Arc<Mutex<AudioProcessor>>) {
-        let socket = self.turn_socket.clone();
+    /// Receives packets and feeds them to `processor` until `cancel` fires.
+    /// Packets from addresses outside the room, from muted members, or that
+    /// fail authentication under the room key are counted and dropped.
+    /// Peers that stop sending are reported as reconnecting, then removed.
+    pub async fn handle_incoming(&mut self, processor: AudioProcessor, cancel: CancellationToken) -> Result<(), String> {
+        let mut room_key = self.room_key.subscribe();
+        let mut opener = PacketOpener::new(room_key.borrow_and_update().as_ref().ok_or(NO_ROOM_KEY)?);
+        let mut socket_rx = self.turn_socket.subscribe();
+        let link = self.link.clone();
         let audio_tx = self.audio_tx.clone();
         let mut audio_rx = self.audio_tx.subscribe();
-        let jitter_buffers = Arc::new(Mutex::new(self.jitter_buffers.clone()));
-        let quality_monitors = Arc::new(Mutex::new(self.quality_monitors.clone()));
+        let jitter_buffers = self.jitter_buffers.clone();
+        let quality_monitors = self.quality_monitors.clone();
         let stats_tx = self.stats_tx.clone();
+        // Last-seen times from an earlier session would time everyone out.
+        *self.presence.lock() = PresenceTracker::default();
+        let presence = self.presence.clone();
+        let presence_tx = self.presence_tx.clone();
+        let recording_tx = self.recording_tx.clone();
+        let whisper_tx = self.whisper_tx.clone();
+        let peers = self.peers.clone();
+        let muted_peers = self.muted_peers.clone();
+        let dropped = self.dropped.clone();
+        let sfu = self.sfu.subscribe();
+        let chat_inbox = self.chat_inbox.clone();
+        let chat_outbox = self.chat_outbox.clone();
+        let chat_tx = self.chat_tx.clone();
+        let control_tx = self.control_tx.clone();
+        let moved_tx = self.moved_tx.clone();
+        let peer_tables = self.peer_tables();
 
         // Task to handle incoming packets.
         let jb_clone = jitter_buffers.clone();
         let qm_clone = quality_monitors.clone();
+        let recv_cancel = cancel.clone();
         tokio::spawn(async move {
             let mut buffer = vec![0u8; 2048];
-            println!("Started listening for incoming audio packets");
+            let mut recording_peers = HashSet::new();
+            // When each whispering peer last whispered to us.
+            let mut whispering_peers: HashMap<SocketAddr, Instant> = HashMap::new();
+            eprintln!("Started listening for incoming audio packets");
             loop {
-                match socket.recv_from(&mut buffer).await {
-                    Ok((size, addr)) => {
-                        if size < 4 {
-                            println!("Received packet too small: {} bytes from {}", size, addr);
+                let socket = socket_rx.borrow_and_update().clone();
+                let received = tokio::select! {
+                    _ = recv_cancel.cancelled() => break,
+                    // Reconnected; listen on the new socket.
+                    _ = socket_rx.changed() => continue,
+                    received = socket.recv_from(&mut buffer) => received,
+                };
+                match received {
+                    Ok((size, source)) => {
+                        // Through a forwarding server, the member who sent
+                        // the packet is named in the relay framing.
+                        let server = sfu.borrow().as_ref().map(|route| route.server);
+                        let (relay_flags, addr, datagram) = match server {
+                            Some(server) if source == server => match parse_relay_frame(&buffer[..size]) {
+                                // The server's mix of the room is keyed on the server.
+                                Some((flags, _, packet)) if flags & RELAY_MIXED != 0 => (flags, source, packet),
+                                Some(relayed) => relayed,
+                                None => {
+                                    dropped.malformed.fetch_add(1, Ordering::Relaxed);
+                                    continue;
+                                }
+                            },
+                            _ => (0, source, &buffer[..size]),
+                        };
+                        let mixed = relay_flags & RELAY_MIXED != 0;
+                        // Members who left with the old key can't follow the
+                        // room past a new one.
+                        if room_key.has_changed().unwrap_or(false) {
+                            if let Some(key) = room_key.borrow_and_update().as_ref() {
+                                opener = PacketOpener::new(key);
+                            }
+                        }
+                        // Checked before decrypting so strangers cost us
+                        // nothing. A member who reconnected announces its
+                        // new address in the session it had at the old one.
+                        let mut moved_from = None;
+                        if !mixed && !peers.lock().contains(&addr) {
+                            let owner = PacketHeader::parse(datagram)
+                                .filter(|(header, _)| header.is_control())
+                                .and_then(|_| opener.session_owner(datagram))
+                                .filter(|owner| peers.lock().contains(owner));
+                            match owner {
+                                Some(owner) => moved_from = Some(owner),
+                                None => {
+                                    dropped.unknown_source.fetch_add(1, Ordering::Relaxed);
+                                    continue;
+                                }
+                            }
+                        }
+                        let (header, payload) = match opener.open(moved_from.unwrap_or(addr), datagram) {
+                            Ok(opened) => opened,
+                            Err(e) => {
+                                dropped.count(e);
+                                continue;
+                            }
+                        };
+                        if let Some(old) = moved_from {
+                            if ControlMessage::parse(&payload) != Some(ControlMessage::Moved { old }) {
+                                dropped.unknown_source.fetch_add(1, Ordering::Relaxed);
+                                continue;
+                            }
+                            eprintln!("Peer {} moved to {}", old, addr);
+                            opener.readdress(&old, addr);
+                            peer_tables.readdress(&old, addr);
+                            if recording_peers.remove(&old) {
+                                recording_peers.insert(addr);
+                            }
+                            if let Some(last) = whispering_peers.remove(&old) {
+                                whispering_peers.insert(addr, last);
+                            }
+                            let _ = moved_tx.send((old, addr));
+                        }
+                        let sequence = header.sequence;
+
+                        if mixed {
+                            // Everyone's audio at once; presence and the rest
+                            // come from members' own keepalives.
+                            let _ = audio_tx.send((payload, addr, header));
                             continue;
                         }
 
-                        let sequence = u32::from_be_bytes([
-                            buffer[0], buffer[1], buffer[2], buffer[3]
-                        ]);
-                        
-                        println!("Received {} bytes from {}, sequence: {}", size, addr, sequence);
+                        if header.is_bye() {
+                            presence.lock().forget(&addr);
+                            if recording_peers.remove(&addr) {
+                                let _ = recording_tx.send((addr, false));
+                            }
+                            if whispering_peers.remove(&addr).is_some() {
+                                let _ = whisper_tx.send((addr, false));
+                            }
+                            continue;
+                        }
+                        if let Some(state) = presence.lock().heard(addr, Instant::now()) {
+                            let _ = presence_tx.send((addr, state));
+                        }
 
                         {
                             let mut monitors = qm_clone.lock();
                             if let Some(monitor) = monitors.get_mut(&addr) {
+                                if relay_flags & RELAY_RESUMED != 0 {
+                                    monitor.resume_at(sequence);
+                                }
                                 monitor.update(sequence, Instant::now());
-                                let stats = monitor.get_stats();
-                                let _ = stats_tx.send((addr, stats.clone()));
-                                println!("Network stats for {}: latency={:?}, packet_loss={:.2}%, jitter={:?}", 
-                                    addr, stats.latency, stats.packet_loss * 100.0, stats.jitter);
+                                let _ = stats_tx.send((addr, monitor.get_stats()));
                             }
                         }
 
-                        let audio_data = &buffer[4..size];
-                        let _ = audio_tx.send((audio_data.to_vec(), addr));
+                        let changed = if header.is_recording() {
+                            recording_peers.insert(addr)
+                        } else {
+                            recording_peers.remove(&addr)
+                        };
+                        if changed {
+                            let _ = recording_tx.send((addr, header.is_recording()));
+                        }
+
+                        if header.is_control() {
+                            match ControlMessage::parse(&payload) {
+                                Some(ControlMessage::Chat { stream, sequence, more, text }) => {
+                                    let (ack, texts) = chat_inbox.lock().receive(addr, stream, sequence, more, text);
+                                    if ack {
+                                        if let Some(control_tx) = control_tx.lock().as_ref() {
+                                            let _ = control_tx.send((addr, ControlMessage::Ack { stream, sequence }.encode()));
+                                        }
+                                    }
+                                    for text in texts {
+                                        let _ = chat_tx.send((addr, text));
+                                    }
+                                }
+                                Some(ControlMessage::Ack { stream, sequence }) => chat_outbox.lock().acked(addr, stream, sequence),
+                                // Taken care of above; the rest are repeats.
+                                Some(ControlMessage::Moved { .. }) => {}
+                                None => {
+                                    dropped.malformed.fetch_add(1, Ordering::Relaxed);
+                                }
+                            }
+                            continue;
+                        }
+
+                        if header.is_keepalive() {
+                            // Keepalives go to everyone, so one arriving well
+                            // after the last whisper means the whisper is over.
+                            if whispering_peers.get(&addr).is_some_and(|last| last.elapsed() >= WHISPER_IDLE) {
+                                whispering_peers.remove(&addr);
+                                let _ = whisper_tx.send((addr, false));
+                            }
+                            continue;
+                        }
+                        // Checked after opening so silenced members still count as present.
+                        if muted_peers.lock().contains(&addr) {
+                            dropped.muted_source.fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        }
+                        if header.is_whisper() {
+                            if whispering_peers.insert(addr, Instant::now()).is_none() {
+                                let _ = whisper_tx.send((addr, true));
+                            }
+                        } else if whispering_peers.remove(&addr).is_some() {
+                            let _ = whisper_tx.send((addr, false));
+                        }
+                        let _ = audio_tx.send((payload, addr, header));
                     }
                     Err(e) => {
-                        println!("Error receiving audio packet: {}", e);
+                        eprintln!("Error receiving audio packet: {}", e);
+                        if is_link_failure(&e) {
+                            link.fail(e.to_string());
+                            // This socket is dead; wait for its replacement.
+                            tokio::select! {
+                                _ = recv_cancel.cancelled() => break,
+                                changed = socket_rx.changed() => if changed.is_err() { break },
+                            }
+                        }
                     }
                 }
             }
         });
 
-        // Task to process audio data.
+        // Task to time out peers that went quiet.
+        let sweep_cancel = cancel.clone();
+        let peer_tables = self.peer_tables();
+        let presence = self.presence.clone();
+        let presence_tx = self.presence_tx.clone();
         tokio::spawn(async move {
-            while let Ok((audio_data, _addr)) = audio_rx.recv().await {
-                let processor = processor.lock();
-                if let Err(e) = processor.process_incoming(&audio_data) {
-                    eprintln!("Error processing audio: {}", e);
+            let mut sweep = tokio::time::interval(PRESENCE_SWEEP_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = sweep_cancel.cancelled() => break,
+                    _ = sweep.tick() => {}
+                }
+                let changes = presence.lock().sweep(Instant::now());
+                for (addr, state) in changes {
+                    if state == PeerState::Disconnected {
+                        peer_tables.remove(&addr);
+                    }
+                    let _ = presence_tx.send((addr, state));
                 }
             }
         });
+
+        // Task to process audio data.
+        tokio::spawn(async move {
+            while let Ok((audio_data, addr, header)) = tokio::select! {
+                _ = cancel.cancelled() => return,
+                received = audio_rx.recv() => received,
+            } {
+                processor.process_incoming(addr, &audio_data, header.is_stereo(), header.is_whisper());
+            }
+        });
+        Ok(())
     }
 
     pub fn get_local_addr(&self) -> Result<SocketAddr, Box<dyn std::error::Error>> {
-        Ok(self.turn_socket.local_addr()?)
+        Ok(self.turn_socket.borrow().local_addr()?)
     }
 
     pub fn new_sync() -> Result<Self, Box<dyn std::error::Error>> {
@@ -431,11 +899,324 @@ impl AudioNetwork {
         })
     }
 
+    /// Peers starting or stopping a recording, as seen on their packets.
+    pub fn subscribe_to_recording(&self) -> broadcast::Receiver<(SocketAddr, bool)> {
+        self.recording_tx.subscribe()
+    }
+
+    /// Rebuilds the socket and its TURN allocation whenever sending or
+    /// receiving fails or the route to the TURN server moves to another
+    /// local address, until `cancel` fires. Attempts back off exponentially;
+    /// streaming resumes on the new socket by itself.
+    pub fn supervise(&self, cancel: CancellationToken) -> Result<(), String> {
+        let server: SocketAddr = self
+            .turn_config
+            .url
+            .trim_start_matches("turn:")
+            .parse()
+            .map_err(|e| format!("Invalid TURN server address: {}", e))?;
+        let turn_socket = self.turn_socket.clone();
+        let bind_addr = self.bind_addr.clone();
+        let turn_config = self.turn_config.clone();
+        let link = self.link.clone();
+        let link_tx = self.link_tx.clone();
+        let peers = self.peers.clone();
+        let control_tx = self.control_tx.clone();
+        link.clear();
+        tokio::spawn(async move {
+            let mut route = route_to(server).await.ok();
+            let mut route_check = tokio::time::interval(ROUTE_CHECK_INTERVAL);
+            loop {
+                let mut reason = tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    error = link.failed() => error,
+                    _ = route_check.tick() => {
+                        let current = route_to(server).await.ok();
+                        if current == route {
+                            continue;
+                        }
+                        match current {
+                            Some(ip) => format!("Local address changed to {}", ip),
+                            None => "No route to the TURN server".to_string(),
+                        }
+                    }
+                };
+
+                let mut backoff = Backoff::default();
+                let mut attempt = 1;
+                loop {
+                    eprintln!("Reconnecting (attempt {}): {}", attempt, reason);
+                    let _ = link_tx.send(LinkEvent::Reconnecting { attempt, reason: reason.clone() });
+                    let bound = tokio::select! {
+                        _ = cancel.cancelled() => return,
+                        bound = tokio::time::timeout(ATTEMPT_TIMEOUT, Self::bind(&bind_addr, &turn_config)) => match bound {
+                            Ok(bound) => bound.map_err(|e| e.to_string()),
+                            Err(_) => Err("Timed out waiting for the TURN server".to_string()),
+                        },
+                    };
+                    let error = match bound {
+                        Ok((_, socket)) => match socket.local_addr() {
+                            Ok(new_addr) => {
+                                let old_addr = turn_socket.borrow().local_addr().unwrap_or(new_addr);
+                                turn_socket.send_replace(Arc::new(socket));
+                                // Failures of the old socket are moot now.
+                                link.clear();
+                                route = route_to(server).await.ok();
+                                let _ = link_tx.send(LinkEvent::Reconnected { old_addr, new_addr });
+                                if old_addr != new_addr {
+                                    announce_move(peers.clone(), control_tx.clone(), old_addr, cancel.clone());
+                                }
+                                break;
+                            }
+                            Err(e) => e.to_string(),
+                        },
+                        Err(e) => e,
+                    };
+                    reason = error;
+                    tokio::select! {
+                        _ = cancel.cancelled() => return,
+                        _ = tokio::time::sleep(backoff.next_delay()) => {}
+                    }
+                    attempt += 1;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Rebuilds the socket now, as if the link had failed. Needs
+    /// `supervise` running.
+    pub fn reconnect(&self) {
+        self.link.fail("Reconnect requested".to_string());
+    }
+
+    /// Reconnect attempts and their outcome.
+    pub fn subscribe_to_link(&self) -> broadcast::Receiver<LinkEvent> {
+        self.link_tx.subscribe()
+    }
+
+    /// Peers that reconnected from a new address, as (old, new), once
+    /// everything kept for them has moved over.
+    pub fn subscribe_to_moves(&self) -> broadcast::Receiver<(SocketAddr, SocketAddr)> {
+        self.moved_tx.subscribe()
+    }
+
+    /// Peers becoming reconnecting, connected again, or disconnected. A
+    /// disconnected peer has already been removed.
+    pub fn subscribe_to_presence(&self) -> broadcast::Receiver<(SocketAddr, PeerState)> {
+        self.presence_tx.subscribe()
+    }
+
+    pub fn peer_presence(&self) -> Vec<PeerPresence> {
+        self.presence.lock().snapshot(Instant::now())
+    }
+
+    /// Sends our audio only to `targets` (members of the room) until set
+    /// back to `None`. Whispered packets are flagged so receivers can tell.
+    pub fn set_whisper_targets(&self, targets: Option<Vec<SocketAddr>>) {
+        *self.whisper_targets.lock() = targets;
+    }
+
+    /// Peers starting or stopping a whisper to us.
+    pub fn subscribe_to_whispers(&self) -> broadcast::Receiver<(SocketAddr, bool)> {
+        self.whisper_tx.subscribe()
+    }
+
+    pub fn set_recording(&self, recording: bool) {
+        self.recording.store(recording, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn packet_flags(&self) -> u8 {
+        if self.recording.load(std::sync::atomic::Ordering::Relaxed) { FLAG_RECORDING } else { 0 }
+    }
+
+    pub fn dropped_packets(&self) -> DroppedPackets {
+        self.dropped.snapshot()
+    }
+
     pub fn subscribe_to_stats(&self) -> broadcast::Receiver<(SocketAddr, NetworkStats)> {
         self.stats_tx.subscribe()
     }
 }
 
+/// Handles on everything kept per peer, so a peer is forgotten the same
+/// way whether it left the room or timed out.
+struct PeerTables {
+    peers: Arc<Mutex<Vec<SocketAddr>>>,
+    muted_peers: Arc<Mutex<HashSet<SocketAddr>>>,
+    jitter_buffers: Arc<Mutex<HashMap<SocketAddr, JitterBuffer>>>,
+    quality_monitors: Arc<Mutex<HashMap<SocketAddr, QualityMonitor>>>,
+    presence: Arc<Mutex<PresenceTracker>>,
+    chat_outbox: Arc<Mutex<ChatOutbox>>,
+    chat_inbox: Arc<Mutex<ChatInbox>>,
+}
+
+impl PeerTables {
+    fn remove(&self, addr: &SocketAddr) {
+        self.peers.lock().retain(|peer| peer != addr);
+        self.muted_peers.lock().remove(addr);
+        self.jitter_buffers.lock().remove(addr);
+        self.quality_monitors.lock().remove(addr);
+        self.presence.lock().forget(addr);
+        self.chat_outbox.lock().forget(addr);
+        self.chat_inbox.lock().forget(addr);
+    }
+
+    /// Carries everything kept for a peer over to its new address.
+    fn readdress(&self, old: &SocketAddr, new: SocketAddr) {
+        for peer in self.peers.lock().iter_mut().filter(|peer| *peer == old) {
+            *peer = new;
+        }
+        if self.muted_peers.lock().remove(old) {
+            self.muted_peers.lock().insert(new);
+        }
+        let buffer = self.jitter_buffers.lock().remove(old);
+        if let Some(buffer) = buffer {
+            self.jitter_buffers.lock().insert(new, buffer);
+        }
+        let monitor = self.quality_monitors.lock().remove(old);
+        if let Some(monitor) = monitor {
+            self.quality_monitors.lock().insert(new, monitor);
+        }
+        self.presence.lock().readdress(old, new);
+        self.chat_outbox.lock().readdress(old, new);
+        self.chat_inbox.lock().readdress(old, new);
+    }
+}
+
+/// Tells the room we were at `old`, from our new socket. The streaming task
+/// seals it in the session peers already know us by, which is what lets
+/// them trust the new address.
+fn announce_move(
+    peers: Arc<Mutex<Vec<SocketAddr>>>,
+    control_tx: Arc<Mutex<Option<ControlSender>>>,
+    old: SocketAddr,
+    cancel: CancellationToken,
+) {
+    tokio::spawn(async move {
+        let message = ControlMessage::Moved { old }.encode();
+        for _ in 0..MOVE_ANNOUNCEMENTS {
+            {
+                let peers = peers.lock().clone();
+                if let Some(control_tx) = control_tx.lock().as_ref() {
+                    for peer in peers {
+                        let _ = control_tx.send((peer, message.clone()));
+                    }
+                }
+            }
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = tokio::time::sleep(MOVE_ANNOUNCE_INTERVAL) => {}
+            }
+        }
+    });
+}
+
+/// What the streaming task sends with.
+struct Outbound {
+    socket: watch::Receiver<Arc<UdpSocket>>,
+    link: Arc<LinkMonitor>,
+    room_key: watch::Receiver<Option<RoomKey>>,
+    sealer: PacketSealer,
+    sfu: watch::Receiver<Option<SfuRoute>>,
+    muted_peers: Arc<Mutex<HashSet<SocketAddr>>>,
+    // The mutes a mixing server was last told about.
+    muted_sent: HashSet<SocketAddr>,
+}
+
+impl Outbound {
+    /// Seals with the room key set since the last packet, if there is one.
+    /// Returns whether the key changed.
+    fn follow_room_key(&mut self) -> bool {
+        if !self.room_key.has_changed().unwrap_or(false) {
+            return false;
+        }
+        match self.room_key.borrow_and_update().as_ref() {
+            Some(key) => {
+                self.sealer = PacketSealer::new(key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn muted_changed(&self) -> bool {
+        *self.muted_peers.lock() != self.muted_sent
+    }
+
+    /// Gives a mixing server the room key and the members we muted, if we
+    /// want a mix.
+    async fn send_key(&mut self) {
+        let Some(route) = self.sfu.borrow().clone() else {
+            return;
+        };
+        let socket = self.socket.borrow().clone();
+        let Ok(from) = socket.local_addr() else {
+            return;
+        };
+        if let Some(frame) = key_frame(&route, from) {
+            if let Err(e) = socket.send_to(&frame, route.server).await {
+                eprintln!("Error sending the room key to the mixing server: {}", e);
+            }
+        }
+        self.muted_sent = self.muted_peers.lock().clone();
+        let muted: Vec<SocketAddr> = self.muted_sent.iter().copied().collect();
+        if let Some(frame) = muted_frame(&route, from, &muted) {
+            if let Err(e) = socket.send_to(&frame, route.server).await {
+                eprintln!("Error sending our mutes to the mixing server: {}", e);
+            }
+        }
+    }
+
+    /// Seals one packet and sends it to each of `targets`, or once to the
+    /// forwarding server, which delivers it to them.
+    async fn send(&self, targets: &[SocketAddr], sequence: u32, flags: u8, payload: &[u8]) {
+        let packet = match self.sealer.seal(build_audio_packet(sequence, flags, payload)) {
+            Ok(packet) => packet,
+            Err(e) => {
+                eprintln!("Error sealing audio packet: {}", e);
+                return;
+            }
+        };
+        let socket = self.socket.borrow().clone();
+        let route = self.sfu.borrow().clone();
+        let result = match &route {
+            Some(route) => {
+                let aimed = flags & (FLAG_WHISPER | FLAG_CONTROL) != 0;
+                if aimed && targets.is_empty() {
+                    // No targets on the server means everyone.
+                    return;
+                }
+                match socket.local_addr() {
+                    Ok(from) => {
+                        let frame = client_frame(route, from, if aimed { targets } else { &[] }, &packet);
+                        socket.send_to(&frame, route.server).await.map(|_| ())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            None => {
+                for peer in targets {
+                    if let Err(e) = socket.send_to(&packet, peer).await {
+                        eprintln!("Error sending audio to peer {}: {}", peer, e);
+                        if is_link_failure(&e) {
+                            self.link.fail(e.to_string());
+                            return;
+                        }
+                    }
+                }
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("Error sending audio to the forwarding server: {}", e);
+            if is_link_failure(&e) {
+                self.link.fail(e.to_string());
+            }
+        }
+    }
+}
+
 // Helper functions for TURN authentication.
 fn pad_to_multiple_of_4(data: &mut Vec<u8>) {
     while data.len() % 4 != 0 {
@@ -467,7 +1248,7 @@ fn process_turn_response(response: &[u8]) -> Result<Option<SocketAddr>, Box<dyn
     }
 
     let message_type = u16::from_be_bytes([response[0], response[1]]);
-    println!("Processing response with type: 0x{:04x}", message_type);
+    eprintln!("Processing response with type: 0x{:04x}", message_type);
 
     // Check if it's an error response
     if message_type & 0x0110 == 0x0110 {
@@ -476,24 +1257,24 @@ fn process_turn_response(response: &[u8]) -> Result<Option<SocketAddr>, Box<dyn
         while pos + 4 <= response.len() {
             let attr_type = u16::from_be_bytes([response[pos], response[pos + 1]]);
             let attr_len = u16::from_be_bytes([response[pos + 2], response[pos + 3]]) as usize;
-            println!("Found attribute type: 0x{:04x}, length: {}", attr_type, attr_len);
+            eprintln!("Found attribute type: 0x{:04x}, length: {}", attr_type, attr_len);
 
             if attr_type == 0x0009 && pos + 8 <= response.len() { // ERROR-CODE
                 let error_class = response[pos + 6] as u16;
                 let error_number = response[pos + 7] as u16;
                 let error_code = error_class * 100 + error_number;
                 
-                println!("Error bytes: {:02x?}", &response[pos..pos + 8]);
-                println!("Error class: {}, number: {}", error_class, error_number);
-                println!("Received error response. Error code: {}", error_code);
+                eprintln!("Error bytes: {:02x?}", &response[pos..pos + 8]);
+                eprintln!("Error class: {}, number: {}", error_class, error_number);
+                eprintln!("Received error response. Error code: {}", error_code);
                 match error_code {
-                    401 => println!("Unauthorized: Need to include REALM and NONCE attributes"),
-                    431 => println!("Integrity Check Failure: Authentication failed"),
-                    437 => println!("Allocation Mismatch: Request conflicts with existing allocation"),
-                    441 => println!("Wrong Credentials"),
-                    486 => println!("Allocation Quota Reached"),
-                    508 => println!("Insufficient Port Capacity"),
-                    _ => println!("Unknown error code: {}", error_code),
+                    401 => eprintln!("Unauthorized: Need to include REALM and NONCE attributes"),
+                    431 => eprintln!("Integrity Check Failure: Authentication failed"),
+                    437 => eprintln!("Allocation Mismatch: Request conflicts with existing allocation"),
+                    441 => eprintln!("Wrong Credentials"),
+                    486 => eprintln!("Allocation Quota Reached"),
+                    508 => eprintln!("Insufficient Port Capacity"),
+                    _ => eprintln!("Unknown error code: {}", error_code),
                 }
                 return Ok(None);
             }
@@ -502,12 +1283,12 @@ fn process_turn_response(response: &[u8]) -> Result<Option<SocketAddr>, Box<dyn
                 pos += 4 - (attr_len % 4);
             }
         }
-        println!("Error response but no ERROR-CODE attribute found");
+        eprintln!("Error response but no ERROR-CODE attribute found");
         return Ok(None);
     }
 
     if message_type != 0x0103 { // Not an Allocation Success
-        println!("Unexpected message type: 0x{:04x}", message_type);
+        eprintln!("Unexpected message type: 0x{:04x}", message_type);
         return Ok(None);
     }
 
@@ -515,7 +1296,7 @@ fn process_turn_response(response: &[u8]) -> Result<Option<SocketAddr>, Box<dyn
     while pos + 4 <= response.len() {
         let attr_type = u16::from_be_bytes([response[pos], response[pos + 1]]);
         let attr_len = u16::from_be_bytes([response[pos + 2], response[pos + 3]]) as usize;
-        println!("Found attribute type: 0x{:04x}, length: {}", attr_type, attr_len);
+        eprintln!("Found attribute type: 0x{:04x}, length: {}", attr_type, attr_len);
 
         if attr_type == XOR_MAPPED_ADDRESS {
             let family = response[pos + 5];
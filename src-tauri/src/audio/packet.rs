@@ -0,0 +1,93 @@
+// src-tauri/src/audio/packet.rs
+
+use bytes::{BufMut, BytesMut};
+
+// sequence (4) + timestamp in ms (8) + flags (1)
+pub const HEADER_LEN: usize = 13;
+
+pub const FLAG_STEREO: u8 = 0x01;
+// The sender is recording the session.
+pub const FLAG_RECORDING: u8 = 0x02;
+// The payload is sealed with the room key; see `crypto`.
+pub const FLAG_ENCRYPTED: u8 = 0x04;
+// No audio: the sender is still there; see `presence`.
+pub const FLAG_KEEPALIVE: u8 = 0x08;
+// No audio: the sender stopped streaming on purpose.
+pub const FLAG_BYE: u8 = 0x10;
+// Sent only to some of the room; see `AudioNetwork::set_whisper_targets`.
+pub const FLAG_WHISPER: u8 = 0x20;
+// No audio: a control message, such as chat; see `control`.
+pub const FLAG_CONTROL: u8 = 0x40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketHeader {
+    pub sequence: u32,
+    pub timestamp: u64,
+    pub flags: u8,
+}
+
+impl PacketHeader {
+    pub fn is_stereo(&self) -> bool {
+        self.flags & FLAG_STEREO != 0
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.flags & FLAG_RECORDING != 0
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+
+    pub fn is_keepalive(&self) -> bool {
+        self.flags & FLAG_KEEPALIVE != 0
+    }
+
+    pub fn is_bye(&self) -> bool {
+        self.flags & FLAG_BYE != 0
+    }
+
+    pub fn is_whisper(&self) -> bool {
+        self.flags & FLAG_WHISPER != 0
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.flags & FLAG_CONTROL != 0
+    }
+
+    pub fn write(&self, packet: &mut BytesMut) {
+        packet.put_u32(self.sequence);
+        packet.put_u64(self.timestamp);
+        packet.put_u8(self.flags);
+    }
+
+    /// Splits a datagram into its header and Opus payload.
+    pub fn parse(data: &[u8]) -> Option<(PacketHeader, &[u8])> {
+        if data.len() < HEADER_LEN {
+            return None;
+        }
+        let sequence = u32::from_be_bytes(data[0..4].try_into().ok()?);
+        let timestamp = u64::from_be_bytes(data[4..12].try_into().ok()?);
+        let flags = data[12];
+        Some((PacketHeader { sequence, timestamp, flags }, &data[HEADER_LEN..]))
+    }
+}
+
+/// Builds a complete audio datagram. The stereo flag is taken from the Opus
+/// TOC byte so the sender doesn't have to track the encoder layout; `flags`
+/// carries any others.
+pub fn build_audio_packet(sequence: u32, flags: u8, payload: &[u8]) -> BytesMut {
+    let stereo = matches!(opus::packet::get_nb_channels(payload), Ok(opus::Channels::Stereo));
+    let header = PacketHeader {
+        sequence,
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64,
+        flags: if stereo { flags | FLAG_STEREO } else { flags },
+    };
+    let mut packet = BytesMut::with_capacity(HEADER_LEN + payload.len());
+    header.write(&mut packet);
+    packet.put_slice(payload);
+    packet
+}
@@ -0,0 +1,114 @@
+// src-tauri/src/audio/presence.rs
+//
+// Every streaming peer sends at least one packet a second: audio while it
+// has some, a keepalive otherwise. Peers that go quiet are first shown as
+// reconnecting, then dropped as disconnected. A peer that stops streaming
+// on purpose says goodbye and is no longer watched.
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// How often a peer with nothing else to send sends a keepalive.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+/// Silence after which a peer is shown as reconnecting.
+pub const RECONNECTING_AFTER: Duration = Duration::from_secs(3);
+/// Silence after which a peer is treated as gone.
+pub const DISCONNECTED_AFTER: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerState {
+    Connected,
+    /// Missed its keepalives; may still come back.
+    Reconnecting,
+    Disconnected,
+}
+
+/// One peer's presence, as reported to the UI.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerPresence {
+    pub addr: SocketAddr,
+    pub state: PeerState,
+    /// Milliseconds since the peer's last authenticated packet.
+    pub last_seen_ms: u64,
+}
+
+struct Entry {
+    last_seen: Instant,
+    state: PeerState,
+}
+
+/// Last-seen times of the peers we are hearing from. Only peers that have
+/// sent something are watched, so members who haven't started streaming
+/// yet are never timed out.
+#[derive(Default)]
+pub struct PresenceTracker {
+    peers: HashMap<SocketAddr, Entry>,
+}
+
+impl PresenceTracker {
+    /// Records an authenticated packet from `addr`. Returns the new state
+    /// if the peer just (re)appeared.
+    pub fn heard(&mut self, addr: SocketAddr, now: Instant) -> Option<PeerState> {
+        match self.peers.get_mut(&addr) {
+            Some(entry) => {
+                entry.last_seen = now;
+                if entry.state == PeerState::Connected {
+                    return None;
+                }
+                entry.state = PeerState::Connected;
+            }
+            None => {
+                self.peers.insert(addr, Entry { last_seen: now, state: PeerState::Connected });
+            }
+        }
+        Some(PeerState::Connected)
+    }
+
+    /// Stops watching `addr`, e.g. after it said goodbye or was removed.
+    pub fn forget(&mut self, addr: &SocketAddr) {
+        self.peers.remove(addr);
+    }
+
+    /// Keeps watching a peer that reconnected from `new`.
+    pub fn readdress(&mut self, old: &SocketAddr, new: SocketAddr) {
+        if let Some(entry) = self.peers.remove(old) {
+            self.peers.insert(new, entry);
+        }
+    }
+
+    /// Moves peers that have gone quiet to their next state and returns the
+    /// changes. Disconnected peers are forgotten.
+    pub fn sweep(&mut self, now: Instant) -> Vec<(SocketAddr, PeerState)> {
+        let mut changes = Vec::new();
+        for (addr, entry) in self.peers.iter_mut() {
+            let silent_for = now.saturating_duration_since(entry.last_seen);
+            let state = if silent_for >= DISCONNECTED_AFTER {
+                PeerState::Disconnected
+            } else if silent_for >= RECONNECTING_AFTER {
+                PeerState::Reconnecting
+            } else {
+                PeerState::Connected
+            };
+            if state != entry.state {
+                entry.state = state;
+                changes.push((*addr, state));
+            }
+        }
+        self.peers.retain(|_, entry| entry.state != PeerState::Disconnected);
+        changes
+    }
+
+    pub fn snapshot(&self, now: Instant) -> Vec<PeerPresence> {
+        self.peers
+            .iter()
+            .map(|(addr, entry)| PeerPresence {
+                addr: *addr,
+                state: entry.state,
+                last_seen_ms: now.saturating_duration_since(entry.last_seen).as_millis() as u64,
+            })
+            .collect()
+    }
+}
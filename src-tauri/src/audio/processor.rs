@@ -1,188 +1,232 @@
 // src-tauri/src/audio/processor.rs
 
-use cpal::traits::{DeviceTrait, HostTrait};
-use opus::{Encoder, Decoder, Channels};
-use tokio::sync::mpsc;
-use ringbuf::{HeapRb, Producer};
+use super::codec::{AudioMode, EncoderSettings};
+use super::loopback::{Loopback, LoopbackSettings};
+use super::recorder::{Recorder, RecordingMetadata, RecordingSettings, TrackSource};
+use super::file_source::Clip;
+use super::soundboard::Soundboard;
+use super::engine::{spawn_engine, EngineCommand, EngineShared, Reply};
+use super::mixer::{MixerCommand, MixerHandle};
+use super::realtime::{CallbackStats, CallbackTimer, Worker};
+use super::spatial::{ListenerPose, Position, SpatialSettings};
+use super::virtual_device::VirtualDevices;
+use tokio::sync::{mpsc, oneshot};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::Mutex; // We use Tokio's Mutex for async safety.
 use atomic_float::AtomicF32; // From the atomic_float crate
+use super::dsp::{AgcSettings, DspControls, DspStageKind, DspStageSettings};
 
-// A simple wrapper for cpal::Stream to mark it Send + Sync.
-#[derive(Default)]
-struct StreamWrapper(Option<cpal::Stream>);
-unsafe impl Send for StreamWrapper {}
-unsafe impl Sync for StreamWrapper {}
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioCallbackStats {
+    pub input: CallbackStats,
+    pub output: CallbackStats,
+}
 
+/// Handle to the audio engine. The device streams live on the engine thread;
+/// this only holds a command channel and shared atomics, so clones are cheap
+/// and all control the same engine. The engine stops when the last clone is
+/// dropped.
+#[derive(Clone)]
 pub struct AudioProcessor {
-    encoder: Arc<Mutex<Encoder>>,
-    decoder: Arc<Mutex<Decoder>>,
-    input_stream: Arc<Mutex<StreamWrapper>>,
-    output_stream: Arc<Mutex<StreamWrapper>>,
-    sample_rate: u32,
-    channels: u16,
-    tx: mpsc::Sender<Vec<u8>>,
+    commands: std::sync::mpsc::Sender<EngineCommand>,
+    shared: EngineShared,
     output_volume: Arc<AtomicF32>,
-    is_muted: Arc<std::sync::atomic::AtomicBool>,
-    // Specify both generic parameters for the Producer.
-    pub output_producer: Option<Arc<Mutex<Producer<f32, Arc<HeapRb<f32>>>>>>,
-}
-
-// In our Clone implementation, we don’t clone the output_producer.
-impl Clone for AudioProcessor {
-    fn clone(&self) -> Self {
-        Self {
-            encoder: self.encoder.clone(),
-            decoder: self.decoder.clone(),
-            input_stream: Arc::new(Mutex::new(StreamWrapper(None))),
-            output_stream: Arc::new(Mutex::new(StreamWrapper(None))),
-            sample_rate: self.sample_rate,
-            channels: self.channels,
-            tx: self.tx.clone(),
-            output_volume: self.output_volume.clone(),
-            is_muted: self.is_muted.clone(),
-            output_producer: None,
-        }
-    }
+    _engine: Arc<Worker>,
 }
 
 impl AudioProcessor {
     pub fn new(tx: mpsc::Sender<Vec<u8>>) -> Result<Self, Box<dyn std::error::Error>> {
-        let encoder = Encoder::new(48000, Channels::Mono, opus::Application::Voip)?;
-        let decoder = Decoder::new(48000, Channels::Mono)?;
-        Ok(Self {
-            encoder: Arc::new(Mutex::new(encoder)),
-            decoder: Arc::new(Mutex::new(decoder)),
-            input_stream: Arc::new(Mutex::new(StreamWrapper(None))),
-            output_stream: Arc::new(Mutex::new(StreamWrapper(None))),
-            sample_rate: 48000,
-            channels: 1,
+        let output_volume = Arc::new(AtomicF32::new(1.0));
+        let shared = EngineShared {
             tx,
-            output_volume: Arc::new(AtomicF32::new(1.0)),
+            mixer: MixerHandle::spawn(output_volume.clone())?,
             is_muted: Arc::new(std::sync::atomic::AtomicBool::new(false)),
-            output_producer: None,
+            input_volume: Arc::new(AtomicF32::new(1.0)),
+            dsp: DspControls::default(),
+            loopback: Loopback::default(),
+            recorder: Recorder::default(),
+            soundboard: Soundboard::default(),
+            input_timer: Arc::new(CallbackTimer::default()),
+            output_timer: Arc::new(CallbackTimer::default()),
+        };
+        let (commands, rx) = std::sync::mpsc::channel();
+        let engine = spawn_engine(shared.clone(), rx)?;
+        Ok(Self {
+            commands,
+            shared,
+            output_volume,
+            _engine: Arc::new(engine),
         })
     }
 
-    pub async fn setup_output_stream(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .ok_or("No output device available")?;
-        let config = cpal::StreamConfig {
-            channels: self.channels,
-            sample_rate: cpal::SampleRate(self.sample_rate),
-            buffer_size: cpal::BufferSize::Fixed(480),
-        };
+    async fn request(
+        &self,
+        command: impl FnOnce(Reply) -> EngineCommand,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .map_err(|_| "Audio engine has stopped")?;
+        response.await.map_err(|_| "Audio engine has stopped")??;
+        Ok(())
+    }
+
+    pub async fn setup_output_stream(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.request(EngineCommand::StartOutput).await
+    }
+
+    /// Hands a received packet to the mixer thread. Never blocks; if the mixer
+    /// is backed up the packet is dropped like a late one. Whispers are
+    /// announced with a cue.
+    pub fn process_incoming(&self, from: SocketAddr, data: &[u8], stereo: bool, whisper: bool) {
+        self.shared.recorder.tap(TrackSource::Peer(from), data, stereo);
+        self.shared.mixer.send(MixerCommand::Packet {
+            from,
+            payload: data.to_vec(),
+            stereo,
+            whisper,
+        });
+    }
 
-        let ring_size = 4800; // e.g. 100ms of audio buffer
-        let (producer, mut consumer) = HeapRb::<f32>::new(ring_size).split();
-        let producer = Arc::new(Mutex::new(producer));
-        self.output_producer = Some(producer.clone());
-
-        let volume = self.output_volume.clone();
-        let is_muted = self.is_muted.clone();
-
-        let output_stream = device.build_output_stream(
-            &config,
-            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                for sample in data.iter_mut() {
-                    if is_muted.load(std::sync::atomic::Ordering::Relaxed) {
-                        *sample = 0.0;
-                    } else {
-                        *sample = consumer
-                            .pop()
-                            .unwrap_or(0.0)
-                            * volume.load(std::sync::atomic::Ordering::Relaxed);
-                    }
-                }
-            },
-            |err| eprintln!("Output error: {}", err),
-            None,
-        )?;
-        let output_stream_handle = output_stream;
-        *self.output_stream.lock().await = StreamWrapper(Some(output_stream_handle));
+    /// Playback gain for one peer, applied before the mix.
+    pub fn set_peer_volume(&self, addr: SocketAddr, volume: f32) {
+        self.shared.mixer.send(MixerCommand::SetPeerVolume { addr, volume: volume.clamp(0.0, 2.0) });
+    }
+
+    pub fn remove_peer(&self, addr: SocketAddr) {
+        self.shared.mixer.send(MixerCommand::RemovePeer(addr));
+    }
+
+    /// Places a peer in the game world, or takes them out of it with `None`.
+    /// Only heard while spatial audio is enabled.
+    pub fn set_peer_position(&self, addr: SocketAddr, position: Option<Position>) {
+        self.shared.mixer.send(MixerCommand::SetPeerPosition { addr, position });
+    }
+
+    pub fn set_listener(&self, pose: ListenerPose) {
+        self.shared.mixer.send(MixerCommand::SetListener(pose));
+    }
+
+    pub fn set_spatial_settings(&self, settings: SpatialSettings) {
+        self.shared.mixer.send(MixerCommand::SetSpatial(settings));
+    }
+
+    /// Plays our own encoded stream back locally, through `settings`'
+    /// simulated network. Runs alongside (or without) a call.
+    pub fn start_loopback(&self, settings: LoopbackSettings) -> Result<(), Box<dyn std::error::Error>> {
+        self.shared.loopback.start(settings, self.shared.mixer.clone())?;
         Ok(())
     }
 
-    pub fn process_incoming(&self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-        let mut pcm_data = vec![0f32; 480];
-        {
-            let mut decoder = self.decoder.blocking_lock();
-            // Pass the data slice directly instead of wrapping in Some(…)
-            decoder.decode_float(data, &mut pcm_data, false)?;
-        }
-        if let Some(producer) = &self.output_producer {
-            let mut prod = producer.blocking_lock();
-            for sample in pcm_data {
-                let _ = prod.push(sample);
-            }
-        }
+    pub fn stop_loopback(&self) {
+        self.shared.loopback.stop();
+    }
+
+    /// Starts writing our capture and every peer's stream to `directory`.
+    /// `track_names` labels per-participant files.
+    pub fn start_recording(
+        &self,
+        settings: RecordingSettings,
+        metadata: RecordingMetadata,
+        directory: PathBuf,
+        track_names: HashMap<TrackSource, String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.shared.recorder.start(settings, metadata, directory, track_names)?;
         Ok(())
     }
 
+    /// Finalizes the recording and returns its metadata, if one was running.
+    pub fn stop_recording(&self) -> Option<RecordingMetadata> {
+        self.shared.recorder.stop()
+    }
+
+    /// Mixes `clip` into our outgoing stream; peers hear it once capture
+    /// is running.
+    pub fn play_clip(&self, clip: Clip) -> uuid::Uuid {
+        self.shared.soundboard.play(clip)
+    }
+
+    pub fn stop_clip(&self, id: Option<uuid::Uuid>) {
+        self.shared.soundboard.stop(id);
+    }
+
+    pub fn set_soundboard_volume(&self, volume: f32) {
+        self.shared.soundboard.set_volume(volume);
+    }
+
+    pub fn set_soundboard_monitoring(&self, enabled: bool) {
+        self.shared.soundboard.set_monitoring(enabled);
+    }
+
+    pub fn callback_stats(&self) -> AudioCallbackStats {
+        AudioCallbackStats {
+            input: self.shared.input_timer.stats(),
+            output: self.shared.output_timer.stats(),
+        }
+    }
+
     pub fn set_output_volume(&self, volume: f32) {
         self.output_volume.store(volume, std::sync::atomic::Ordering::Relaxed);
     }
 
     pub fn set_muted(&self, muted: bool) {
-        self.is_muted.store(muted, std::sync::atomic::Ordering::Relaxed);
-    }
-
-    pub async fn start_capture(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let host = cpal::default_host();
-        let device = host
-            .default_input_device()
-            .ok_or("No input device available")?;
-        let config = cpal::StreamConfig {
-            channels: self.channels,
-            sample_rate: cpal::SampleRate(self.sample_rate),
-            buffer_size: cpal::BufferSize::Fixed(480),
-        };
-        let tx = self.tx.clone();
-        let encoder = self.encoder.clone();
-        let stream = device.build_input_stream(
-            &config,
-            move |data: &[f32], _: &_| {
-                let mut opus_data = vec![0u8; 1275]; // Maximum opus frame size.
-                let mut enc = encoder.blocking_lock();
-                if let Ok(size) = enc.encode_float(data, &mut opus_data) {
-                    let _ = tx.try_send(opus_data[..size].to_vec());
-                }
-            },
-            |err| eprintln!("Audio capture error: {}", err),
-            None,
-        )?;
-        let stream_handle = stream;
-        *self.input_stream.lock().await = StreamWrapper(Some(stream_handle));
-        Ok(())
+        self.shared.is_muted.store(muted, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn set_dsp_stage(&self, stage: DspStageKind, enabled: bool, strength: f32) {
+        self.shared.dsp.set_stage(stage, enabled, strength);
     }
 
-    pub async fn cleanup(&mut self) {
-        let mut stream = self.input_stream.lock().await;
-        *stream = StreamWrapper(None);
-        let mut stream = self.output_stream.lock().await;
-        *stream = StreamWrapper(None);
-        self.output_producer = None;
+    pub fn dsp_settings(&self) -> Vec<DspStageSettings> {
+        self.shared.dsp.settings()
     }
 
-    pub async fn set_input_device(&mut self, _device_id: &str) -> Result<(), Box<dyn std::error::Error>> {
-        // Drop the current stream first
-        {
-            let mut stream = self.input_stream.lock().await;
-            *stream = StreamWrapper(None);
-        } // stream lock is dropped here
-        // Now we can start capture
-        self.start_capture().await
+    pub fn set_agc_settings(&self, settings: AgcSettings) {
+        self.shared.dsp.set_agc(settings);
+    }
+
+    pub fn agc_settings(&self) -> AgcSettings {
+        self.shared.dsp.agc()
+    }
+
+    /// Captures from `devices.input` and plays into `devices.record` (or
+    /// nowhere) instead of the sound card. Call before starting the streams.
+    pub async fn use_virtual_devices(&self, devices: VirtualDevices) -> Result<(), Box<dyn std::error::Error>> {
+        self.request(|reply| EngineCommand::UseVirtualDevices(devices, reply)).await
+    }
+
+    pub async fn start_capture(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.request(EngineCommand::StartCapture).await
+    }
+
+    /// Switches to a new encoder profile. A running capture stream is rebuilt
+    /// so the new channel count and frame size take effect immediately.
+    pub async fn set_encoder_settings(&self, settings: EncoderSettings) -> Result<(), Box<dyn std::error::Error>> {
+        self.request(|reply| EngineCommand::SetEncoderSettings(settings, reply)).await
+    }
+
+    /// Music mode turns off the voice-only stages (echo cancellation, noise
+    /// suppression, AGC) so instruments pass through untouched.
+    pub fn set_audio_mode(&self, mode: AudioMode) {
+        self.shared.dsp.set_music_mode(mode == AudioMode::Music);
+    }
+
+    pub async fn cleanup(&self) {
+        let _ = self.request(EngineCommand::Stop).await;
+    }
+
+    /// Captures from the named device, or the system default for `None`.
+    /// A running capture stream moves to the new device immediately.
+    pub async fn set_input_device(&self, device: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+        self.request(|reply| EngineCommand::SetInputDevice(device, reply)).await
     }
 
     pub fn set_input_volume(&self, volume: f32) -> Result<(), Box<dyn std::error::Error>> {
         let vol = volume.clamp(0.0, 1.0);
-        self.output_volume.store(vol, std::sync::atomic::Ordering::Relaxed);
+        self.shared.input_volume.store(vol, std::sync::atomic::Ordering::Relaxed);
         Ok(())
     }
 }
-
-// Mark AudioProcessor as Send + Sync unsafely.
-unsafe impl Send for AudioProcessor {}
-unsafe impl Sync for AudioProcessor {}
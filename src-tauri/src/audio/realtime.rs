@@ -0,0 +1,151 @@
+// src-tauri/src/audio/realtime.rs
+//
+// Everything that runs inside a cpal callback lives here. Callbacks may only
+// touch the SPSC rings they own and atomics: no locks, no allocation, no
+// syscalls beyond reading the clock. Encoding, decoding and mixing happen on
+// the worker threads in `capture` and `mixer`.
+
+use serde::Serialize;
+use ringbuf::{HeapConsumer, HeapProducer};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::JoinHandle;
+use std::time::Instant;
+use super::dsp::EchoReference;
+
+/// Worst-case and average time spent inside an audio callback.
+#[derive(Default)]
+pub struct CallbackTimer {
+    callbacks: AtomicU64,
+    total_ns: AtomicU64,
+    worst_ns: AtomicU64,
+    overruns: AtomicU64,
+    // Samples the ring couldn't take (capture) or didn't have (playback).
+    dropped_samples: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CallbackStats {
+    pub callbacks: u64,
+    pub mean_us: f64,
+    pub worst_us: f64,
+    /// Callbacks that took longer than the audio they carried.
+    pub overruns: u64,
+    pub dropped_samples: u64,
+}
+
+impl CallbackTimer {
+    pub fn record(&self, started: Instant, budget_ns: u64, dropped: usize) {
+        let elapsed = started.elapsed().as_nanos() as u64;
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+        self.total_ns.fetch_add(elapsed, Ordering::Relaxed);
+        self.worst_ns.fetch_max(elapsed, Ordering::Relaxed);
+        if elapsed > budget_ns {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        if dropped > 0 {
+            self.dropped_samples.fetch_add(dropped as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> CallbackStats {
+        let callbacks = self.callbacks.load(Ordering::Relaxed);
+        let total_ns = self.total_ns.load(Ordering::Relaxed);
+        CallbackStats {
+            callbacks,
+            mean_us: if callbacks == 0 { 0.0 } else { total_ns as f64 / callbacks as f64 / 1000.0 },
+            worst_us: self.worst_ns.load(Ordering::Relaxed) as f64 / 1000.0,
+            overruns: self.overruns.load(Ordering::Relaxed),
+            dropped_samples: self.dropped_samples.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn budget_ns(samples: usize, channels: usize, sample_rate: u32) -> u64 {
+    (samples / channels.max(1)) as u64 * 1_000_000_000 / sample_rate as u64
+}
+
+/// Body of the cpal input callback: copies device samples into the capture ring.
+pub struct CaptureCallback {
+    producer: HeapProducer<f32>,
+    channels: usize,
+    sample_rate: u32,
+    timer: Arc<CallbackTimer>,
+}
+
+impl CaptureCallback {
+    pub fn new(producer: HeapProducer<f32>, channels: usize, sample_rate: u32, timer: Arc<CallbackTimer>) -> Self {
+        Self { producer, channels, sample_rate, timer }
+    }
+
+    pub fn process(&mut self, data: &[f32]) {
+        let started = Instant::now();
+        let pushed = self.producer.push_slice(data);
+        self.timer.record(started, budget_ns(data.len(), self.channels, self.sample_rate), data.len() - pushed);
+    }
+}
+
+/// Body of the cpal output callback: drains the playback ring filled by the
+/// mixer and publishes what was played as the echo canceller's reference.
+pub struct PlaybackCallback {
+    consumer: HeapConsumer<f32>,
+    channels: usize,
+    sample_rate: u32,
+    is_muted: Arc<AtomicBool>,
+    echo_reference: Arc<EchoReference>,
+    timer: Arc<CallbackTimer>,
+}
+
+impl PlaybackCallback {
+    pub fn new(
+        consumer: HeapConsumer<f32>,
+        channels: usize,
+        sample_rate: u32,
+        is_muted: Arc<AtomicBool>,
+        echo_reference: Arc<EchoReference>,
+        timer: Arc<CallbackTimer>,
+    ) -> Self {
+        Self { consumer, channels, sample_rate, is_muted, echo_reference, timer }
+    }
+
+    pub fn process(&mut self, data: &mut [f32]) {
+        let started = Instant::now();
+        let popped = self.consumer.pop_slice(data);
+        data[popped..].fill(0.0);
+        if self.is_muted.load(Ordering::Relaxed) {
+            data.fill(0.0);
+        }
+        self.echo_reference.push_interleaved(data, self.channels);
+        self.timer.record(started, budget_ns(data.len(), self.channels, self.sample_rate), data.len() - popped);
+    }
+}
+
+/// A dedicated thread that runs until it is told to stop. Dropping the
+/// handle stops the thread and waits for it.
+pub struct Worker {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    pub fn spawn<F>(name: &str, body: F) -> std::io::Result<Self>
+    where
+        F: FnOnce(Arc<AtomicBool>) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || body(thread_stop))?;
+        Ok(Self { stop, handle: Some(handle) })
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
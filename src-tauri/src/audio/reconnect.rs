@@ -0,0 +1,104 @@
+// src-tauri/src/audio/reconnect.rs
+//
+// Keeps the network usable across Wi-Fi switches and address changes. The
+// send and receive tasks report failures here; a supervisor also watches
+// which local address routes to the TURN server. Either way the socket and
+// its TURN allocation are rebuilt, retrying with exponential backoff.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+
+/// How often the route to the TURN server is checked for a new local address.
+pub const ROUTE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// How long one reconnect attempt may spend binding and allocating. A
+/// lost allocation response would otherwise stall reconnecting for good.
+pub const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+const BACKOFF_INITIAL: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// What the UI hears about the connection.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LinkEvent {
+    /// The link failed and reconnect attempt `attempt` (from 1) is starting.
+    Reconnecting { attempt: u32, reason: String },
+    /// Streaming resumed from `new_addr`.
+    Reconnected { old_addr: SocketAddr, new_addr: SocketAddr },
+}
+
+/// Where streaming tasks report a broken link.
+#[derive(Default)]
+pub struct LinkMonitor {
+    notify: Notify,
+    error: Mutex<Option<String>>,
+}
+
+impl LinkMonitor {
+    /// Reports `error`, unless a failure is already waiting to be handled.
+    pub fn fail(&self, error: String) {
+        self.error.lock().get_or_insert(error);
+        self.notify.notify_one();
+    }
+
+    /// Waits for the next reported failure.
+    pub async fn failed(&self) -> String {
+        loop {
+            if let Some(error) = self.error.lock().take() {
+                return error;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Forgets failures reported before a new session.
+    pub fn clear(&self) {
+        self.error.lock().take();
+    }
+}
+
+/// Whether `error` means our own network is gone, rather than one peer
+/// being unreachable.
+pub fn is_link_failure(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::AddrNotAvailable
+            | io::ErrorKind::NetworkDown
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+/// The local address the OS would use to reach `server`. Nothing is sent.
+pub async fn route_to(server: SocketAddr) -> io::Result<IpAddr> {
+    let bind: SocketAddr = if server.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+    let probe = UdpSocket::bind(bind).await?;
+    probe.connect(server).await?;
+    Ok(probe.local_addr()?.ip())
+}
+
+/// Delays between reconnect attempts: doubling from half a second up to
+/// thirty, each shortened by up to a fifth so clients that lost the same
+/// network don't retry in lockstep.
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self { next: BACKOFF_INITIAL }
+    }
+}
+
+impl Backoff {
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(BACKOFF_MAX);
+        delay.mul_f32(1.0 - rand::random::<f32>() * 0.2)
+    }
+}
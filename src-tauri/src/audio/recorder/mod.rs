@@ -0,0 +1,487 @@
+// src-tauri/src/audio/recorder/mod.rs
+
+pub mod ogg;
+
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use opus::{Application, Channels, Encoder};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use super::codec::{StreamDecoder, MAX_FRAME_SAMPLES, MAX_PACKET_SIZE, SAMPLE_RATE};
+use super::realtime::Worker;
+use self::ogg::{OggOpusWriter, DEFAULT_PRE_SKIP};
+
+// Recordings are always 48 kHz stereo; mono sources are duplicated.
+const CHANNELS: usize = 2;
+// A track that falls this far behind the wall clock has a gap; skip ahead
+// rather than stacking later audio onto the old position.
+const GAP_TOLERANCE: u64 = SAMPLE_RATE as u64 / 10;
+// The mixed file is written this far behind real time so late packets still land.
+const MIX_DELAY: u64 = SAMPLE_RATE as u64 / 2;
+// 20 ms frames when the mixed recording is encoded to Opus.
+const MIX_FRAME: usize = 960;
+const PACKET_QUEUE: usize = 256;
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingFormat {
+    OggOpus,
+    Wav,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingLayout {
+    /// Everyone, including us, in a single file.
+    Mixed,
+    /// One file per participant. Ogg tracks are the received packets remuxed
+    /// without re-encoding.
+    PerParticipant,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RecordingSettings {
+    pub format: RecordingFormat,
+    pub layout: RecordingLayout,
+}
+
+/// Written next to the audio as `<name>.json` when the recording stops.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingMetadata {
+    pub room_id: Uuid,
+    pub room_name: String,
+    pub room_created_at: DateTime<Utc>,
+    pub participants: Vec<String>,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub format: RecordingFormat,
+    pub layout: RecordingLayout,
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackSource {
+    Local,
+    Peer(SocketAddr),
+}
+
+struct TrackPacket {
+    source: TrackSource,
+    payload: Vec<u8>,
+    stereo: bool,
+}
+
+struct Session {
+    packets: SyncSender<TrackPacket>,
+    metadata: RecordingMetadata,
+    // Sidecar path, written on stop.
+    sidecar: PathBuf,
+    files: Arc<Mutex<Vec<PathBuf>>>,
+    _worker: Worker,
+}
+
+/// Taps the encoded packets of every track (ours from the capture worker,
+/// peers' from the receive path) and writes them out on its own thread.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    session: Arc<Mutex<Option<Session>>>,
+}
+
+impl Recorder {
+    /// `track_names` labels the per-participant files; unknown peers are
+    /// named after their address.
+    pub fn start(
+        &self,
+        settings: RecordingSettings,
+        metadata: RecordingMetadata,
+        directory: PathBuf,
+        track_names: HashMap<TrackSource, String>,
+    ) -> Result<(), String> {
+        let mut session = self.session.lock();
+        if session.is_some() {
+            return Err("Already recording".to_string());
+        }
+        std::fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+
+        let base_name = format!(
+            "{}-{}",
+            sanitize(&metadata.room_name),
+            metadata.started_at.format("%Y%m%d-%H%M%S")
+        );
+        let files = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = SessionWriter {
+            settings,
+            directory: directory.clone(),
+            base_name: base_name.clone(),
+            tags: vec![
+                ("TITLE".to_string(), metadata.room_name.clone()),
+                ("ROOM_ID".to_string(), metadata.room_id.to_string()),
+                ("ROOM_CREATED".to_string(), metadata.room_created_at.to_rfc3339()),
+                ("DATE".to_string(), metadata.started_at.to_rfc3339()),
+                ("PARTICIPANTS".to_string(), metadata.participants.join(", ")),
+            ],
+            track_names,
+            started: Instant::now(),
+            tracks: HashMap::new(),
+            mix: None,
+            files: files.clone(),
+            pcm: vec![0.0; MAX_FRAME_SAMPLES * CHANNELS],
+            stereo_pcm: Vec::with_capacity(MAX_FRAME_SAMPLES * CHANNELS),
+        };
+        if settings.layout == RecordingLayout::Mixed {
+            writer.mix = Some(writer.create_mix().map_err(|e| e.to_string())?);
+        }
+
+        let (packets, rx) = mpsc::sync_channel::<TrackPacket>(PACKET_QUEUE);
+        let worker = Worker::spawn("llas-recorder", move |stop: Arc<AtomicBool>| {
+            while !stop.load(Ordering::Relaxed) {
+                match rx.recv_timeout(POLL_INTERVAL) {
+                    Ok(packet) => {
+                        if let Err(e) = writer.write(packet) {
+                            eprintln!("Error writing recording: {}", e);
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                if let Err(e) = writer.flush_mix() {
+                    eprintln!("Error writing recording: {}", e);
+                }
+            }
+            writer.finish();
+        })
+        .map_err(|e| e.to_string())?;
+
+        *session = Some(Session {
+            packets,
+            metadata,
+            sidecar: directory.join(format!("{}.json", base_name)),
+            files,
+            _worker: worker,
+        });
+        Ok(())
+    }
+
+    /// Finalizes the files and writes the metadata sidecar. Returns `None` if
+    /// nothing was being recorded.
+    pub fn stop(&self) -> Option<RecordingMetadata> {
+        let Session { packets, mut metadata, sidecar, files, _worker: worker } = self.session.lock().take()?;
+        drop(packets);
+        // Joining the worker flushes and closes every file.
+        drop(worker);
+
+        metadata.ended_at = Some(Utc::now());
+        metadata.files = files.lock().clone();
+        match serde_json::to_vec_pretty(&metadata) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&sidecar, json) {
+                    eprintln!("Error writing recording metadata: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Error serializing recording metadata: {}", e),
+        }
+        Some(metadata)
+    }
+
+    /// Called with every encoded packet of a track. Never blocks.
+    pub fn tap(&self, source: TrackSource, payload: &[u8], stereo: bool) {
+        if let Some(session) = self.session.lock().as_ref() {
+            let _ = session.packets.try_send(TrackPacket {
+                source,
+                payload: payload.to_vec(),
+                stereo,
+            });
+        }
+    }
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+type WavFile = WavWriter<BufWriter<File>>;
+
+fn create_wav(path: &Path) -> Result<WavFile, Box<dyn std::error::Error>> {
+    let spec = WavSpec {
+        channels: CHANNELS as u16,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    Ok(WavWriter::create(path, spec)?)
+}
+
+fn write_wav_samples(wav: &mut WavFile, samples: &[f32]) -> Result<(), hound::Error> {
+    for sample in samples {
+        wav.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+    }
+    Ok(())
+}
+
+struct Track {
+    decoder: Option<StreamDecoder>,
+    // Sample position (per channel, since the recording started) where the next packet goes.
+    position: u64,
+    ogg: Option<OggOpusWriter>,
+    wav: Option<WavFile>,
+    wav_written: u64,
+}
+
+impl Track {
+    fn finish(self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(ogg) = self.ogg {
+            ogg.finish()?;
+        }
+        if let Some(wav) = self.wav {
+            wav.finalize()?;
+        }
+        Ok(())
+    }
+}
+
+enum MixSink {
+    Wav(WavFile),
+    Ogg {
+        writer: OggOpusWriter,
+        encoder: Encoder,
+        pending: Vec<f32>,
+        granule: u64,
+        pre_skip: u64,
+    },
+}
+
+/// The mixed timeline. `buffer` holds interleaved stereo starting at sample
+/// position `start`; everything older has been written.
+struct MixedOutput {
+    buffer: VecDeque<f32>,
+    start: u64,
+    sink: MixSink,
+}
+
+impl MixedOutput {
+    fn add(&mut self, position: u64, samples: &[f32]) {
+        let skip = self.start.saturating_sub(position) as usize * CHANNELS;
+        if skip >= samples.len() {
+            // Arrived after its part of the timeline was written.
+            return;
+        }
+        let offset = position.saturating_sub(self.start) as usize * CHANNELS;
+        let end = offset + samples.len() - skip;
+        if self.buffer.len() < end {
+            self.buffer.resize(end, 0.0);
+        }
+        for (i, sample) in samples[skip..].iter().enumerate() {
+            self.buffer[offset + i] += sample;
+        }
+    }
+
+    /// Writes the timeline up to `until`, filling silence where nobody spoke.
+    fn flush(&mut self, until: u64) -> Result<(), Box<dyn std::error::Error>> {
+        if until <= self.start {
+            return Ok(());
+        }
+        let len = (until - self.start) as usize * CHANNELS;
+        if self.buffer.len() < len {
+            self.buffer.resize(len, 0.0);
+        }
+        let samples: Vec<f32> = self.buffer.drain(..len).collect();
+        self.start = until;
+        match &mut self.sink {
+            MixSink::Wav(wav) => write_wav_samples(wav, &samples)?,
+            MixSink::Ogg { writer, encoder, pending, granule, pre_skip } => {
+                pending.extend_from_slice(&samples);
+                let mut packet = vec![0u8; MAX_PACKET_SIZE];
+                while pending.len() >= MIX_FRAME * CHANNELS {
+                    let size = encoder.encode_float(&pending[..MIX_FRAME * CHANNELS], &mut packet)?;
+                    pending.drain(..MIX_FRAME * CHANNELS);
+                    *granule += MIX_FRAME as u64;
+                    writer.write_packet(&packet[..size], *granule + *pre_skip)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self, end: u64) -> Result<(), Box<dyn std::error::Error>> {
+        // Round the tail up to a whole Opus frame.
+        let end = end.div_ceil(MIX_FRAME as u64) * MIX_FRAME as u64;
+        self.flush(end)?;
+        match self.sink {
+            MixSink::Wav(wav) => wav.finalize()?,
+            MixSink::Ogg { writer, .. } => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+/// Owned by the recorder thread.
+struct SessionWriter {
+    settings: RecordingSettings,
+    directory: PathBuf,
+    base_name: String,
+    tags: Vec<(String, String)>,
+    track_names: HashMap<TrackSource, String>,
+    started: Instant,
+    tracks: HashMap<TrackSource, Track>,
+    mix: Option<MixedOutput>,
+    files: Arc<Mutex<Vec<PathBuf>>>,
+    pcm: Vec<f32>,
+    stereo_pcm: Vec<f32>,
+}
+
+impl SessionWriter {
+    fn now(&self) -> u64 {
+        (self.started.elapsed().as_secs_f64() * SAMPLE_RATE as f64) as u64
+    }
+
+    fn extension(&self) -> &'static str {
+        match self.settings.format {
+            RecordingFormat::OggOpus => "ogg",
+            RecordingFormat::Wav => "wav",
+        }
+    }
+
+    fn create_mix(&mut self) -> Result<MixedOutput, Box<dyn std::error::Error>> {
+        let path = self.directory.join(format!("{}.{}", self.base_name, self.extension()));
+        let sink = match self.settings.format {
+            RecordingFormat::Wav => MixSink::Wav(create_wav(&path)?),
+            RecordingFormat::OggOpus => {
+                let mut encoder = Encoder::new(SAMPLE_RATE, Channels::Stereo, Application::Audio)?;
+                let pre_skip = encoder.get_lookahead()? as u16;
+                MixSink::Ogg {
+                    writer: OggOpusWriter::create(&path, CHANNELS as u8, pre_skip, &self.tags)?,
+                    encoder,
+                    pending: Vec::with_capacity(MIX_FRAME * CHANNELS * 2),
+                    granule: 0,
+                    pre_skip: pre_skip as u64,
+                }
+            }
+        };
+        self.files.lock().push(path);
+        Ok(MixedOutput {
+            buffer: VecDeque::new(),
+            start: 0,
+            sink,
+        })
+    }
+
+    fn create_track(&mut self, source: TrackSource, stereo: bool) -> Result<Track, Box<dyn std::error::Error>> {
+        let name = self.track_names.get(&source).cloned().unwrap_or_else(|| match source {
+            TrackSource::Local => "local".to_string(),
+            TrackSource::Peer(addr) => addr.to_string(),
+        });
+        let mut track = Track {
+            decoder: None,
+            position: 0,
+            ogg: None,
+            wav: None,
+            wav_written: 0,
+        };
+        if self.settings.layout == RecordingLayout::Mixed {
+            track.decoder = Some(StreamDecoder::new(stereo)?);
+            return Ok(track);
+        }
+
+        let path = self.directory.join(format!("{}-{}.{}", self.base_name, sanitize(&name), self.extension()));
+        match self.settings.format {
+            RecordingFormat::OggOpus => {
+                let mut tags = self.tags.clone();
+                tags.push(("ARTIST".to_string(), name));
+                let channels = if stereo { 2 } else { 1 };
+                track.ogg = Some(OggOpusWriter::create(&path, channels, DEFAULT_PRE_SKIP, &tags)?);
+            }
+            RecordingFormat::Wav => {
+                track.decoder = Some(StreamDecoder::new(stereo)?);
+                track.wav = Some(create_wav(&path)?);
+            }
+        }
+        self.files.lock().push(path);
+        Ok(track)
+    }
+
+    fn write(&mut self, packet: TrackPacket) -> Result<(), Box<dyn std::error::Error>> {
+        let now = self.now();
+        if !self.tracks.contains_key(&packet.source) {
+            let track = self.create_track(packet.source, packet.stereo)?;
+            self.tracks.insert(packet.source, track);
+        }
+        let Some(track) = self.tracks.get_mut(&packet.source) else {
+            return Ok(());
+        };
+
+        let frames = opus::packet::get_nb_samples(&packet.payload, SAMPLE_RATE)? as u64;
+        if track.position + GAP_TOLERANCE < now {
+            track.position = now.saturating_sub(frames);
+        }
+        let position = track.position;
+        track.position += frames;
+
+        if let Some(ogg) = track.ogg.as_mut() {
+            ogg.write_packet(&packet.payload, track.position + DEFAULT_PRE_SKIP as u64)?;
+            return Ok(());
+        }
+
+        let Some(decoder) = track.decoder.as_mut() else {
+            return Ok(());
+        };
+        let decoded = decoder.decode(&packet.payload, packet.stereo, &mut self.pcm)?;
+        self.stereo_pcm.clear();
+        if packet.stereo {
+            self.stereo_pcm.extend_from_slice(&self.pcm[..decoded * CHANNELS]);
+        } else {
+            for &sample in &self.pcm[..decoded] {
+                self.stereo_pcm.push(sample);
+                self.stereo_pcm.push(sample);
+            }
+        }
+
+        if let Some(wav) = track.wav.as_mut() {
+            // Keep every track on the same timeline by writing the gaps as silence.
+            while track.wav_written < position {
+                wav.write_sample(0i16)?;
+                wav.write_sample(0i16)?;
+                track.wav_written += 1;
+            }
+            write_wav_samples(wav, &self.stereo_pcm)?;
+            track.wav_written += decoded as u64;
+        } else if let Some(mix) = self.mix.as_mut() {
+            mix.add(position, &self.stereo_pcm);
+        }
+        Ok(())
+    }
+
+    fn flush_mix(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let until = self.now().saturating_sub(MIX_DELAY);
+        if let Some(mix) = self.mix.as_mut() {
+            mix.flush(until)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) {
+        let end = self.tracks.values().map(|t| t.position).max().unwrap_or(0).max(self.now());
+        if let Some(mix) = self.mix.take() {
+            if let Err(e) = mix.finish(end) {
+                eprintln!("Error finishing recording: {}", e);
+            }
+        }
+        for (_, track) in self.tracks.drain() {
+            if let Err(e) = track.finish() {
+                eprintln!("Error finishing recording: {}", e);
+            }
+        }
+    }
+}
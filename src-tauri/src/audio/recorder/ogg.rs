@@ -0,0 +1,74 @@
+// src-tauri/src/audio/recorder/ogg.rs
+
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use crate::audio::codec::SAMPLE_RATE;
+
+// libopus' encoder delay at 48 kHz for the voip and audio applications. We
+// don't know how a remote peer configured its encoder, so assume the default.
+pub const DEFAULT_PRE_SKIP: u16 = 312;
+
+/// A single Ogg Opus logical stream (RFC 7845) in its own file. Packets are
+/// written as-is; the caller supplies the granule position.
+pub struct OggOpusWriter {
+    writer: PacketWriter<BufWriter<File>>,
+    serial: u32,
+    // The last packet is held back so it can be flagged as end of stream.
+    pending: Option<(Vec<u8>, u64)>,
+}
+
+impl OggOpusWriter {
+    pub fn create(path: &Path, channels: u8, pre_skip: u16, tags: &[(String, String)]) -> std::io::Result<Self> {
+        let mut writer = PacketWriter::new(BufWriter::new(File::create(path)?));
+        let serial = rand::random();
+
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // version
+        head.push(channels);
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // mapping family: mono/stereo
+        writer.write_packet(head.into_boxed_slice(), serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        let vendor = concat!("llas ", env!("CARGO_PKG_VERSION"));
+        let mut comments = Vec::new();
+        comments.extend_from_slice(b"OpusTags");
+        comments.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        comments.extend_from_slice(vendor.as_bytes());
+        comments.extend_from_slice(&(tags.len() as u32).to_le_bytes());
+        for (key, value) in tags {
+            let comment = format!("{}={}", key, value);
+            comments.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            comments.extend_from_slice(comment.as_bytes());
+        }
+        writer.write_packet(comments.into_boxed_slice(), serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        Ok(Self { writer, serial, pending: None })
+    }
+
+    /// `granule` is the sample position (48 kHz, including pre-skip) at the
+    /// end of this packet.
+    pub fn write_packet(&mut self, packet: &[u8], granule: u64) -> std::io::Result<()> {
+        if let Some((previous, previous_granule)) = self.pending.replace((packet.to_vec(), granule)) {
+            self.writer.write_packet(
+                previous.into_boxed_slice(),
+                self.serial,
+                PacketWriteEndInfo::NormalPacket,
+                previous_granule,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if let Some((packet, granule)) = self.pending.take() {
+            self.writer.write_packet(packet.into_boxed_slice(), self.serial, PacketWriteEndInfo::EndStream, granule)?;
+        }
+        use std::io::Write;
+        self.writer.inner_mut().flush()
+    }
+}
@@ -0,0 +1,583 @@
+// src-tauri/src/audio/sfu.rs
+//
+// Selective forwarding: instead of sending every frame to each peer, a
+// client sends it once to the forwarding server, which copies it to the
+// rest of the room. Packets stay sealed under the room key end to end; the
+// server only reads the authenticated-but-clear header and the framing
+// below, so it never needs the key. Clients that would rather receive one
+// stream can hand the server the key and have it mix the room for them;
+// see `mcu`.
+//
+// Client to server: frame kind, room id (16 bytes) and the sender's room
+// address, then for a packet: client flags, a target count and that many
+// addresses (none means the whole room) and the sealed packet; for a room
+// key: the key envelope; for the members a client muted: a count and that
+// many addresses, left out of its mix. Server to client: relay flags, the sender's room
+// address, then the sealed packet. Room addresses are the ones members know
+// each other by, so receivers key presence, replay windows and volumes on
+// the original sender exactly as they would without a server.
+
+use bytes::{Buf, BufMut, BytesMut};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use uuid::Uuid;
+use super::crypto::{Identity, KeyEnvelope, OpenError, PacketOpener, RoomKey};
+use super::mcu::{MixRoom, MIX_INTERVAL};
+use super::packet::PacketHeader;
+use super::presence::{DISCONNECTED_AFTER, RECONNECTING_AFTER};
+
+const FRAME_PACKET: u8 = 0;
+const FRAME_ROOM_KEY: u8 = 1;
+const FRAME_MUTED: u8 = 2;
+
+/// The sender wants the room mixed into one stream rather than forwarded.
+pub const CLIENT_MIX: u8 = 0x01;
+
+/// Packets from this sender were withheld before this one, so a gap in its
+/// sequence numbers isn't loss.
+pub const RELAY_RESUMED: u8 = 0x01;
+/// The packet is the server's mix of the room for us, sealed by the server.
+pub const RELAY_MIXED: u8 = 0x02;
+
+// Weight of the newest packet in a sender's running size.
+const LEVEL_SMOOTHING: f32 = 0.1;
+// Senders without audio for this long aren't speaking.
+const ACTIVE_WINDOW: Duration = Duration::from_secs(1);
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+// A room address unheard for this long may be taken over by another
+// source, as when a client's NAT mapping changes.
+const CLAIM_EXPIRY: Duration = RECONNECTING_AFTER;
+// Mixed frames aren't from any one member.
+const MIX_SENDER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+const ENVELOPE_HEADER_LEN: usize = 32 + 32 + 12;
+
+/// Where a client sends when it streams through a forwarding server.
+#[derive(Debug, Clone)]
+pub struct SfuRoute {
+    pub server: SocketAddr,
+    pub room_id: Uuid,
+    /// The room key wrapped for the server. Set to receive the room as one
+    /// mixed stream instead of a stream per member.
+    pub mix_key: Option<KeyEnvelope>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SfuSettings {
+    /// Forward audio from at most this many of the loudest senders per
+    /// room. Keepalives and goodbyes always go through.
+    pub max_speakers: Option<usize>,
+}
+
+fn put_addr(frame: &mut BytesMut, addr: SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            frame.put_u8(4);
+            frame.put_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            frame.put_u8(6);
+            frame.put_slice(&ip.octets());
+        }
+    }
+    frame.put_u16(addr.port());
+}
+
+fn get_addr(data: &mut &[u8]) -> Option<SocketAddr> {
+    if data.is_empty() {
+        return None;
+    }
+    let ip = match data.get_u8() {
+        4 if data.len() >= 4 + 2 => {
+            let mut octets = [0u8; 4];
+            data.copy_to_slice(&mut octets);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        6 if data.len() >= 16 + 2 => {
+            let mut octets = [0u8; 16];
+            data.copy_to_slice(&mut octets);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, data.get_u16()))
+}
+
+fn frame_start(kind: u8, route: &SfuRoute, from: SocketAddr, capacity: usize) -> BytesMut {
+    let mut frame = BytesMut::with_capacity(1 + 16 + 19 + capacity);
+    frame.put_u8(kind);
+    frame.put_slice(route.room_id.as_bytes());
+    put_addr(&mut frame, from);
+    frame
+}
+
+/// Wraps a sealed packet for the server. `targets` narrows delivery to
+/// those members, as for a whisper; empty means everyone.
+pub fn client_frame(route: &SfuRoute, from: SocketAddr, targets: &[SocketAddr], packet: &[u8]) -> BytesMut {
+    let mut frame = frame_start(FRAME_PACKET, route, from, 2 + 19 * targets.len() + packet.len());
+    frame.put_u8(if route.mix_key.is_some() { CLIENT_MIX } else { 0 });
+    frame.put_u8(targets.len().min(u8::MAX as usize) as u8);
+    for target in targets.iter().take(u8::MAX as usize) {
+        put_addr(&mut frame, *target);
+    }
+    frame.put_slice(packet);
+    frame
+}
+
+/// Hands the server the room key, for clients that want a mix.
+pub fn key_frame(route: &SfuRoute, from: SocketAddr) -> Option<BytesMut> {
+    let envelope = route.mix_key.as_ref()?;
+    let mut frame = frame_start(FRAME_ROOM_KEY, route, from, ENVELOPE_HEADER_LEN + envelope.ciphertext.len());
+    frame.put_slice(&envelope.sender);
+    frame.put_slice(&envelope.recipient);
+    frame.put_slice(&envelope.nonce);
+    frame.put_slice(&envelope.ciphertext);
+    Some(frame)
+}
+
+/// Tells the server whom we muted, for clients that want a mix: muting only
+/// drops streams we receive, and a mix has them all in one.
+pub fn muted_frame(route: &SfuRoute, from: SocketAddr, muted: &[SocketAddr]) -> Option<BytesMut> {
+    route.mix_key.as_ref()?;
+    let mut frame = frame_start(FRAME_MUTED, route, from, 1 + 19 * muted.len());
+    frame.put_u8(muted.len().min(u8::MAX as usize) as u8);
+    for addr in muted.iter().take(u8::MAX as usize) {
+        put_addr(&mut frame, *addr);
+    }
+    Some(frame)
+}
+
+pub enum ClientMessage<'a> {
+    Packet {
+        mix: bool,
+        targets: Vec<SocketAddr>,
+        packet: &'a [u8],
+    },
+    RoomKey(KeyEnvelope),
+    Muted(Vec<SocketAddr>),
+}
+
+/// A client frame taken apart.
+pub struct ClientFrame<'a> {
+    pub room_id: Uuid,
+    pub from: SocketAddr,
+    pub message: ClientMessage<'a>,
+}
+
+pub fn parse_client_frame(mut data: &[u8]) -> Option<ClientFrame<'_>> {
+    if data.len() < 1 + 16 {
+        return None;
+    }
+    let kind = data.get_u8();
+    let room_id = Uuid::from_slice(&data[..16]).ok()?;
+    data.advance(16);
+    let from = get_addr(&mut data)?;
+    let message = match kind {
+        FRAME_PACKET => {
+            if data.len() < 2 {
+                return None;
+            }
+            let flags = data.get_u8();
+            let count = data.get_u8();
+            let targets = (0..count).map(|_| get_addr(&mut data)).collect::<Option<Vec<_>>>()?;
+            ClientMessage::Packet { mix: flags & CLIENT_MIX != 0, targets, packet: data }
+        }
+        FRAME_ROOM_KEY => {
+            if data.len() <= ENVELOPE_HEADER_LEN {
+                return None;
+            }
+            ClientMessage::RoomKey(KeyEnvelope {
+                sender: data[..32].try_into().ok()?,
+                recipient: data[32..64].try_into().ok()?,
+                nonce: data[64..76].try_into().ok()?,
+                ciphertext: data[ENVELOPE_HEADER_LEN..].to_vec(),
+            })
+        }
+        FRAME_MUTED => {
+            if data.is_empty() {
+                return None;
+            }
+            let count = data.get_u8();
+            let muted = (0..count).map(|_| get_addr(&mut data)).collect::<Option<Vec<_>>>()?;
+            ClientMessage::Muted(muted)
+        }
+        _ => return None,
+    };
+    Some(ClientFrame { room_id, from, message })
+}
+
+/// Wraps a sealed packet from `from` for delivery to the rest of the room.
+pub fn relay_frame(flags: u8, from: SocketAddr, packet: &[u8]) -> BytesMut {
+    let mut frame = BytesMut::with_capacity(1 + 19 + packet.len());
+    frame.put_u8(flags);
+    put_addr(&mut frame, from);
+    frame.put_slice(packet);
+    frame
+}
+
+/// Splits a datagram from the server into relay flags, the original
+/// sender's room address and its sealed packet.
+pub fn parse_relay_frame(mut data: &[u8]) -> Option<(u8, SocketAddr, &[u8])> {
+    if data.is_empty() {
+        return None;
+    }
+    let flags = data.get_u8();
+    let from = get_addr(&mut data)?;
+    Some((flags, from, data))
+}
+
+/// One client of a room, keyed by its room address.
+struct Client {
+    // Where its datagrams come from, and so where we send. The room address
+    // stays bound to it until it says goodbye or goes quiet.
+    source: SocketAddr,
+    // Packets seen from `source` under this address. Nothing is forwarded
+    // for or to a client until its second, so a lone spoofed datagram
+    // can't make us send to a whole room.
+    packets: u32,
+    last_seen: Instant,
+    last_audio: Option<Instant>,
+    // Running average packet size. Opus spends few bytes on silence, so this
+    // ranks speakers without reading the (sealed) audio.
+    level: f32,
+    withheld: bool,
+    wants_mix: bool,
+    // Room addresses left out of its mix.
+    muted: Vec<SocketAddr>,
+}
+
+impl Client {
+    fn confirmed(&self) -> bool {
+        self.packets > 1
+    }
+}
+
+#[derive(Default)]
+struct RoomState {
+    clients: HashMap<SocketAddr, Client>,
+    // Present once a packet from the room has opened under a key a client
+    // handed over.
+    mix: Option<MixRoom>,
+    // The latest key each client has offered that isn't the one we mix
+    // with. Anyone can wrap a key for us, so an offer only takes over once
+    // it opens a packet from the room.
+    offered_keys: HashMap<SocketAddr, RoomKey>,
+}
+
+impl RoomState {
+    fn remove(&mut self, addr: SocketAddr) {
+        self.clients.remove(&addr);
+        self.offered_keys.remove(&addr);
+        if let Some(mix) = self.mix.as_mut() {
+            mix.retain(|source| *source != addr);
+        }
+    }
+
+    /// Mixes a packet from `from`. A packet that fails under the current
+    /// key may be sealed with a new one, say after a kick; an offered key
+    /// that opens it replaces the mix.
+    fn mix_packet(&mut self, room_id: Uuid, from: SocketAddr, targets: &[SocketAddr], packet: &[u8]) {
+        let current = self.mix.as_mut().map(|mix| mix.push(from, targets, packet));
+        if !matches!(current, None | Some(Err(OpenError::Unauthenticated))) {
+            return;
+        }
+        let Some(key) = self
+            .offered_keys
+            .values()
+            .find(|key| PacketOpener::new(key).open(from, packet).is_ok())
+            .cloned()
+        else {
+            return;
+        };
+        eprintln!("Mixing room {}", room_id);
+        self.offered_keys.retain(|_, offered| *offered != key);
+        let mut mix = MixRoom::new(key);
+        // Just opened, so this can't fail.
+        let _ = mix.push(from, targets, packet);
+        self.mix = Some(mix);
+    }
+}
+
+/// Decides who gets each datagram. Holds no sockets, so `serve` and tests
+/// drive it alike.
+pub struct Forwarder {
+    settings: SfuSettings,
+    // Without one, clients can't hand us room keys and nothing is mixed.
+    identity: Option<Identity>,
+    rooms: HashMap<Uuid, RoomState>,
+    // The room and room address each source speaks for. A source is one
+    // client, in one room.
+    claims: HashMap<SocketAddr, (Uuid, SocketAddr)>,
+}
+
+impl Forwarder {
+    pub fn new(settings: SfuSettings, identity: Option<Identity>) -> Self {
+        Self {
+            settings,
+            identity,
+            rooms: HashMap::new(),
+            claims: HashMap::new(),
+        }
+    }
+
+    /// Handles one datagram from `source` and returns the frame to send and
+    /// where to send it, if anywhere.
+    pub fn route(&mut self, source: SocketAddr, datagram: &[u8], now: Instant) -> Option<(BytesMut, Vec<SocketAddr>)> {
+        let frame = parse_client_frame(datagram)?;
+        let (mix, targets, packet) = match frame.message {
+            ClientMessage::Packet { mix, targets, packet } => (mix, targets, packet),
+            ClientMessage::RoomKey(envelope) => {
+                self.accept_key(source, frame.room_id, frame.from, &envelope);
+                return None;
+            }
+            ClientMessage::Muted(muted) => {
+                self.set_muted(source, frame.room_id, frame.from, muted);
+                return None;
+            }
+        };
+        let (header, _) = PacketHeader::parse(packet)?;
+        if !self.claim(source, frame.room_id, frame.from, now) {
+            return None;
+        }
+
+        let room = self.rooms.get_mut(&frame.room_id)?;
+        let client = room.clients.get_mut(&frame.from)?;
+        client.last_seen = now;
+        client.packets = client.packets.saturating_add(1);
+        if !client.confirmed() {
+            if header.is_bye() {
+                self.release(source);
+            }
+            return None;
+        }
+        client.wants_mix = mix;
+        let audio = !header.is_keepalive() && !header.is_bye() && !header.is_control();
+        if audio {
+            client.last_audio = Some(now);
+            client.level += (packet.len() as f32 - client.level) * LEVEL_SMOOTHING;
+        }
+        if room.mix.is_some() || !room.offered_keys.is_empty() {
+            // Mixed whatever the speaker limit: mixing is what keeps the
+            // listener's bandwidth down.
+            room.mix_packet(frame.room_id, frame.from, &targets, packet);
+        }
+
+        // Whispers are aimed at someone, so they are never withheld.
+        if audio && !header.is_whisper() && !is_speaker(&room.clients, frame.from, self.settings.max_speakers, now) {
+            if let Some(client) = room.clients.get_mut(&frame.from) {
+                client.withheld = true;
+            }
+            return None;
+        }
+
+        let client = room.clients.get_mut(&frame.from)?;
+        let flags = if std::mem::take(&mut client.withheld) { RELAY_RESUMED } else { 0 };
+        // Clients getting a mix hear this sender's audio there.
+        let mixed = audio && room.mix.is_some();
+        let destinations = room
+            .clients
+            .iter()
+            .filter(|(addr, client)| **addr != frame.from && client.confirmed())
+            .filter(|(addr, _)| targets.is_empty() || targets.contains(addr))
+            .filter(|(_, client)| !(mixed && client.wants_mix))
+            .map(|(_, client)| client.source)
+            .collect();
+        if header.is_bye() {
+            self.release(source);
+        }
+        Some((relay_frame(flags, frame.from, packet), destinations))
+    }
+
+    /// Binds room address `from` in `room_id` to `source`. Fails while
+    /// another source holds the address and has been heard from recently;
+    /// an address only moves after a goodbye or once its holder goes quiet.
+    fn claim(&mut self, source: SocketAddr, room_id: Uuid, from: SocketAddr, now: Instant) -> bool {
+        if self.claims.get(&source) == Some(&(room_id, from)) {
+            return true;
+        }
+        let holder = self
+            .rooms
+            .get(&room_id)
+            .and_then(|room| room.clients.get(&from))
+            .map(|client| (client.source, client.last_seen));
+        if let Some((holder, last_seen)) = holder {
+            if now.duration_since(last_seen) < CLAIM_EXPIRY {
+                return false;
+            }
+            self.release(holder);
+        }
+        // One room per client: joining here means it left the others.
+        self.release(source);
+        self.claims.insert(source, (room_id, from));
+        self.rooms.entry(room_id).or_default().clients.insert(from, Client {
+            source,
+            packets: 0,
+            last_seen: now,
+            last_audio: None,
+            level: 0.0,
+            withheld: false,
+            wants_mix: false,
+            muted: Vec::new(),
+        });
+        true
+    }
+
+    /// Forgets the client `source` speaks for.
+    fn release(&mut self, source: SocketAddr) {
+        if let Some((room_id, addr)) = self.claims.remove(&source) {
+            if let Some(room) = self.rooms.get_mut(&room_id) {
+                room.remove(addr);
+            }
+        }
+    }
+
+    /// Takes the key a client of the room wrapped for us. It is only an
+    /// offer until it opens one of the room's packets; see `mix_packet`.
+    fn accept_key(&mut self, source: SocketAddr, room_id: Uuid, from: SocketAddr, envelope: &KeyEnvelope) {
+        let Some(identity) = self.identity.as_ref() else {
+            return;
+        };
+        if self.claims.get(&source) != Some(&(room_id, from)) {
+            // Not a client of this room, or not this one.
+            return;
+        }
+        let key = match identity.unwrap_key(envelope, room_id) {
+            Ok(key) => key,
+            Err(e) => {
+                eprintln!("Rejected room key for {}: {}", room_id, e);
+                return;
+            }
+        };
+        let Some(room) = self.rooms.get_mut(&room_id) else {
+            return;
+        };
+        if room.mix.as_ref().is_some_and(|mix| *mix.key() == key) {
+            room.offered_keys.remove(&from);
+        } else {
+            room.offered_keys.insert(from, key);
+        }
+    }
+
+    /// Takes the members a client of the room muted, whom its mix leaves out.
+    fn set_muted(&mut self, source: SocketAddr, room_id: Uuid, from: SocketAddr, muted: Vec<SocketAddr>) {
+        if self.claims.get(&source) != Some(&(room_id, from)) {
+            return;
+        }
+        if let Some(client) = self.rooms.get_mut(&room_id).and_then(|room| room.clients.get_mut(&from)) {
+            client.muted = muted;
+        }
+    }
+
+    /// The next mixed frame for every client that asked for one, with
+    /// where to send it.
+    pub fn mix(&mut self) -> Vec<(BytesMut, SocketAddr)> {
+        let mut frames = Vec::new();
+        for room in self.rooms.values_mut() {
+            let Some(mix) = room.mix.as_mut() else {
+                continue;
+            };
+            let listeners: Vec<(SocketAddr, &[SocketAddr])> = room
+                .clients
+                .iter()
+                .filter(|(_, client)| client.wants_mix)
+                .map(|(addr, client)| (*addr, client.muted.as_slice()))
+                .collect();
+            for (addr, packet) in mix.render(&listeners) {
+                if let Some(client) = room.clients.get(&addr) {
+                    frames.push((relay_frame(RELAY_MIXED, MIX_SENDER, &packet), client.source));
+                }
+            }
+        }
+        frames
+    }
+
+    /// Forgets clients that have gone quiet, and rooms left empty.
+    pub fn expire(&mut self, now: Instant) {
+        for room in self.rooms.values_mut() {
+            room.clients.retain(|_, client| now.duration_since(client.last_seen) < DISCONNECTED_AFTER);
+            let clients = &room.clients;
+            room.offered_keys.retain(|addr, _| clients.contains_key(addr));
+            if let Some(mix) = room.mix.as_mut() {
+                mix.retain(|addr| clients.contains_key(addr));
+            }
+        }
+        self.rooms.retain(|_, room| !room.clients.is_empty());
+        let rooms = &self.rooms;
+        self.claims.retain(|source, (room_id, addr)| {
+            rooms
+                .get(room_id)
+                .and_then(|room| room.clients.get(addr))
+                .is_some_and(|client| client.source == *source)
+        });
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.rooms.values().map(|room| room.clients.len()).sum()
+    }
+}
+
+/// Whether `addr` is among the room's `max_speakers` loudest recent senders.
+fn is_speaker(clients: &HashMap<SocketAddr, Client>, addr: SocketAddr, max_speakers: Option<usize>, now: Instant) -> bool {
+    let Some(max_speakers) = max_speakers else {
+        return true;
+    };
+    let Some(level) = clients.get(&addr).map(|client| client.level) else {
+        return false;
+    };
+    let louder = clients
+        .iter()
+        .filter(|(other, client)| {
+            **other != addr
+                && client.level > level
+                && client.last_audio.is_some_and(|last| now.duration_since(last) < ACTIVE_WINDOW)
+        })
+        .count();
+    louder < max_speakers
+}
+
+/// Runs a forwarding server on `bind_addr`. Only failing to bind stops it;
+/// a receive error, such as a reset from a client that went away, is
+/// logged and skipped. With an `identity`, clients may ask it to mix their
+/// room.
+pub async fn serve(bind_addr: &str, settings: SfuSettings, identity: Option<Identity>) -> io::Result<()> {
+    let socket = UdpSocket::bind(bind_addr).await?;
+    eprintln!("Forwarding on {}", socket.local_addr()?);
+    let mut forwarder = Forwarder::new(settings, identity);
+    let mut expiry = tokio::time::interval(EXPIRY_INTERVAL);
+    let mut mix = tokio::time::interval(MIX_INTERVAL);
+    let mut buffer = vec![0u8; 2048];
+    loop {
+        let (size, source) = tokio::select! {
+            received = socket.recv_from(&mut buffer) => match received {
+                Ok(received) => received,
+                Err(e) => {
+                    eprintln!("Error receiving: {}", e);
+                    continue;
+                }
+            },
+            _ = expiry.tick() => {
+                forwarder.expire(Instant::now());
+                continue;
+            }
+            _ = mix.tick() => {
+                for (frame, destination) in forwarder.mix() {
+                    if let Err(e) = socket.send_to(&frame, destination).await {
+                        eprintln!("Error sending mix to {}: {}", destination, e);
+                    }
+                }
+                continue;
+            }
+        };
+        let Some((frame, destinations)) = forwarder.route(source, &buffer[..size], Instant::now()) else {
+            continue;
+        };
+        for destination in destinations {
+            if let Err(e) = socket.send_to(&frame, destination).await {
+                eprintln!("Error forwarding to {}: {}", destination, e);
+            }
+        }
+    }
+}
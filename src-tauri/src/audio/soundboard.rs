@@ -0,0 +1,93 @@
+// src-tauri/src/audio/soundboard.rs
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use uuid::Uuid;
+use super::file_source::Clip;
+
+struct Voice {
+    id: Uuid,
+    clip: Clip,
+    // Next stereo frame to play.
+    position: usize,
+}
+
+struct State {
+    voices: Vec<Voice>,
+    volume: f32,
+    monitoring: bool,
+}
+
+/// Clips injected into our outgoing stream. The capture worker mixes every
+/// playing clip into each frame after the DSP chain, so peers hear them
+/// untouched by noise suppression or AGC.
+#[derive(Clone)]
+pub struct Soundboard {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Soundboard {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                voices: Vec::new(),
+                volume: 1.0,
+                monitoring: true,
+            })),
+        }
+    }
+}
+
+impl Soundboard {
+    /// Starts a clip from the beginning; clips already playing keep going.
+    pub fn play(&self, clip: Clip) -> Uuid {
+        let id = Uuid::new_v4();
+        self.state.lock().voices.push(Voice { id, clip, position: 0 });
+        id
+    }
+
+    /// Stops one clip, or all of them when `id` is `None`.
+    pub fn stop(&self, id: Option<Uuid>) {
+        let mut state = self.state.lock();
+        match id {
+            Some(id) => state.voices.retain(|voice| voice.id != id),
+            None => state.voices.clear(),
+        }
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.state.lock().volume = volume.clamp(0.0, 2.0);
+    }
+
+    /// Whether we hear the injected audio ourselves.
+    pub fn set_monitoring(&self, enabled: bool) {
+        self.state.lock().monitoring = enabled;
+    }
+
+    /// Renders the next `injected.len() / channels` frames of every playing
+    /// clip into `injected`. Returns `None` if nothing is playing, otherwise
+    /// whether the audio should also be monitored locally.
+    pub fn render(&self, injected: &mut [f32], channels: usize) -> Option<bool> {
+        let mut state = self.state.lock();
+        if state.voices.is_empty() {
+            return None;
+        }
+        injected.fill(0.0);
+        let volume = state.volume;
+        for voice in state.voices.iter_mut() {
+            let remaining = &voice.clip[voice.position * 2..];
+            let frames = (injected.len() / channels).min(remaining.len() / 2);
+            for (out, pair) in injected.chunks_exact_mut(channels).zip(remaining.chunks_exact(2)) {
+                if channels == 1 {
+                    out[0] += (pair[0] + pair[1]) * 0.5 * volume;
+                } else {
+                    out[0] += pair[0] * volume;
+                    out[1] += pair[1] * volume;
+                }
+            }
+            voice.position += frames;
+        }
+        state.voices.retain(|voice| voice.position * 2 < voice.clip.len());
+        Some(state.monitoring)
+    }
+}
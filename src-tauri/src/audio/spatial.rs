@@ -0,0 +1,339 @@
+// src-tauri/src/audio/spatial.rs
+//
+// Positional audio for game sessions. Each remote user can be placed in a
+// shared 3D space (2D games leave z at 0) and the listener has a position
+// and heading. The mixer runs every placed peer's decoded audio through a
+// spatializer on its way into the peer's queue: quieter with distance, and
+// either panned between the speakers or rendered through a spherical-head
+// model of the ears for headphones.
+//
+// Axes: x to the right, y forward and z up, as seen by a listener with yaw
+// 0. Only left and right are modelled, so height matters through distance
+// alone.
+
+use serde::{Serialize, Deserialize};
+use std::f32::consts::PI;
+use super::codec::SAMPLE_RATE;
+
+// Spherical-head model (Brown & Duda, 1998).
+const HEAD_RADIUS_M: f32 = 0.0875;
+const SPEED_OF_SOUND: f32 = 343.0;
+// Head shadow at its deepest (alpha) and the ear angle where that happens.
+const SHADOW_ALPHA_MIN: f32 = 0.1;
+const SHADOW_THETA_MIN: f32 = 150.0 * PI / 180.0;
+// Longest interaural delay is about 0.66 ms; the delay line covers it.
+const DELAY_LINE: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+    #[serde(default)]
+    pub z: f32,
+}
+
+impl Position {
+    fn minus(self, other: Position) -> Position {
+        Position { x: self.x - other.x, y: self.y - other.y, z: self.z - other.z }
+    }
+
+    fn dot(self, other: Position) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+}
+
+/// Where we are and which way we face.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ListenerPose {
+    pub position: Position,
+    /// Heading in degrees, clockwise from +y seen from above.
+    #[serde(default)]
+    pub yaw: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpatialMode {
+    /// Equal-power panning between left and right; works on speakers.
+    #[default]
+    Panning,
+    /// Interaural delay and head shadow from a spherical-head model. Needs
+    /// headphones.
+    Hrtf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpatialSettings {
+    pub enabled: bool,
+    pub mode: SpatialMode,
+    /// Distance in world units at which a voice plays at full volume.
+    pub reference_distance: f32,
+    /// Beyond this distance a voice gets no quieter.
+    pub max_distance: f32,
+    /// How fast volume falls off past the reference distance; 0 disables
+    /// attenuation.
+    pub rolloff: f32,
+}
+
+impl Default for SpatialSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: SpatialMode::Panning,
+            reference_distance: 1.0,
+            max_distance: 50.0,
+            rolloff: 1.0,
+        }
+    }
+}
+
+impl SpatialSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.reference_distance.is_finite() || self.reference_distance <= 0.0 {
+            return Err("Reference distance must be positive".to_string());
+        }
+        if self.max_distance.is_nan() || self.max_distance < self.reference_distance {
+            return Err("Max distance must be at least the reference distance".to_string());
+        }
+        if !self.rolloff.is_finite() || self.rolloff < 0.0 {
+            return Err("Rolloff can't be negative".to_string());
+        }
+        Ok(())
+    }
+
+    /// Inverse-distance gain, clamped between the reference and maximum
+    /// distance like OpenAL's default model.
+    fn distance_gain(&self, distance: f32) -> f32 {
+        let distance = distance.clamp(self.reference_distance, self.max_distance);
+        self.reference_distance / (self.reference_distance + self.rolloff * (distance - self.reference_distance))
+    }
+}
+
+/// A source as heard from the listener.
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    gain: f32,
+    /// Sine of the angle off the median plane: -1 fully left, 1 fully right.
+    lateral: f32,
+}
+
+fn place(source: Position, listener: &ListenerPose, settings: &SpatialSettings) -> Placement {
+    let yaw = listener.yaw.to_radians();
+    let right = Position { x: yaw.cos(), y: -yaw.sin(), z: 0.0 };
+    let offset = source.minus(listener.position);
+    let distance = offset.dot(offset).sqrt();
+    let lateral = if distance > f32::EPSILON { offset.dot(right) / distance } else { 0.0 };
+    Placement {
+        gain: settings.distance_gain(distance),
+        lateral: lateral.clamp(-1.0, 1.0),
+    }
+}
+
+/// One-pole, one-zero head-shadow filter for one ear.
+#[derive(Default)]
+struct HeadShadow {
+    b0: f32,
+    b1: f32,
+    a1: f32,
+    x1: f32,
+    y1: f32,
+}
+
+impl HeadShadow {
+    /// `ear_angle` is the angle between the source and this ear's axis: 0
+    /// when the source faces the ear, PI when it is on the other side.
+    fn set_angle(&mut self, ear_angle: f32) {
+        let alpha = (1.0 + SHADOW_ALPHA_MIN / 2.0)
+            + (1.0 - SHADOW_ALPHA_MIN / 2.0) * (ear_angle / SHADOW_THETA_MIN * PI).cos();
+        // H(s) = (alpha * s + beta) / (s + beta), through the bilinear transform.
+        let beta = 2.0 * SPEED_OF_SOUND / HEAD_RADIUS_M;
+        let k = 2.0 * SAMPLE_RATE as f32;
+        self.b0 = (beta + alpha * k) / (beta + k);
+        self.b1 = (beta - alpha * k) / (beta + k);
+        self.a1 = (beta - k) / (beta + k);
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 - self.a1 * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+}
+
+/// Per-peer spatial state. Gains and delays glide from one packet's
+/// placement to the next so moving voices don't click.
+pub struct Spatializer {
+    gains: [f32; 2],
+    delays: [f32; 2],
+    shadows: [HeadShadow; 2],
+    // The last `DELAY_LINE` input samples, then the current packet.
+    line: Vec<f32>,
+    started: bool,
+}
+
+impl Default for Spatializer {
+    fn default() -> Self {
+        Self {
+            gains: [0.0; 2],
+            delays: [0.0; 2],
+            shadows: Default::default(),
+            line: vec![0.0; DELAY_LINE],
+            started: false,
+        }
+    }
+}
+
+impl Spatializer {
+    /// Renders mono `input` from `source` into interleaved `channels`-channel
+    /// `output`. Mono output only gets distance attenuation.
+    pub fn process(
+        &mut self,
+        input: &[f32],
+        source: Position,
+        listener: &ListenerPose,
+        settings: &SpatialSettings,
+        channels: usize,
+        output: &mut Vec<f32>,
+    ) {
+        let placement = place(source, listener, settings);
+        let (targets, delays) = match (channels, settings.mode) {
+            (2, SpatialMode::Panning) => {
+                let angle = (placement.lateral + 1.0) * PI / 4.0;
+                ([angle.cos() * placement.gain, angle.sin() * placement.gain], [0.0; 2])
+            }
+            (2, SpatialMode::Hrtf) => {
+                // Woodworth's interaural time difference; the far ear lags.
+                let theta = placement.lateral.asin();
+                let itd = HEAD_RADIUS_M / SPEED_OF_SOUND * (theta.abs() + theta.abs().sin()) * SAMPLE_RATE as f32;
+                let (left, right) = if theta > 0.0 { (itd, 0.0) } else { (0.0, itd) };
+                let ear_angles = [PI / 2.0 + theta, PI / 2.0 - theta];
+                for (shadow, angle) in self.shadows.iter_mut().zip(ear_angles) {
+                    shadow.set_angle(angle);
+                }
+                ([placement.gain; 2], [left, right])
+            }
+            _ => ([placement.gain; 2], [0.0; 2]),
+        };
+        if !self.started {
+            self.gains = targets;
+            self.delays = delays;
+            self.started = true;
+        }
+
+        let history = self.line.len() - DELAY_LINE;
+        self.line.drain(..history);
+        self.line.extend_from_slice(input);
+
+        output.clear();
+        let len = input.len().max(1) as f32;
+        let hrtf = channels == 2 && settings.mode == SpatialMode::Hrtf;
+        for (i, &dry) in input.iter().enumerate() {
+            let t = (i + 1) as f32 / len;
+            for ear in 0..channels.min(2) {
+                let gain = self.gains[ear] + (targets[ear] - self.gains[ear]) * t;
+                let sample = if hrtf {
+                    let delay = self.delays[ear] + (delays[ear] - self.delays[ear]) * t;
+                    let position = (DELAY_LINE + i) as f32 - delay;
+                    let index = position.floor() as usize;
+                    let fraction = position - index as f32;
+                    let next = self.line[(index + 1).min(self.line.len() - 1)];
+                    let delayed = self.line[index] * (1.0 - fraction) + next * fraction;
+                    self.shadows[ear].process(delayed)
+                } else {
+                    dry
+                };
+                output.push(sample * gain);
+            }
+            for _ in 2..channels {
+                output.push(0.0);
+            }
+        }
+        self.gains = targets;
+        self.delays = delays;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f32, y: f32) -> Position {
+        Position { x, y, z: 0.0 }
+    }
+
+    fn facing(yaw: f32) -> ListenerPose {
+        ListenerPose { position: Position::default(), yaw }
+    }
+
+    /// Left and right output levels for a steady tone from `source`.
+    fn ears(source: Position, listener: &ListenerPose, settings: &SpatialSettings) -> [f32; 2] {
+        let input = vec![0.5; 480];
+        let mut output = Vec::new();
+        Spatializer::default().process(&input, source, listener, settings, 2, &mut output);
+        let level = |ear: usize| output.iter().skip(ear).step_by(2).map(|s| s.abs()).sum::<f32>();
+        [level(0), level(1)]
+    }
+
+    #[test]
+    fn lateral_follows_position_and_yaw() {
+        let settings = SpatialSettings::default();
+        let lateral = |source, listener| place(source, &listener, &settings).lateral;
+        assert!((lateral(at(3.0, 0.0), facing(0.0)) - 1.0).abs() < 1e-5);
+        assert!((lateral(at(-3.0, 0.0), facing(0.0)) + 1.0).abs() < 1e-5);
+        assert!(lateral(at(0.0, 3.0), facing(0.0)).abs() < 1e-5);
+        // Turning right puts what was ahead on the left.
+        assert!((lateral(at(0.0, 3.0), facing(90.0)) + 1.0).abs() < 1e-5);
+        assert!((lateral(at(3.0, 0.0), facing(180.0)) + 1.0).abs() < 1e-5);
+        // Measured from where the listener stands.
+        let listener = ListenerPose { position: at(5.0, 0.0), yaw: 0.0 };
+        assert!((lateral(at(3.0, 0.0), listener) + 1.0).abs() < 1e-5);
+        assert_eq!(lateral(at(5.0, 0.0), listener), 0.0);
+    }
+
+    #[test]
+    fn panning_favours_the_side_the_source_is_on() {
+        let settings = SpatialSettings::default();
+        let [left, right] = ears(at(1.0, 0.0), &facing(0.0), &settings);
+        assert!(right > 0.0 && left < right * 1e-3);
+        let [left, right] = ears(at(-1.0, 1.0), &facing(0.0), &settings);
+        assert!(left > right * 2.0);
+        let [left, right] = ears(at(1.0, 0.0), &facing(90.0), &settings);
+        assert!((left - right).abs() < 1e-3 * left);
+    }
+
+    #[test]
+    fn distance_gain_is_clamped_to_the_reference_and_max_distance() {
+        let settings = SpatialSettings { reference_distance: 2.0, max_distance: 10.0, rolloff: 1.0, ..Default::default() };
+        assert_eq!(settings.distance_gain(0.0), 1.0);
+        assert_eq!(settings.distance_gain(2.0), 1.0);
+        assert!((settings.distance_gain(4.0) - 0.5).abs() < 1e-6);
+        assert!(settings.distance_gain(6.0) < settings.distance_gain(4.0));
+        assert_eq!(settings.distance_gain(10.0), 0.2);
+        assert_eq!(settings.distance_gain(1000.0), 0.2);
+
+        let flat = SpatialSettings { rolloff: 0.0, ..settings };
+        assert_eq!(flat.distance_gain(8.0), 1.0);
+    }
+
+    #[test]
+    fn hrtf_delays_the_far_ear() {
+        let settings = SpatialSettings { mode: SpatialMode::Hrtf, ..Default::default() };
+        let mut impulse = vec![0.0; 480];
+        impulse[0] = 1.0;
+        // Roughly 0.66 ms for a source straight to one side.
+        let expected = HEAD_RADIUS_M / SPEED_OF_SOUND * (PI / 2.0 + 1.0) * SAMPLE_RATE as f32;
+        for (source, near, far) in [(at(1.0, 0.0), 1, 0), (at(-1.0, 0.0), 0, 1)] {
+            let mut output = Vec::new();
+            Spatializer::default().process(&impulse, source, &facing(0.0), &settings, 2, &mut output);
+            let peak = |ear: usize| {
+                (0..impulse.len())
+                    .max_by(|a, b| output[a * 2 + ear].abs().total_cmp(&output[b * 2 + ear].abs()))
+                    .unwrap()
+            };
+            assert_eq!(peak(near), 0);
+            assert!((peak(far) as f32 - expected).abs() <= 1.0, "far ear peaks at {}", peak(far));
+        }
+    }
+}
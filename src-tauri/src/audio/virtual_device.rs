@@ -0,0 +1,133 @@
+// src-tauri/src/audio/virtual_device.rs
+//
+// Stand-ins for the sound card on machines without one. A clocked thread
+// feeds the capture callback from a test signal, and another drains the
+// playback callback, optionally into a WAV file. Both tick every 10 ms, like
+// a device running at the engine's fixed buffer size, so the DSP chain,
+// encoder and mixer behind them run exactly as they do in a call.
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use super::codec::SAMPLE_RATE;
+use super::file_source::Clip;
+use super::realtime::{CaptureCallback, PlaybackCallback, Worker};
+
+const TICK: Duration = Duration::from_millis(10);
+const TICK_FRAMES: usize = SAMPLE_RATE as usize / 100;
+const TONE_LEVEL: f32 = 0.25;
+
+/// What the virtual microphone picks up.
+#[derive(Clone)]
+pub enum TestSignal {
+    Silence,
+    /// A sine wave at this many hertz.
+    Tone(f32),
+    /// A decoded file, looped.
+    Clip(Clip),
+}
+
+/// Replaces the capture and playback devices. `record` keeps whatever we
+/// would have played; it is rewritten each time playback restarts.
+#[derive(Clone)]
+pub struct VirtualDevices {
+    pub input: TestSignal,
+    pub record: Option<PathBuf>,
+}
+
+/// Sleeps until each tick's deadline, so time spent working doesn't drift
+/// the clock.
+fn run_clocked(stop: &AtomicBool, mut tick: impl FnMut()) {
+    let mut deadline = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        tick();
+        deadline += TICK;
+        let now = Instant::now();
+        if deadline > now {
+            std::thread::sleep(deadline - now);
+        } else {
+            // Fell behind (suspended, overloaded); don't try to catch up.
+            deadline = now;
+        }
+    }
+}
+
+pub fn spawn_virtual_input(
+    signal: TestSignal,
+    channels: usize,
+    mut callback: CaptureCallback,
+) -> std::io::Result<Worker> {
+    Worker::spawn("llas-virtual-input", move |stop: Arc<AtomicBool>| {
+        let mut data = vec![0f32; TICK_FRAMES * channels];
+        // Sample frames generated so far, for the tone phase and clip position.
+        let mut position = 0usize;
+        run_clocked(&stop, || {
+            for (n, frame) in data.chunks_exact_mut(channels).enumerate() {
+                let (left, right) = match &signal {
+                    TestSignal::Silence => (0.0, 0.0),
+                    TestSignal::Tone(frequency) => {
+                        let t = (position + n) as f64 / SAMPLE_RATE as f64;
+                        let sample = (2.0 * std::f64::consts::PI * *frequency as f64 * t).sin() as f32 * TONE_LEVEL;
+                        (sample, sample)
+                    }
+                    TestSignal::Clip(clip) => {
+                        let index = (position + n) % (clip.len() / 2) * 2;
+                        (clip[index], clip[index + 1])
+                    }
+                };
+                if channels == 1 {
+                    frame[0] = (left + right) * 0.5;
+                } else {
+                    frame[0] = left;
+                    frame[1] = right;
+                }
+            }
+            position += TICK_FRAMES;
+            callback.process(&data);
+        });
+    })
+}
+
+pub fn spawn_virtual_output(
+    record: Option<PathBuf>,
+    channels: usize,
+    mut callback: PlaybackCallback,
+) -> Result<Worker, Box<dyn std::error::Error>> {
+    let mut wav = match &record {
+        Some(path) => {
+            let spec = WavSpec {
+                channels: channels as u16,
+                sample_rate: SAMPLE_RATE,
+                bits_per_sample: 16,
+                sample_format: SampleFormat::Int,
+            };
+            Some(WavWriter::<BufWriter<File>>::create(path, spec)?)
+        }
+        None => None,
+    };
+    let worker = Worker::spawn("llas-virtual-output", move |stop: Arc<AtomicBool>| {
+        let mut data = vec![0f32; TICK_FRAMES * channels];
+        run_clocked(&stop, || {
+            callback.process(&mut data);
+            if let Some(writer) = wav.as_mut() {
+                let written = data
+                    .iter()
+                    .try_for_each(|sample| writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16));
+                if let Err(e) = written {
+                    eprintln!("Error writing playback recording, stopping it: {}", e);
+                    wav = None;
+                }
+            }
+        });
+        if let Some(writer) = wav {
+            if let Err(e) = writer.finalize() {
+                eprintln!("Error finishing playback recording: {}", e);
+            }
+        }
+    })?;
+    Ok(worker)
+}
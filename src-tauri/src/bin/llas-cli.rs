@@ -0,0 +1,405 @@
+// src-tauri/src/bin/llas-cli.rs
+//
+// Headless client for bots and load tests. Runs one or more clients in a
+// room on virtual audio devices: each streams a test tone or a sound file
+// instead of a microphone, and the first can record what it hears to a WAV
+// file. Every line on stdout is a JSON object: one `joined` event, then a
+// `stats` event per client each interval.
+//
+// As in the app, rooms live in this process's RoomManager. The first client
+// creates the room, or joins it when `--state` holds a saved room of that
+// name, and the rest join it. Networking needs the same TURN_* environment
+// as the app.
+//
+//     llas-cli [--room NAME] [--password PASS] [--clients N]
+//              [--tone HZ | --wav FILE] [--record FILE] [--duration SECS]
+//              [--stats-interval SECS] [--sfu ADDR [--mix-key KEY]]
+//              [--state DIR]
+
+use llas_lib::audio::{AudioNetwork, AudioProcessor};
+use llas_lib::audio::crypto::{parse_public_key, Identity};
+use llas_lib::audio::file_source::load_clip;
+use llas_lib::audio::network::{DroppedPackets, NetworkStats};
+use llas_lib::audio::presence::PeerPresence;
+use llas_lib::audio::processor::AudioCallbackStats;
+use llas_lib::audio::sfu::SfuRoute;
+use llas_lib::audio::virtual_device::{TestSignal, VirtualDevices};
+use llas_lib::config::TurnConfig;
+use llas_lib::room::access::JoinCredentials;
+use llas_lib::room::{Room, RoomManager};
+use llas_lib::storage::{Storage, StoredState};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+const DEFAULT_ROOM: &str = "llas-cli";
+const DEFAULT_TONE_HZ: f32 = 440.0;
+const DEFAULT_STATS_INTERVAL: Duration = Duration::from_secs(1);
+const USAGE: &str = "usage: llas-cli [--room NAME] [--password PASS] [--clients N] \
+[--tone HZ | --wav FILE] [--record FILE] [--duration SECS] [--stats-interval SECS] \
+[--sfu ADDR [--mix-key KEY]] [--state DIR]";
+
+enum Source {
+    Tone(f32),
+    File(PathBuf),
+}
+
+struct Args {
+    room: String,
+    password: Option<String>,
+    clients: usize,
+    source: Source,
+    record: Option<PathBuf>,
+    duration: Option<Duration>,
+    stats_interval: Duration,
+    sfu: Option<SocketAddr>,
+    mix_key: Option<[u8; 32]>,
+    state: Option<PathBuf>,
+}
+
+fn parse_seconds(flag: &str, value: Option<String>) -> Result<Duration, String> {
+    let value = value.ok_or_else(|| format!("{} needs a number of seconds", flag))?;
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs > 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("Invalid {}: {}", flag, value))
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut parsed = Args {
+        room: DEFAULT_ROOM.to_string(),
+        password: None,
+        clients: 1,
+        source: Source::Tone(DEFAULT_TONE_HZ),
+        record: None,
+        duration: None,
+        stats_interval: DEFAULT_STATS_INTERVAL,
+        sfu: None,
+        mix_key: None,
+        state: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--room" => parsed.room = args.next().ok_or("--room needs a name")?,
+            "--password" => parsed.password = Some(args.next().ok_or("--password needs a password")?),
+            "--clients" => {
+                let value = args.next().ok_or("--clients needs a count")?;
+                parsed.clients = value
+                    .parse()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or_else(|| format!("Invalid client count: {}", value))?;
+            }
+            "--tone" => {
+                let value = args.next().ok_or("--tone needs a frequency")?;
+                let frequency = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|hz| *hz > 0.0 && *hz < 24000.0)
+                    .ok_or_else(|| format!("Invalid tone frequency: {}", value))?;
+                parsed.source = Source::Tone(frequency);
+            }
+            "--wav" => parsed.source = Source::File(args.next().ok_or("--wav needs a file")?.into()),
+            "--record" => parsed.record = Some(args.next().ok_or("--record needs a file")?.into()),
+            "--duration" => parsed.duration = Some(parse_seconds("--duration", args.next())?),
+            "--stats-interval" => parsed.stats_interval = parse_seconds("--stats-interval", args.next())?,
+            "--sfu" => {
+                let value = args.next().ok_or("--sfu needs an address")?;
+                parsed.sfu = Some(value.parse().map_err(|e| format!("Invalid server address: {}", e))?);
+            }
+            "--mix-key" => parsed.mix_key = Some(parse_public_key(&args.next().ok_or("--mix-key needs a key")?)?),
+            "--state" => parsed.state = Some(args.next().ok_or("--state needs a directory")?.into()),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+    if parsed.mix_key.is_some() && parsed.sfu.is_none() {
+        return Err("--mix-key needs --sfu".to_string());
+    }
+    Ok(parsed)
+}
+
+/// One simulated participant.
+struct Client {
+    user_id: Uuid,
+    addr: SocketAddr,
+    identity: Identity,
+    network: AudioNetwork,
+    processor: Option<AudioProcessor>,
+    // Latest quality figures for each peer we hear.
+    peer_stats: Arc<Mutex<HashMap<SocketAddr, NetworkStats>>>,
+}
+
+#[derive(Serialize)]
+struct ClientInfo {
+    user_id: Uuid,
+    addr: SocketAddr,
+}
+
+#[derive(Serialize)]
+struct PeerStats {
+    addr: SocketAddr,
+    latency_ms: f64,
+    jitter_ms: f64,
+    packet_loss: f32,
+    quality: String,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Report {
+    Joined {
+        room_id: Uuid,
+        room_name: String,
+        clients: Vec<ClientInfo>,
+    },
+    Stats {
+        elapsed_ms: u64,
+        client: usize,
+        user_id: Uuid,
+        peers: Vec<PeerStats>,
+        presence: Vec<PeerPresence>,
+        dropped: DroppedPackets,
+        callbacks: Option<AudioCallbackStats>,
+    },
+}
+
+fn print_report(report: &Report) {
+    match serde_json::to_string(report) {
+        Ok(line) => println!("{}", line),
+        Err(e) => eprintln!("Failed to serialize report: {}", e),
+    }
+}
+
+/// Puts every client in the room named `args.room`, creating it if there is
+/// none, and hands the room key to each of them. Returns the room as joined.
+fn join_clients(manager: &mut RoomManager, clients: &[Client], args: &Args) -> Result<Room, String> {
+    let existing = manager.list_rooms().into_iter().find(|room| room.name == args.room);
+    let room_id = match existing {
+        Some(room) => room.id,
+        None => {
+            let room = manager.create_room(args.room.clone(), clients[0].user_id);
+            manager.set_password(room.id, clients[0].user_id, args.password.as_deref())?;
+            room.id
+        }
+    };
+    let credentials = JoinCredentials { password: args.password.clone(), invite_code: None };
+    for (i, client) in clients.iter().enumerate() {
+        manager
+            .join_room(room_id, client.user_id, &credentials)
+            .map_err(|e| format!("Client {} couldn't join: {}", i, e))?;
+        manager.set_member_key(room_id, client.user_id, client.identity.public_key())?;
+    }
+    // Whoever holds the key (or mints it, in a fresh room) wraps it for the rest.
+    for client in clients {
+        manager.share_room_key(room_id, &client.identity)?;
+    }
+    manager.get_room(&room_id).cloned().ok_or_else(|| "Room not found".to_string())
+}
+
+/// Starts a client's audio engine on virtual devices and streams to the
+/// rest of the room until `cancel` fires.
+async fn start_client(
+    manager: &RoomManager,
+    client: &mut Client,
+    room: &Room,
+    devices: VirtualDevices,
+    args: &Args,
+    cancel: CancellationToken,
+) -> Result<(), String> {
+    let envelope = manager
+        .key_envelope(&room.id, &client.identity.public_key())
+        .ok_or("Nobody has shared the room key with this client")?;
+    let room_key = client.identity.unwrap_key(&envelope, room.id)?;
+
+    let (tx, rx) = mpsc::channel(32);
+    let processor = AudioProcessor::new(tx).map_err(|e| e.to_string())?;
+    processor.use_virtual_devices(devices).await.map_err(|e| e.to_string())?;
+    processor.set_audio_mode(room.audio_mode);
+    processor
+        .set_encoder_settings(room.encoder_settings.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    processor.setup_output_stream().await.map_err(|e| e.to_string())?;
+    processor.start_capture().await.map_err(|e| e.to_string())?;
+
+    for peer in manager.get_room_peers(&room.id) {
+        if peer != client.addr {
+            client.network.add_peer(peer);
+        }
+    }
+    let mix_key = match &args.mix_key {
+        Some(server_key) => Some(client.identity.wrap_key(server_key, room.id, &room_key)?),
+        None => None,
+    };
+    client.network.set_sfu(args.sfu.map(|server| SfuRoute { server, room_id: room.id, mix_key }));
+    client.network.set_room_key(Some(room_key));
+    client.network.start_streaming(rx, cancel.clone()).await?;
+    client.network.handle_incoming(processor.clone(), cancel.clone()).await?;
+
+    let mut stats_rx = client.network.subscribe_to_stats();
+    let peer_stats = client.peer_stats.clone();
+    tokio::spawn(async move {
+        loop {
+            let (peer, stats) = tokio::select! {
+                _ = cancel.cancelled() => break,
+                received = stats_rx.recv() => match received {
+                    Ok(update) => update,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
+            peer_stats.lock().insert(peer, stats);
+        }
+    });
+    client.processor = Some(processor);
+    Ok(())
+}
+
+fn report_stats(clients: &[Client], started: Instant) {
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    for (i, client) in clients.iter().enumerate() {
+        let mut peers: Vec<PeerStats> = client
+            .peer_stats
+            .lock()
+            .iter()
+            .map(|(addr, stats)| PeerStats {
+                addr: *addr,
+                latency_ms: stats.latency.as_secs_f64() * 1000.0,
+                jitter_ms: stats.jitter.as_secs_f64() * 1000.0,
+                packet_loss: stats.packet_loss,
+                quality: format!("{:?}", stats.connection_quality),
+            })
+            .collect();
+        peers.sort_by_key(|peer| peer.addr);
+        print_report(&Report::Stats {
+            elapsed_ms,
+            client: i,
+            user_id: client.user_id,
+            peers,
+            presence: client.network.peer_presence(),
+            dropped: client.network.dropped_packets(),
+            callbacks: client.processor.as_ref().map(AudioProcessor::callback_stats),
+        });
+    }
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    let signal = match &args.source {
+        Source::Tone(frequency) => TestSignal::Tone(*frequency),
+        Source::File(path) => TestSignal::Clip(
+            load_clip(path).map_err(|e| format!("Failed to load {}: {}", path.display(), e))?,
+        ),
+    };
+    let storage = args.state.as_deref().map(Storage::new);
+    let stored = match &storage {
+        Some(storage) => storage.load()?,
+        None => StoredState::default(),
+    };
+    // Clients keep their user across runs, so a saved room stays theirs.
+    let saved_users: HashMap<String, Uuid> = stored.rooms.users.iter().map(|user| (user.name.clone(), user.id)).collect();
+    let mut manager = RoomManager::from_snapshot(stored.rooms);
+
+    let mut clients = Vec::with_capacity(args.clients);
+    for i in 0..args.clients {
+        let network = AudioNetwork::new("0.0.0.0:0", TurnConfig::default())
+            .await
+            .map_err(|e| format!("Client {} couldn't connect: {}", i, e))?;
+        let addr = network.get_local_addr().map_err(|e| e.to_string())?;
+        let name = format!("llas-cli-{}", i);
+        let user_id = match saved_users.get(&name) {
+            Some(user_id) => *user_id,
+            None => manager.add_user(name).id,
+        };
+        manager.add_peer_address(user_id, addr)?;
+        clients.push(Client {
+            user_id,
+            addr,
+            identity: Identity::generate(),
+            network,
+            processor: None,
+            peer_stats: Arc::new(Mutex::new(HashMap::new())),
+        });
+    }
+    let room = join_clients(&mut manager, &clients, &args)?;
+    print_report(&Report::Joined {
+        room_id: room.id,
+        room_name: room.name.clone(),
+        clients: clients.iter().map(|client| ClientInfo { user_id: client.user_id, addr: client.addr }).collect(),
+    });
+
+    let cancel = CancellationToken::new();
+    let mut outcome = Ok(());
+    for (i, client) in clients.iter_mut().enumerate() {
+        // Only the first client records; the rest would hear the same room.
+        let devices = VirtualDevices {
+            input: signal.clone(),
+            record: if i == 0 { args.record.clone() } else { None },
+        };
+        if let Err(e) = start_client(&manager, client, &room, devices, &args, cancel.clone()).await {
+            outcome = Err(format!("Client {} couldn't start: {}", i, e));
+            break;
+        }
+    }
+
+    if outcome.is_ok() {
+        let started = Instant::now();
+        let mut stats = tokio::time::interval(args.stats_interval);
+        stats.tick().await;
+        let deadline = tokio::time::sleep(args.duration.unwrap_or(Duration::MAX));
+        tokio::pin!(deadline);
+        loop {
+            tokio::select! {
+                _ = stats.tick() => report_stats(&clients, started),
+                _ = &mut deadline, if args.duration.is_some() => break,
+                _ = tokio::signal::ctrl_c() => break,
+            }
+        }
+        report_stats(&clients, started);
+    }
+
+    // Peers are sent a bye as streaming stops; stopping the engines
+    // finishes the recording.
+    cancel.cancel();
+    for client in &clients {
+        if let Some(processor) = &client.processor {
+            processor.cleanup().await;
+        }
+        let _ = manager.leave_room(room.id, client.user_id);
+    }
+    if let Some(storage) = &storage {
+        let saved = StoredState { rooms: manager.snapshot(), settings: stored.settings };
+        if let Err(e) = storage.save(&saved) {
+            eprintln!("Failed to save state: {}", e);
+        }
+    }
+    outcome
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = run(args).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
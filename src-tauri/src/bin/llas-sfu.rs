@@ -0,0 +1,130 @@
+// src-tauri/src/bin/llas-sfu.rs
+//
+// Selective forwarding server. Clients that stream through it send each
+// frame once and it copies the frame to the rest of their room. Given an
+// identity file it can also mix a room into one stream per client, for
+// clients that ask and hand it the room key.
+//
+//     llas-sfu [--bind 0.0.0.0:7000] [--max-speakers N] [--identity FILE]
+
+use llas_lib::audio::crypto::{format_public_key, Identity};
+use llas_lib::audio::sfu::{serve, SfuSettings};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+const DEFAULT_BIND: &str = "0.0.0.0:7000";
+const USAGE: &str = "usage: llas-sfu [--bind ADDR] [--max-speakers N] [--identity FILE]";
+
+struct Args {
+    bind: String,
+    settings: SfuSettings,
+    identity: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut parsed = Args {
+        bind: DEFAULT_BIND.to_string(),
+        settings: SfuSettings::default(),
+        identity: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bind" => parsed.bind = args.next().ok_or("--bind needs an address")?,
+            "--max-speakers" => {
+                let value = args.next().ok_or("--max-speakers needs a count")?;
+                let count = value.parse().map_err(|_| format!("Invalid speaker count: {}", value))?;
+                if count == 0 {
+                    return Err("--max-speakers must be at least 1".to_string());
+                }
+                parsed.settings.max_speakers = Some(count);
+            }
+            "--identity" => parsed.identity = Some(args.next().ok_or("--identity needs a file")?),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Loads the server's key pair, creating it on first run so clients can
+/// keep trusting the same public key across restarts. The secret unwraps
+/// every room key clients hand us, so only its owner may read the file.
+fn load_identity(path: &Path) -> Result<Identity, String> {
+    match File::open(path) {
+        Ok(mut file) => {
+            check_private(&file, path)?;
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            let secret: [u8; 32] = bytes
+                .try_into()
+                .map_err(|_| format!("{} is not an identity file", path.display()))?;
+            Ok(Identity::from_secret(secret))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let identity = Identity::generate();
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options
+                .open(path)
+                .and_then(|mut file| file.write_all(&identity.secret_bytes()))
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            Ok(identity)
+        }
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(unix)]
+fn check_private(file: &File, path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = file
+        .metadata()
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        return Err(format!(
+            "{} is accessible to other users; restrict it with chmod 600",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_private(_file: &File, _path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let identity = match args.identity.as_deref().map(|path| load_identity(Path::new(path))).transpose() {
+        Ok(identity) => identity,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(identity) = &identity {
+        println!("Mixing enabled; server key: {}", format_public_key(&identity.public_key()));
+    }
+    if let Err(e) = serve(&args.bind, args.settings, identity).await {
+        eprintln!("Forwarding server stopped: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,3 +1,8 @@
+pub mod audio;
+pub mod config;
+pub mod room;
+pub mod storage;
+
 #[tauri::command]
 fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
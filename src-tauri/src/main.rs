@@ -1,19 +1,42 @@
 // src-tauri/src/main.rs
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-mod room;
-mod config;
-mod audio;
-
-use tauri::State;
+use tauri::{AppHandle, Emitter, Manager, State};
+use serde::Serialize;
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::Mutex; 
 use uuid::Uuid;
-use crate::room::{RoomManager, Room, User};
-use crate::audio::{AudioProcessor, AudioNetwork};
-use crate::config::TurnConfig;
-use tokio::sync::mpsc;
-use parking_lot::Mutex as PLMutex;
+use llas_lib::room::{RoomManager, Room, User};
+use llas_lib::room::access::{Invite, JoinCredentials, JoinError};
+use llas_lib::room::chat::ChatMessage;
+use llas_lib::room::moderation::Role;
+use llas_lib::room::whisper::WhisperTarget;
+use llas_lib::audio::{AudioProcessor, AudioNetwork, EngineLifecycle, EngineState};
+use llas_lib::audio::crypto::{parse_public_key, Identity, RoomKey};
+use llas_lib::audio::lifecycle::StartRequest;
+use llas_lib::audio::network::DroppedPackets;
+use llas_lib::audio::presence::{PeerPresence, PeerState};
+use llas_lib::audio::reconnect::LinkEvent;
+use llas_lib::audio::sfu::SfuRoute;
+use llas_lib::audio::spatial::{ListenerPose, Position, SpatialSettings};
+use llas_lib::audio::file_source::load_clip;
+use llas_lib::audio::loopback::LoopbackSettings;
+use llas_lib::audio::recorder::{RecordingMetadata, RecordingSettings, TrackSource};
+use llas_lib::audio::processor::AudioCallbackStats;
+use llas_lib::audio::codec::{AudioMode, EncoderSettings};
+use llas_lib::audio::dsp::{AgcSettings, DspControls, DspStageKind, DspStageSettings};
+use llas_lib::config::TurnConfig;
+use llas_lib::storage::{Settings, Storage, StoredState};
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::sync::CancellationToken;
+
+// How long a room may stay empty before it is deleted.
+const ROOM_IDLE_TIMEOUT: Duration = Duration::hours(24);
+const ROOM_EXPIRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
 
 type SafeAudioProcessor = Arc<Mutex<Option<AudioProcessor>>>;
 type SafeAudioNetwork = Arc<Mutex<Option<AudioNetwork>>>;
@@ -22,22 +45,70 @@ pub struct AppState {
     room_manager: Arc<Mutex<RoomManager>>,
     audio_processor: SafeAudioProcessor,
     network: SafeAudioNetwork,
+    engine: Arc<EngineLifecycle>,
+    // Standalone processor for the mic test when no call is running.
+    mic_test: SafeAudioProcessor,
+    // Serializes start/stop so each sees the other's finished state.
+    engine_ops: Mutex<()>,
+    // Our key pair for receiving room keys.
+    identity: Arc<Identity>,
+    // None when there is nowhere to save, or the saved state couldn't be
+    // read and must not be overwritten.
+    storage: Option<Mutex<Storage>>,
+    settings: Mutex<Settings>,
+    // Game positions of remote users and of us; live state, never saved.
+    user_positions: Mutex<HashMap<Uuid, Position>>,
+    listener_pose: Mutex<ListenerPose>,
 }
 
 impl AppState {
-    fn new() -> Self {
+    fn new(storage: Option<Storage>) -> Self {
+        let (stored, storage) = match storage.as_ref().map(Storage::load) {
+            Some(Ok(stored)) => (stored, storage),
+            Some(Err(e)) => {
+                eprintln!("Not saving changes, failed to load saved state: {}", e);
+                (StoredState::default(), None)
+            }
+            None => (StoredState::default(), None),
+        };
         Self {
-            room_manager: Arc::new(Mutex::new(RoomManager::new())),
+            room_manager: Arc::new(Mutex::new(RoomManager::from_snapshot(stored.rooms))),
             audio_processor: Arc::new(Mutex::new(None)),
             network: Arc::new(Mutex::new(None)),
+            engine: Arc::new(EngineLifecycle::new()),
+            mic_test: Arc::new(Mutex::new(None)),
+            engine_ops: Mutex::new(()),
+            identity: Arc::new(Identity::generate()),
+            storage: storage.map(Mutex::new),
+            settings: Mutex::new(stored.settings),
+            user_positions: Mutex::new(HashMap::new()),
+            listener_pose: Mutex::new(ListenerPose::default()),
         }
     }
 }
 
+/// Writes rooms, users and settings to disk. A failed save is logged; the
+/// in-memory state stays authoritative.
+async fn persist(state: &AppState) {
+    let Some(storage) = state.storage.as_ref() else {
+        return;
+    };
+    // Held across the snapshot so saves land in the order they were taken.
+    let storage = storage.lock().await;
+    let stored = StoredState {
+        rooms: state.room_manager.lock().await.snapshot(),
+        settings: state.settings.lock().await.clone(),
+    };
+    if let Err(e) = storage.save(&stored) {
+        eprintln!("Failed to save state: {}", e);
+    }
+}
+
 #[tauri::command]
 async fn add_user(state: State<'_, AppState>, name: String) -> Result<User, String> {
-    let mut manager = state.room_manager.lock().await;
-    Ok(manager.add_user(name))
+    let user = state.room_manager.lock().await.add_user(name);
+    persist(&state).await;
+    Ok(user)
 }
 
 #[tauri::command]
@@ -45,10 +116,33 @@ async fn create_room(
     state: State<'_, AppState>,
     name: String,
     user_id: String,
+    password: Option<String>,
+    max_participants: Option<usize>,
 ) -> Result<Room, String> {
-    let mut manager = state.room_manager.lock().await;
     let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
-    Ok(manager.create_room(name, user_id))
+    let room = {
+        let mut manager = state.room_manager.lock().await;
+        let room = manager.create_room(name, user_id);
+        // Applied before the lock is released, so the room is never open unprotected.
+        manager.set_password(room.id, user_id, password.as_deref())?;
+        manager.set_max_participants(room.id, user_id, max_participants)?;
+
+        // The creator mints the room's audio key and keeps the first copy.
+        manager.set_member_key(room.id, user_id, state.identity.public_key())?;
+        manager.share_room_key(room.id, &state.identity)?;
+        manager.get_room(&room.id).cloned().ok_or_else(|| "Room not found".to_string())?
+    };
+    persist(&state).await;
+    Ok(room)
+}
+
+/// Drops audio from participants the room has silenced.
+fn sync_room_mutes(net: &AudioNetwork, room: &Room) {
+    for participant in &room.participants {
+        if let Some(addr) = participant.peer_addr {
+            net.set_peer_muted(addr, !room.can_speak(participant.id));
+        }
+    }
 }
 
 async fn init_network(network: &SafeAudioNetwork) -> Result<(), String> {
@@ -69,10 +163,12 @@ async fn init_network(network: &SafeAudioNetwork) -> Result<(), String> {
 
 #[tauri::command]
 async fn join_room(
+    app: AppHandle,
     state: State<'_, AppState>,
     room_id: String,
     user_id: String,
-) -> Result<Room, String> {
+    credentials: Option<JoinCredentials>,
+) -> Result<Room, JoinError> {
     let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
     let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
     
@@ -87,139 +183,961 @@ async fn join_room(
             .map_err(|e| e.to_string())?
     };
 
-    {
+    let (room, history) = {
         let mut manager = state.room_manager.lock().await;
+        // Joining moves the user out of the room they were in.
+        let previous_peers = manager
+            .room_of(&user_id)
+            .filter(|previous| *previous != room_id)
+            .map(|previous| manager.get_room_peers(&previous))
+            .unwrap_or_default();
         manager.add_peer_address(user_id, peer_addr)?;
-        let room = manager.join_room(room_id, user_id)?;
+        manager.join_room(room_id, user_id, &credentials.unwrap_or_default())?;
+        manager.set_member_key(room_id, user_id, state.identity.public_key())?;
+        manager.share_room_key(room_id, &state.identity)?;
+        let room = manager.get_room(&room_id).cloned().ok_or(JoinError::RoomNotFound)?;
         
         // Add peers to network
         {
             let mut network = state.network.lock().await;
             if let Some(net) = network.as_mut() {
+                for peer in &previous_peers {
+                    net.remove_peer(peer);
+                }
                 for participant in &room.participants {
                     if let Some(participant_addr) = participant.peer_addr {
                         net.add_peer(participant_addr);
                     }
                 }
+                sync_room_mutes(net, &room);
             }
         }
-        Ok(room)
-    }
+        let history = manager.messages(room_id, user_id, None)?;
+        (room, history)
+    };
+    persist(&state).await;
+    let _ = app.emit("message-history", MessageHistoryEvent { room_id, messages: history });
+    Ok(room)
+}
+
+#[derive(Clone, Serialize)]
+struct MessageHistoryEvent {
+    room_id: Uuid,
+    messages: Vec<ChatMessage>,
 }
 
+/// Posts a chat message to the room. Everyone in the room gets it as a
+/// `message-received` event, in the order messages were accepted; peers
+/// get it over the call.
 #[tauri::command]
-async fn leave_room(
+async fn send_message(
+    app: AppHandle,
     state: State<'_, AppState>,
     room_id: String,
     user_id: String,
-) -> Result<(), String> {
+    text: String,
+) -> Result<ChatMessage, String> {
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
     let mut manager = state.room_manager.lock().await;
+    let message = manager.post_message(room_id, user_id, &text)?;
+    // Emitted under the lock so events leave in sequence order.
+    let _ = app.emit("message-received", message.clone());
+    if let Some(net) = state.network.lock().await.as_ref() {
+        // Without a call there is nobody to send it to.
+        let _ = net.send_chat(&message.text);
+    }
+    Ok(message)
+}
+
+/// The room's chat history, or only messages after sequence `after`.
+#[tauri::command]
+async fn get_messages(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    after: Option<u64>,
+) -> Result<Vec<ChatMessage>, String> {
     let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
     let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
-    manager.leave_room(room_id, user_id)
+    state.room_manager.lock().await.messages(room_id, user_id, after)
 }
 
 #[tauri::command]
-async fn list_rooms(state: State<'_, AppState>) -> Result<Vec<Room>, String> {
-    let manager = state.room_manager.lock().await;
-    Ok(manager.list_rooms())
+async fn leave_room(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+) -> Result<(), String> {
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    remove_member(&state, room_id, user_id, |manager| manager.leave_room(room_id, user_id)).await
 }
 
-async fn setup_processor(processor: &SafeAudioProcessor, tx: mpsc::Sender<Vec<u8>>) -> Result<(), String> {
-    let mut processor_lock = processor.lock().await;
-    if processor_lock.is_none() {
-        *processor_lock = Some(AudioProcessor::new(tx).map_err(|e| e.to_string())?);
+/// Runs `remove` on the room manager, which may take `user_id` out of
+/// `room_id`. Every way of leaving a room goes through here. If they are
+/// gone, we stop hearing them (the whole room, if we left with them), and
+/// when we stay the room key is rotated and the call follows it.
+async fn remove_member<T>(
+    state: &AppState,
+    room_id: Uuid,
+    user_id: Uuid,
+    remove: impl FnOnce(&mut RoomManager) -> Result<T, String>,
+) -> Result<T, String> {
+    let in_room = |manager: &RoomManager| {
+        manager
+            .get_room(&room_id)
+            .is_some_and(|room| room.participants.iter().any(|p| p.id == user_id))
+    };
+    let (result, removed, gone) = {
+        let mut manager = state.room_manager.lock().await;
+        let was_in_room = in_room(&manager);
+        let peers = manager.get_room_peers(&room_id);
+        let addr = manager.addr_for_user(&user_id);
+        let result = remove(&mut manager)?;
+        let removed = was_in_room && !in_room(&manager);
+        let gone = if !removed {
+            Vec::new()
+        } else if manager.has_member_key(&room_id, &state.identity.public_key()) {
+            addr.into_iter().collect()
+        } else {
+            peers
+        };
+        if removed {
+            manager.rotate_room_key(room_id, &state.identity)?;
+        }
+        (result, removed, gone)
+    };
+    persist(state).await;
+    forget_peers(state, &gone).await;
+    if removed {
+        follow_room_key(state, room_id).await?;
     }
+    Ok(result)
+}
 
-    // Get a reference to the processor
-    let processor_ref = processor_lock.as_mut().ok_or_else(|| "Processor not initialized".to_string())?;
-    
-    // Setup streams
-    processor_ref.setup_output_stream().await.map_err(|e| e.to_string())?;
-    processor_ref.start_capture().await.map_err(|e| e.to_string())
+/// Groups `member_ids` into a sub-channel of the room that can be whispered
+/// to as one.
+#[tauri::command]
+async fn create_sub_channel(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    name: String,
+    member_ids: Vec<String>,
+) -> Result<Room, String> {
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    let members = member_ids
+        .iter()
+        .map(|id| Uuid::parse_str(id).map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let room = state.room_manager.lock().await.create_sub_channel(room_id, user_id, name, members)?;
+    persist(&state).await;
+    Ok(room)
 }
 
 #[tauri::command]
-async fn start_streaming(
+async fn remove_sub_channel(
     state: State<'_, AppState>,
-    room_id: String
+    room_id: String,
+    user_id: String,
+    channel_id: String,
+) -> Result<Room, String> {
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    let channel_id = Uuid::parse_str(&channel_id).map_err(|e| e.to_string())?;
+    let room = state.room_manager.lock().await.remove_sub_channel(room_id, user_id, channel_id)?;
+    persist(&state).await;
+    Ok(room)
+}
+
+/// Routes our audio only to `target` until `stop_whisper`. Meant to be
+/// held for as long as the whisper hotkey is down.
+#[tauri::command]
+async fn start_whisper(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    target: WhisperTarget,
 ) -> Result<(), String> {
-    println!("Starting streaming for room: {}", room_id);
-    let (tx, rx) = mpsc::channel(32);
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    let addrs = state.room_manager.lock().await.whisper_addrs(room_id, user_id, &target)?;
+    let network = state.network.lock().await;
+    let net = network.as_ref().ok_or("Not streaming")?;
+    net.set_whisper_targets(Some(addrs));
+    Ok(())
+}
 
-    // Initialize audio processor if not already initialized
-    {
-        let mut processor = state.audio_processor.lock().await;
-        if processor.is_none() {
-            println!("Initializing audio processor");
-            let (audio_tx, _) = mpsc::channel(32); // Create a separate channel for the audio processor
-            *processor = Some(AudioProcessor::new(audio_tx).map_err(|e| e.to_string())?);
-            println!("Audio processor initialized successfully");
+/// Goes back to talking to the whole room.
+#[tauri::command]
+async fn stop_whisper(state: State<'_, AppState>) -> Result<(), String> {
+    if let Some(net) = state.network.lock().await.as_ref() {
+        net.set_whisper_targets(None);
+    }
+    Ok(())
+}
+
+/// Stops accepting audio from a room's members.
+async fn forget_peers(state: &AppState, peers: &[SocketAddr]) {
+    if let Some(net) = state.network.lock().await.as_mut() {
+        for peer in peers {
+            net.remove_peer(peer);
         }
     }
-    
-    println!("Setting up processor with channel");
-    // Setup processor with the channel
-    setup_processor(&state.audio_processor, tx).await?;
-    println!("Processor setup complete");
-    
+}
+
+/// Takes a user who is going away (signing out, closing the app) out of
+/// their room.
+#[tauri::command]
+async fn disconnect_user(state: State<'_, AppState>, user_id: String) -> Result<(), String> {
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    disconnect(&state, user_id).await
+}
+
+/// Takes `user_id` out of their room, if they are in one, and forgets
+/// their address.
+async fn disconnect(state: &AppState, user_id: Uuid) -> Result<(), String> {
+    let room_id = state.room_manager.lock().await.room_of(&user_id);
+    let disconnect = |manager: &mut RoomManager| {
+        manager.disconnect_user(user_id);
+        Ok(())
+    };
+    match room_id {
+        Some(room_id) => remove_member(state, room_id, user_id, disconnect).await,
+        None => {
+            disconnect(&mut *state.room_manager.lock().await)?;
+            persist(state).await;
+            Ok(())
+        }
+    }
+}
+
+#[tauri::command]
+async fn set_room_password(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    password: Option<String>,
+) -> Result<Room, String> {
     let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
-    let peers = {
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    let room = state.room_manager.lock().await.set_password(room_id, user_id, password.as_deref())?;
+    persist(&state).await;
+    Ok(room)
+}
+
+#[tauri::command]
+async fn set_max_participants(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    max_participants: Option<usize>,
+) -> Result<Room, String> {
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    let room = state.room_manager.lock().await.set_max_participants(room_id, user_id, max_participants)?;
+    persist(&state).await;
+    Ok(room)
+}
+
+/// Creates an invite code that lets its holder skip the room password.
+/// Expires after `valid_minutes`, a day by default.
+#[tauri::command]
+async fn create_invite(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    valid_minutes: Option<u32>,
+) -> Result<Invite, String> {
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    let expires_at = Utc::now() + Duration::minutes(valid_minutes.unwrap_or(24 * 60) as i64);
+    let invite = state.room_manager.lock().await.create_invite(room_id, user_id, expires_at)?;
+    persist(&state).await;
+    Ok(invite)
+}
+
+/// Runs a moderation action by `user_id` on `target_id`, then stops
+/// accepting audio from whoever it removed or silenced. Removing someone
+/// rotates the room key.
+async fn moderate(
+    state: &AppState,
+    room_id: &str,
+    user_id: &str,
+    target_id: &str,
+    action: impl FnOnce(&mut RoomManager, Uuid, Uuid, Uuid) -> Result<Room, String>,
+) -> Result<Room, String> {
+    let room_id = Uuid::parse_str(room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(user_id).map_err(|e| e.to_string())?;
+    let target_id = Uuid::parse_str(target_id).map_err(|e| e.to_string())?;
+    remove_member(state, room_id, target_id, |manager| action(manager, room_id, user_id, target_id)).await?;
+    // Fetched again, since removing someone rotates the key.
+    let room = state.room_manager.lock().await.get_room(&room_id).cloned().ok_or("Room not found")?;
+    if let Some(net) = state.network.lock().await.as_mut() {
+        sync_room_mutes(net, &room);
+    }
+    Ok(room)
+}
+
+/// Switches a running call in `room_id` over to the room's current key,
+/// after it was rotated. Nothing to do once we are out of the room.
+async fn follow_room_key(state: &AppState, room_id: Uuid) -> Result<(), String> {
+    let envelope = state.room_manager.lock().await.key_envelope(&room_id, &state.identity.public_key());
+    let Some(envelope) = envelope else {
+        return Ok(());
+    };
+    let room_key = state.identity.unwrap_key(&envelope, room_id)?;
+    let sfu_route = sfu_route(state, room_id, &room_key).await?;
+    if let Some(net) = state.network.lock().await.as_mut() {
+        // Route first, so a mixing server is handed the new key with it.
+        net.set_sfu(sfu_route);
+        net.set_room_key(Some(room_key));
+    }
+    Ok(())
+}
+
+/// How to reach the forwarding server for `room_id`, if we use one, with
+/// the room key wrapped for it when it should mix.
+async fn sfu_route(state: &AppState, room_id: Uuid, room_key: &RoomKey) -> Result<Option<SfuRoute>, String> {
+    let settings = state.settings.lock().await;
+    let Some(server) = settings.sfu_server else {
+        return Ok(None);
+    };
+    let mix_key = match &settings.sfu_mix_key {
+        Some(server_key) => Some(state.identity.wrap_key(&parse_public_key(server_key)?, room_id, room_key)?),
+        None => None,
+    };
+    Ok(Some(SfuRoute { server, room_id, mix_key }))
+}
+
+#[tauri::command]
+async fn kick_participant(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    target_id: String,
+) -> Result<Room, String> {
+    moderate(&state, &room_id, &user_id, &target_id, |manager, room, actor, target| {
+        manager.kick(room, actor, target)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn ban_participant(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    target_id: String,
+) -> Result<Room, String> {
+    moderate(&state, &room_id, &user_id, &target_id, |manager, room, actor, target| {
+        manager.ban(room, actor, target)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn unban_participant(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    target_id: String,
+) -> Result<Room, String> {
+    moderate(&state, &room_id, &user_id, &target_id, |manager, room, actor, target| {
+        manager.unban(room, actor, target)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn set_force_muted(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    target_id: String,
+    muted: bool,
+) -> Result<Room, String> {
+    moderate(&state, &room_id, &user_id, &target_id, |manager, room, actor, target| {
+        manager.set_force_muted(room, actor, target, muted)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn set_participant_role(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    target_id: String,
+    role: Role,
+) -> Result<Room, String> {
+    moderate(&state, &room_id, &user_id, &target_id, |manager, room, actor, target| {
+        manager.set_role(room, actor, target, role)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn transfer_ownership(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    target_id: String,
+) -> Result<Room, String> {
+    moderate(&state, &room_id, &user_id, &target_id, |manager, room, actor, target| {
+        manager.transfer_ownership(room, actor, target)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn list_rooms(state: State<'_, AppState>) -> Result<Vec<Room>, String> {
+    let manager = state.room_manager.lock().await;
+    Ok(manager.list_rooms())
+}
+
+/// Puts the saved input device, volume, capture stages and gain control on
+/// a fresh processor.
+async fn apply_saved_input(state: &AppState, processor: &AudioProcessor) -> Result<(), String> {
+    let settings = state.settings.lock().await.clone();
+    processor.set_input_device(settings.input_device).await.map_err(|e| e.to_string())?;
+    if let Some(volume) = settings.input_volume {
+        processor.set_input_volume(volume).map_err(|e| e.to_string())?;
+    }
+    for stage in settings.dsp_stages {
+        processor.set_dsp_stage(stage.stage, stage.enabled, stage.strength);
+    }
+    processor.set_agc_settings(settings.agc);
+    Ok(())
+}
+
+async fn setup_processor(
+    state: &AppState,
+    room_id: Uuid,
+    tx: mpsc::Sender<Vec<u8>>,
+) -> Result<(AudioProcessor, Vec<SocketAddr>), String> {
+    let (peers, encoder_settings, audio_mode) = {
         let manager = state.room_manager.lock().await;
         let peers = manager.get_room_peers(&room_id);
         println!("Found {} peers in room", peers.len());
-        peers
+        let room = manager.get_room(&room_id);
+        (
+            peers,
+            room.map(|r| r.encoder_settings.clone()).unwrap_or_default(),
+            room.map(|r| r.audio_mode).unwrap_or_default(),
+        )
     };
 
+    // A call needs the devices the standalone mic test is holding.
+    if let Some(mic_test) = state.mic_test.lock().await.take() {
+        mic_test.cleanup().await;
+    }
+
+    println!("Initializing audio processor");
+    let processor = AudioProcessor::new(tx).map_err(|e| e.to_string())?;
+    *state.audio_processor.lock().await = Some(processor.clone());
+
+    // Everyone in the room encodes with the room's profile.
+    processor.set_audio_mode(audio_mode);
+    processor.set_encoder_settings(encoder_settings).await.map_err(|e| e.to_string())?;
+
+    apply_saved_input(state, &processor).await?;
+
+    // Setup streams
+    processor.setup_output_stream().await.map_err(|e| e.to_string())?;
+    processor.start_capture().await.map_err(|e| e.to_string())?;
+    Ok((processor, peers))
+}
+
+async fn run_engine(
+    app: &AppHandle,
+    state: &AppState,
+    room_id: Uuid,
+    cancel: CancellationToken,
+) -> Result<(), String> {
+    let (room, room_key) = {
+        let manager = state.room_manager.lock().await;
+        let room = manager.get_room(&room_id).cloned().ok_or("Room not found")?;
+        let envelope = manager
+            .key_envelope(&room_id, &state.identity.public_key())
+            .ok_or("No participant has shared this room's key with us yet")?;
+        (room, state.identity.unwrap_key(&envelope, room_id)?)
+    };
+
+    let (tx, rx) = mpsc::channel(32);
+    let (processor, peers) = setup_processor(state, room_id, tx).await?;
+    println!("Processor setup complete");
+
     // Initialize network if not already initialized
     println!("Initializing network");
     init_network(&state.network).await?;
     println!("Network initialized");
 
     let mut network = state.network.lock().await;
-    if let Some(net) = network.as_mut() {
-        for peer_addr in peers {
-            println!("Adding peer: {}", peer_addr);
-            net.add_peer(peer_addr);
+    let net = network.as_mut().ok_or_else(|| "Network not initialized".to_string())?;
+    for peer_addr in peers {
+        println!("Adding peer: {}", peer_addr);
+        net.add_peer(peer_addr);
+    }
+    sync_room_mutes(net, &room);
+    let user_volumes = state.settings.lock().await.user_volumes.clone();
+    for participant in &room.participants {
+        if let (Some(addr), Some(volume)) = (participant.peer_addr, user_volumes.get(&participant.id)) {
+            processor.set_peer_volume(addr, *volume);
         }
-        println!("Starting audio streaming");
-        net.start_streaming(rx).await;
-        println!("Audio streaming started");
-        
-        // Get the processor reference
-        let processor = {
-            let guard = state.audio_processor.lock().await;
-            guard.as_ref().ok_or_else(|| "Processor not initialized".to_string())?.clone()
-        };
-        
-        // Create a new Arc<Mutex<AudioProcessor>> for the network
-        let network_processor = Arc::new(PLMutex::new(processor));
-        println!("Starting to handle incoming audio");
-        net.handle_incoming(network_processor).await;
-        println!("Handling incoming audio started");
     }
-    
+    processor.set_spatial_settings(state.settings.lock().await.spatial);
+    processor.set_listener(*state.listener_pose.lock().await);
+    let user_positions = state.user_positions.lock().await.clone();
+    for participant in &room.participants {
+        if let (Some(addr), Some(position)) = (participant.peer_addr, user_positions.get(&participant.id)) {
+            processor.set_peer_position(addr, Some(*position));
+        }
+    }
+    net.set_sfu(sfu_route(state, room_id, &room_key).await?);
+    net.set_room_key(Some(room_key));
+    println!("Starting audio streaming");
+    net.start_streaming(rx, cancel.clone()).await?;
+    // The network gets its own handle to the same engine.
+    println!("Starting to handle incoming audio");
+    net.handle_incoming(processor.clone(), cancel.clone()).await?;
+    watch_presence(app, net, processor.clone(), cancel.clone());
+    net.supervise(cancel.clone())?;
+    watch_link(app, net, processor.clone(), cancel.clone());
+    watch_moves(app, net, processor, cancel.clone());
+    watch_whispers(app, net, cancel.clone());
+    watch_chat(app, net, cancel.clone());
+
+    // Surface peers' recording indicators to the UI.
+    let mut recording_rx = net.subscribe_to_recording();
+    let room_manager = state.room_manager.clone();
+    let app = app.clone();
+    tokio::spawn(async move {
+        loop {
+            let (peer, recording) = tokio::select! {
+                _ = cancel.cancelled() => break,
+                received = recording_rx.recv() => match received {
+                    Ok(update) => update,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
+            let user_id = room_manager.lock().await.user_for_addr(&peer);
+            let _ = app.emit("peer-recording-changed", PeerRecordingEvent { peer, user_id, recording });
+        }
+    });
+    Ok(())
+}
+
+/// Forwards peers' presence to the UI. A peer that timed out is taken out
+/// of its room as if it had left.
+fn watch_presence(app: &AppHandle, net: &AudioNetwork, processor: AudioProcessor, cancel: CancellationToken) {
+    let mut presence_rx = net.subscribe_to_presence();
+    let app = app.clone();
+    tokio::spawn(async move {
+        loop {
+            let (peer, presence) = tokio::select! {
+                _ = cancel.cancelled() => break,
+                received = presence_rx.recv() => match received {
+                    Ok(update) => update,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
+            let state = app.state::<AppState>();
+            let user_id = state.room_manager.lock().await.user_for_addr(&peer);
+            if presence == PeerState::Disconnected {
+                processor.remove_peer(peer);
+                if let Some(user_id) = user_id {
+                    if let Err(e) = disconnect(&state, user_id).await {
+                        eprintln!("Error removing {} after they timed out: {}", peer, e);
+                    }
+                }
+            }
+            let _ = app.emit("peer-presence-changed", PeerPresenceEvent { peer, user_id, state: presence });
+        }
+    });
+}
+
+/// Tells the UI who is whispering to us, so it can show it.
+fn watch_whispers(app: &AppHandle, net: &AudioNetwork, cancel: CancellationToken) {
+    let mut whisper_rx = net.subscribe_to_whispers();
+    let app = app.clone();
+    tokio::spawn(async move {
+        loop {
+            let (peer, whispering) = tokio::select! {
+                _ = cancel.cancelled() => break,
+                received = whisper_rx.recv() => match received {
+                    Ok(update) => update,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
+            let user_id = app.state::<AppState>().room_manager.lock().await.user_for_addr(&peer);
+            let _ = app.emit("peer-whisper-changed", PeerWhisperEvent { peer, user_id, whispering });
+        }
+    });
+}
+
+/// Adds peers' chat to their room's history and passes it on to the UI.
+fn watch_chat(app: &AppHandle, net: &AudioNetwork, cancel: CancellationToken) {
+    let mut chat_rx = net.subscribe_to_chat();
+    let app = app.clone();
+    tokio::spawn(async move {
+        loop {
+            let (peer, text) = tokio::select! {
+                _ = cancel.cancelled() => break,
+                received = chat_rx.recv() => match received {
+                    Ok(chat) => chat,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
+            let state = app.state::<AppState>();
+            let mut manager = state.room_manager.lock().await;
+            let Some(user_id) = manager.user_for_addr(&peer) else {
+                continue;
+            };
+            let Some(room_id) = manager.room_of(&user_id) else {
+                continue;
+            };
+            match manager.post_message(room_id, user_id, &text) {
+                // Emitted under the lock so events leave in sequence order.
+                Ok(message) => {
+                    let _ = app.emit("message-received", message);
+                }
+                Err(e) => eprintln!("Dropped chat from {}: {}", peer, e),
+            }
+        }
+    });
+}
+
+/// Forwards reconnects to the UI. Once the network has a new address, our
+/// users are known by it here, the network tells the room about it, and
+/// streaming carries on without a rejoin.
+fn watch_link(app: &AppHandle, net: &AudioNetwork, processor: AudioProcessor, cancel: CancellationToken) {
+    let mut link_rx = net.subscribe_to_link();
+    let app = app.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = tokio::select! {
+                _ = cancel.cancelled() => break,
+                received = link_rx.recv() => match received {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
+            if let LinkEvent::Reconnected { old_addr, new_addr } = &event {
+                let state = app.state::<AppState>();
+                state.room_manager.lock().await.readdress(*old_addr, *new_addr);
+                if let Some(net) = state.network.lock().await.as_mut() {
+                    net.remove_peer(old_addr);
+                    net.add_peer(*new_addr);
+                }
+                processor.remove_peer(*old_addr);
+                persist(&state).await;
+            }
+            let _ = app.emit("connection-changed", event);
+        }
+    });
+}
+
+/// Follows members who reconnected from a new address: the room knows them
+/// by it from then on, and they keep the volume and placement we gave them.
+fn watch_moves(app: &AppHandle, net: &AudioNetwork, processor: AudioProcessor, cancel: CancellationToken) {
+    let mut moved_rx = net.subscribe_to_moves();
+    let app = app.clone();
+    tokio::spawn(async move {
+        loop {
+            let (old, new) = tokio::select! {
+                _ = cancel.cancelled() => break,
+                received = moved_rx.recv() => match received {
+                    Ok(moved) => moved,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
+            let state = app.state::<AppState>();
+            let users = state.room_manager.lock().await.readdress(old, new);
+            processor.remove_peer(old);
+            let user_volumes = state.settings.lock().await.user_volumes.clone();
+            let user_positions = state.user_positions.lock().await.clone();
+            for user_id in &users {
+                if let Some(volume) = user_volumes.get(user_id) {
+                    processor.set_peer_volume(new, *volume);
+                }
+                if let Some(position) = user_positions.get(user_id) {
+                    processor.set_peer_position(new, Some(*position));
+                }
+            }
+            persist(&state).await;
+            let _ = app.emit("peer-moved", PeerMovedEvent { old, new, user_id: users.first().copied() });
+        }
+    });
+}
+
+/// Stops the session's tasks and releases the audio devices. The network is
+/// kept so our address stays valid for the rooms we are in.
+async fn teardown_engine(state: &AppState) {
+    state.engine.begin_stop();
+    let processor = state.audio_processor.lock().await.take();
+    if let Some(proc) = processor {
+        // Finalize a running recording rather than leaving it without metadata.
+        proc.stop_recording();
+        proc.cleanup().await;
+    }
+    if let Some(net) = state.network.lock().await.as_ref() {
+        net.set_recording(false);
+    }
+    state.engine.finish_stop();
+}
+
+#[tauri::command]
+async fn start_streaming(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    room_id: String
+) -> Result<(), String> {
+    println!("Starting streaming for room: {}", room_id);
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let _ops = state.engine_ops.lock().await;
+    let cancel = match state.engine.begin_start()? {
+        StartRequest::Start(cancel) => cancel,
+        StartRequest::AlreadyRunning => return Ok(()),
+    };
+
+    if let Err(e) = run_engine(&app, &state, room_id, cancel).await {
+        teardown_engine(&state).await;
+        return Err(e);
+    }
+    state.engine.finish_start()?;
     println!("Streaming setup complete");
     Ok(())
 }
 
 #[tauri::command]
 async fn stop_streaming(state: State<'_, AppState>) -> Result<(), String> {
-    let mut network = state.network.lock().await;
-    let mut processor = state.audio_processor.lock().await;
-    *network = None;
-    *processor = None;
+    let _ops = state.engine_ops.lock().await;
+    if state.engine.state() == EngineState::Idle {
+        return Ok(());
+    }
+    teardown_engine(&state).await;
+    Ok(())
+}
+
+/// Lets users hear themselves as peers would. During a call the loopback
+/// joins the call's mix; otherwise a standalone processor is started for it.
+#[tauri::command]
+async fn start_mic_test(
+    state: State<'_, AppState>,
+    settings: Option<LoopbackSettings>,
+) -> Result<(), String> {
+    let settings = settings.unwrap_or_default();
+    if let Some(proc) = state.audio_processor.lock().await.as_ref() {
+        return proc.start_loopback(settings).map_err(|e| e.to_string());
+    }
+
+    let mut mic_test = state.mic_test.lock().await;
+    if mic_test.is_none() {
+        // Nothing sends the encoded stream anywhere but the loopback.
+        let (tx, _) = mpsc::channel(1);
+        let proc = AudioProcessor::new(tx).map_err(|e| e.to_string())?;
+        apply_saved_input(&state, &proc).await?;
+        proc.setup_output_stream().await.map_err(|e| e.to_string())?;
+        proc.start_capture().await.map_err(|e| e.to_string())?;
+        *mic_test = Some(proc);
+    }
+    if let Some(proc) = mic_test.as_ref() {
+        proc.start_loopback(settings).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn stop_mic_test(state: State<'_, AppState>) -> Result<(), String> {
+    if let Some(proc) = state.audio_processor.lock().await.as_ref() {
+        proc.stop_loopback();
+    }
+    let mic_test = state.mic_test.lock().await.take();
+    if let Some(proc) = mic_test {
+        proc.cleanup().await;
+    }
+    Ok(())
+}
+
+/// The call's processor, or the mic test's when no call is running.
+async fn active_processor(state: &AppState) -> Option<AudioProcessor> {
+    if let Some(proc) = state.audio_processor.lock().await.clone() {
+        return Some(proc);
+    }
+    state.mic_test.lock().await.clone()
+}
+
+/// Decodes `path` and mixes it into our outgoing audio. Returns an id that
+/// `stop_sound` accepts.
+#[tauri::command]
+async fn play_sound(state: State<'_, AppState>, path: String) -> Result<String, String> {
+    let processor = active_processor(&state)
+        .await
+        .ok_or_else(|| "Start streaming or the mic test before playing sounds".to_string())?;
+    let path = PathBuf::from(path);
+    let clip = tokio::task::spawn_blocking(move || load_clip(&path).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| e.to_string())??;
+    Ok(processor.play_clip(clip).to_string())
+}
+
+/// Stops one sound, or every playing sound when `sound_id` is omitted.
+#[tauri::command]
+async fn stop_sound(state: State<'_, AppState>, sound_id: Option<String>) -> Result<(), String> {
+    let sound_id = sound_id
+        .map(|id| Uuid::parse_str(&id).map_err(|e| e.to_string()))
+        .transpose()?;
+    if let Some(proc) = active_processor(&state).await {
+        proc.stop_clip(sound_id);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_soundboard_volume(state: State<'_, AppState>, volume: f32) -> Result<(), String> {
+    if let Some(proc) = active_processor(&state).await {
+        proc.set_soundboard_volume(volume);
+    }
     Ok(())
 }
 
+#[tauri::command]
+async fn set_soundboard_monitoring(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    if let Some(proc) = active_processor(&state).await {
+        proc.set_soundboard_monitoring(enabled);
+    }
+    Ok(())
+}
+
+#[derive(Clone, Serialize)]
+struct PeerRecordingEvent {
+    peer: SocketAddr,
+    user_id: Option<Uuid>,
+    recording: bool,
+}
+
+#[derive(Clone, Serialize)]
+struct PeerPresenceEvent {
+    peer: SocketAddr,
+    user_id: Option<Uuid>,
+    state: PeerState,
+}
+
+#[derive(Clone, Serialize)]
+struct PeerMovedEvent {
+    old: SocketAddr,
+    new: SocketAddr,
+    user_id: Option<Uuid>,
+}
+
+#[derive(Clone, Serialize)]
+struct PeerWhisperEvent {
+    peer: SocketAddr,
+    user_id: Option<Uuid>,
+    whispering: bool,
+}
+
+/// Records the room we are streaming into the app data directory and flags
+/// our packets so every peer shows the recording indicator.
+#[tauri::command]
+async fn start_recording(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    settings: RecordingSettings,
+) -> Result<Room, String> {
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    let processor = state
+        .audio_processor
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| "Start streaming before recording".to_string())?;
+
+    let mut manager = state.room_manager.lock().await;
+    let room = manager.get_room(&room_id).cloned().ok_or("Room not found")?;
+    let me = room
+        .participants
+        .iter()
+        .find(|p| p.id == user_id)
+        .ok_or("Only participants can record a room")?;
+
+    let mut track_names: HashMap<TrackSource, String> = room
+        .participants
+        .iter()
+        .filter_map(|p| p.peer_addr.map(|addr| (TrackSource::Peer(addr), p.name.clone())))
+        .collect();
+    track_names.insert(TrackSource::Local, me.name.clone());
+    let metadata = RecordingMetadata {
+        room_id,
+        room_name: room.name.clone(),
+        room_created_at: room.created_at,
+        participants: room.participants.iter().map(|p| p.name.clone()).collect(),
+        started_at: Utc::now(),
+        ended_at: None,
+        format: settings.format,
+        layout: settings.layout,
+        files: Vec::new(),
+    };
+    let directory = app.path().app_data_dir().map_err(|e| e.to_string())?.join("recordings");
+    processor
+        .start_recording(settings, metadata, directory, track_names)
+        .map_err(|e| e.to_string())?;
+
+    if let Some(net) = state.network.lock().await.as_ref() {
+        net.set_recording(true);
+    }
+    let room = manager.set_recording(room_id, user_id, true)?;
+    let _ = app.emit("recording-state-changed", &room);
+    Ok(room)
+}
+
+#[tauri::command]
+async fn stop_recording(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+) -> Result<Option<RecordingMetadata>, String> {
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    let processor = state.audio_processor.lock().await.clone();
+    let metadata = processor.and_then(|proc| proc.stop_recording());
+
+    if let Some(net) = state.network.lock().await.as_ref() {
+        net.set_recording(false);
+    }
+    let room = state.room_manager.lock().await.set_recording(room_id, user_id, false)?;
+    let _ = app.emit("recording-state-changed", &room);
+    Ok(metadata)
+}
+
+#[tauri::command]
+async fn get_engine_state(state: State<'_, AppState>) -> Result<EngineState, String> {
+    Ok(state.engine.state())
+}
+
 #[tauri::command]
 async fn set_input_device(
     state: State<'_, AppState>,
     device_id: String
 ) -> Result<(), String> {
+    state.settings.lock().await.input_device = Some(device_id.clone());
+    persist(&state).await;
     let mut processor_lock = state.audio_processor.lock().await;
     if let Some(proc) = processor_lock.as_mut() {
-        proc.set_input_device(&device_id).await.map_err(|e| e.to_string())?;
+        proc.set_input_device(Some(device_id)).await.map_err(|e| e.to_string())?;
     }
     Ok(())
 }
@@ -229,6 +1147,8 @@ async fn set_input_volume(
     state: State<'_, AppState>,
     volume: f32
 ) -> Result<(), String> {
+    state.settings.lock().await.input_volume = Some(volume);
+    persist(&state).await;
     let mut processor_lock = state.audio_processor.lock().await;
     if let Some(proc) = processor_lock.as_mut() {
         proc.set_input_volume(volume).map_err(|e| e.to_string())?;
@@ -236,6 +1156,31 @@ async fn set_input_volume(
     Ok(())
 }
 
+/// Streams through the forwarding server at `address` from the next call
+/// on, or straight to every peer for `None`. Passing the server's public key
+/// as `mix_key` has it mix the room into a single stream for us instead,
+/// which means trusting it with the room key.
+#[tauri::command]
+async fn set_sfu_server(
+    state: State<'_, AppState>,
+    address: Option<String>,
+    mix_key: Option<String>
+) -> Result<(), String> {
+    let server = address
+        .map(|address| address.parse::<SocketAddr>().map_err(|e| format!("Invalid server address: {}", e)))
+        .transpose()?;
+    if let Some(key) = &mix_key {
+        parse_public_key(key)?;
+    }
+    {
+        let mut settings = state.settings.lock().await;
+        settings.sfu_server = server;
+        settings.sfu_mix_key = mix_key.filter(|_| server.is_some());
+    }
+    persist(&state).await;
+    Ok(())
+}
+
 #[tauri::command]
 async fn set_muted(
     state: State<'_, AppState>,
@@ -251,31 +1196,293 @@ async fn set_muted(
 #[tauri::command]
 async fn set_user_volume(
     state: State<'_, AppState>,
-    _user_id: String, // unused for now
+    user_id: String,
     volume: f32
 ) -> Result<(), String> {
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    state.settings.lock().await.user_volumes.insert(user_id, volume);
+    persist(&state).await;
+    let addr = state.room_manager.lock().await.addr_for_user(&user_id);
+    let processor_lock = state.audio_processor.lock().await;
+    if let (Some(proc), Some(addr)) = (processor_lock.as_ref(), addr) {
+        proc.set_peer_volume(addr, volume);
+    }
+    Ok(())
+}
+
+/// Places a remote user in the game world for spatial audio, or takes them
+/// out of it with `None` so they play unplaced.
+#[tauri::command]
+async fn set_user_position(
+    state: State<'_, AppState>,
+    user_id: String,
+    position: Option<Position>
+) -> Result<(), String> {
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    {
+        let mut positions = state.user_positions.lock().await;
+        match position {
+            Some(position) => positions.insert(user_id, position),
+            None => positions.remove(&user_id),
+        };
+    }
+    let addr = state.room_manager.lock().await.addr_for_user(&user_id);
+    let processor_lock = state.audio_processor.lock().await;
+    if let (Some(proc), Some(addr)) = (processor_lock.as_ref(), addr) {
+        proc.set_peer_position(addr, position);
+    }
+    Ok(())
+}
+
+/// Where we stand and which way we face in the game world.
+#[tauri::command]
+async fn set_listener_pose(
+    state: State<'_, AppState>,
+    pose: ListenerPose
+) -> Result<(), String> {
+    *state.listener_pose.lock().await = pose;
+    let processor_lock = state.audio_processor.lock().await;
+    if let Some(proc) = processor_lock.as_ref() {
+        proc.set_listener(pose);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_spatial_settings(
+    state: State<'_, AppState>,
+    settings: SpatialSettings
+) -> Result<(), String> {
+    settings.validate()?;
+    state.settings.lock().await.spatial = settings;
+    persist(&state).await;
+    let processor_lock = state.audio_processor.lock().await;
+    if let Some(proc) = processor_lock.as_ref() {
+        proc.set_spatial_settings(settings);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_spatial_settings(state: State<'_, AppState>) -> Result<SpatialSettings, String> {
+    Ok(state.settings.lock().await.spatial)
+}
+
+#[tauri::command]
+async fn set_dsp_stage(
+    state: State<'_, AppState>,
+    stage: DspStageKind,
+    enabled: bool,
+    strength: f32
+) -> Result<(), String> {
+    let strength = strength.clamp(0.0, 1.0);
+    {
+        let mut settings = state.settings.lock().await;
+        settings.dsp_stages.retain(|saved| saved.stage != stage);
+        settings.dsp_stages.push(DspStageSettings { stage, enabled, strength });
+    }
+    persist(&state).await;
+    let processor_lock = state.audio_processor.lock().await;
+    if let Some(proc) = processor_lock.as_ref() {
+        proc.set_dsp_stage(stage, enabled, strength);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_dsp_settings(state: State<'_, AppState>) -> Result<Vec<DspStageSettings>, String> {
+    let processor_lock = state.audio_processor.lock().await;
+    if let Some(proc) = processor_lock.as_ref() {
+        return Ok(proc.dsp_settings());
+    }
+    let controls = DspControls::default();
+    for saved in &state.settings.lock().await.dsp_stages {
+        controls.set_stage(saved.stage, saved.enabled, saved.strength);
+    }
+    Ok(controls.settings())
+}
+
+#[tauri::command]
+async fn set_encoder_settings(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    settings: EncoderSettings
+) -> Result<Room, String> {
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    let room = {
+        let mut manager = state.room_manager.lock().await;
+        manager.set_encoder_settings(room_id, user_id, settings)?
+    };
+    persist(&state).await;
     let mut processor_lock = state.audio_processor.lock().await;
     if let Some(proc) = processor_lock.as_mut() {
-        proc.set_output_volume(volume);
+        proc.set_encoder_settings(room.encoder_settings.clone()).await.map_err(|e| e.to_string())?;
+    }
+    Ok(room)
+}
+
+#[tauri::command]
+async fn get_encoder_settings(
+    state: State<'_, AppState>,
+    room_id: String
+) -> Result<EncoderSettings, String> {
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let manager = state.room_manager.lock().await;
+    manager.get_encoder_settings(&room_id).ok_or_else(|| "Room not found".to_string())
+}
+
+#[tauri::command]
+async fn set_room_audio_mode(
+    state: State<'_, AppState>,
+    room_id: String,
+    user_id: String,
+    mode: AudioMode
+) -> Result<Room, String> {
+    let room_id = Uuid::parse_str(&room_id).map_err(|e| e.to_string())?;
+    let user_id = Uuid::parse_str(&user_id).map_err(|e| e.to_string())?;
+    let room = {
+        let mut manager = state.room_manager.lock().await;
+        manager.set_audio_mode(room_id, user_id, mode)?
+    };
+    persist(&state).await;
+    let mut processor_lock = state.audio_processor.lock().await;
+    if let Some(proc) = processor_lock.as_mut() {
+        proc.set_audio_mode(room.audio_mode);
+        proc.set_encoder_settings(room.encoder_settings.clone()).await.map_err(|e| e.to_string())?;
+    }
+    Ok(room)
+}
+
+#[tauri::command]
+async fn set_agc_settings(
+    state: State<'_, AppState>,
+    settings: AgcSettings
+) -> Result<(), String> {
+    let settings = settings.clamped();
+    state.settings.lock().await.agc = settings;
+    persist(&state).await;
+    let processor_lock = state.audio_processor.lock().await;
+    if let Some(proc) = processor_lock.as_ref() {
+        proc.set_agc_settings(settings);
     }
     Ok(())
 }
 
+#[tauri::command]
+async fn get_agc_settings(state: State<'_, AppState>) -> Result<AgcSettings, String> {
+    Ok(state.settings.lock().await.agc)
+}
+
+#[tauri::command]
+async fn get_callback_stats(state: State<'_, AppState>) -> Result<Option<AudioCallbackStats>, String> {
+    let processor_lock = state.audio_processor.lock().await;
+    Ok(processor_lock.as_ref().map(|proc| proc.callback_stats()))
+}
+
+/// Incoming packets the network refused: strangers, forgeries and replays.
+#[tauri::command]
+async fn get_dropped_packets(state: State<'_, AppState>) -> Result<Option<DroppedPackets>, String> {
+    let network = state.network.lock().await;
+    Ok(network.as_ref().map(|net| net.dropped_packets()))
+}
+
+#[tauri::command]
+async fn get_peer_presence(state: State<'_, AppState>) -> Result<Vec<PeerPresence>, String> {
+    let network = state.network.lock().await;
+    Ok(network.as_ref().map(|net| net.peer_presence()).unwrap_or_default())
+}
+
 fn main() {
     tauri::Builder::default()
-        .manage(AppState::new())
+        .setup(|app| {
+            let storage = match app.path().app_data_dir() {
+                Ok(directory) => Some(Storage::new(&directory)),
+                Err(e) => {
+                    eprintln!("No app data directory, settings won't be saved: {}", e);
+                    None
+                }
+            };
+            let state = AppState::new(storage);
+            let mut engine_states = state.engine.subscribe();
+            app.manage(state);
+
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                while engine_states.changed().await.is_ok() {
+                    let engine_state = *engine_states.borrow_and_update();
+                    let _ = handle.emit("engine-state-changed", engine_state);
+                }
+            });
+
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let mut interval = tokio::time::interval(ROOM_EXPIRY_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    let state = handle.state::<AppState>();
+                    let expired = state.room_manager.lock().await.expire_idle_rooms(ROOM_IDLE_TIMEOUT);
+                    if !expired.is_empty() {
+                        persist(&state).await;
+                        let _ = handle.emit("rooms-expired", expired);
+                    }
+                }
+            });
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             add_user,
             create_room,
             join_room,
             leave_room,
+            disconnect_user,
+            send_message,
+            get_messages,
+            create_sub_channel,
+            remove_sub_channel,
+            start_whisper,
+            stop_whisper,
             list_rooms,
+            set_room_password,
+            set_max_participants,
+            create_invite,
+            kick_participant,
+            ban_participant,
+            unban_participant,
+            set_force_muted,
+            set_participant_role,
+            transfer_ownership,
             start_streaming,
             stop_streaming,
             set_user_volume,
+            set_user_position,
+            set_listener_pose,
+            set_spatial_settings,
+            get_spatial_settings,
             set_input_device,
             set_input_volume,
-            set_muted
+            set_sfu_server,
+            set_muted,
+            set_dsp_stage,
+            get_dsp_settings,
+            set_agc_settings,
+            get_agc_settings,
+            set_encoder_settings,
+            get_encoder_settings,
+            set_room_audio_mode,
+            get_callback_stats,
+            get_dropped_packets,
+            get_peer_presence,
+            get_engine_state,
+            start_mic_test,
+            stop_mic_test,
+            start_recording,
+            stop_recording,
+            play_sound,
+            stop_sound,
+            set_soundboard_volume,
+            set_soundboard_monitoring
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,144 @@
+// src-tauri/src/room/access.rs
+
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Why a join was refused. Serialized as `{ "kind": ..., "message": ... }`
+/// so the UI can react to each case.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum JoinError {
+    RoomNotFound,
+    UserNotFound,
+    PasswordRequired,
+    WrongPassword,
+    InvalidInvite,
+    InviteExpired,
+    RoomFull,
+    Banned,
+    Other(String),
+}
+
+impl std::fmt::Display for JoinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinError::RoomNotFound => f.write_str("Room not found"),
+            JoinError::UserNotFound => f.write_str("User not found"),
+            JoinError::PasswordRequired => f.write_str("This room requires a password"),
+            JoinError::WrongPassword => f.write_str("Wrong password"),
+            JoinError::InvalidInvite => f.write_str("Invite code is not valid for this room"),
+            JoinError::InviteExpired => f.write_str("Invite code has expired"),
+            JoinError::RoomFull => f.write_str("Room is full"),
+            JoinError::Banned => f.write_str("You are banned from this room"),
+            JoinError::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<String> for JoinError {
+    fn from(message: String) -> Self {
+        JoinError::Other(message)
+    }
+}
+
+/// What a user presents when joining a room.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JoinCredentials {
+    pub password: Option<String>,
+    pub invite_code: Option<String>,
+}
+
+/// A code that admits its holder to a password-protected room until it expires.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invite {
+    pub room_id: Uuid,
+    pub code: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredInvite {
+    code_hash: [u8; 32],
+    expires_at: DateTime<Utc>,
+}
+
+/// Secrets and bans for one room. Kept out of `Room` so they never reach
+/// the UI.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct RoomAccess {
+    // Argon2id in PHC string format.
+    password_hash: Option<String>,
+    invites: Vec<StoredInvite>,
+    pub banned: HashSet<Uuid>,
+}
+
+fn hash_invite_code(code: &str) -> [u8; 32] {
+    Sha256::digest(code.as_bytes()).into()
+}
+
+impl RoomAccess {
+    pub fn has_password(&self) -> bool {
+        self.password_hash.is_some()
+    }
+
+    pub fn set_password(&mut self, password: Option<&str>) -> Result<(), String> {
+        self.password_hash = match password {
+            Some(password) if !password.is_empty() => {
+                let salt: [u8; 16] = rand::random();
+                let hash = argon2::hash_encoded(password.as_bytes(), &salt, &argon2::Config::owasp2())
+                    .map_err(|e| e.to_string())?;
+                Some(hash)
+            }
+            _ => None,
+        };
+        Ok(())
+    }
+
+    /// Forgets invites that can no longer be used.
+    pub fn prune_invites(&mut self) {
+        let now = Utc::now();
+        self.invites.retain(|invite| invite.expires_at > now);
+    }
+
+    pub fn create_invite(&mut self, room_id: Uuid, expires_at: DateTime<Utc>) -> Invite {
+        self.prune_invites();
+        let code = Uuid::new_v4().simple().to_string();
+        self.invites.push(StoredInvite {
+            code_hash: hash_invite_code(&code),
+            expires_at,
+        });
+        Invite { room_id, code, expires_at }
+    }
+
+    /// Checks a join against the room's password and invites. A valid
+    /// invite stands in for the password.
+    pub fn admit(&self, user_id: Uuid, credentials: &JoinCredentials) -> Result<(), JoinError> {
+        if self.banned.contains(&user_id) {
+            return Err(JoinError::Banned);
+        }
+        if let Some(code) = credentials.invite_code.as_deref() {
+            let code_hash = hash_invite_code(code);
+            let invite = self
+                .invites
+                .iter()
+                .find(|invite| invite.code_hash == code_hash)
+                .ok_or(JoinError::InvalidInvite)?;
+            if invite.expires_at <= Utc::now() {
+                return Err(JoinError::InviteExpired);
+            }
+            return Ok(());
+        }
+        let Some(hash) = self.password_hash.as_deref() else {
+            return Ok(());
+        };
+        let password = credentials.password.as_deref().ok_or(JoinError::PasswordRequired)?;
+        match argon2::verify_encoded(hash, password.as_bytes()) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(JoinError::WrongPassword),
+            Err(e) => Err(JoinError::Other(e.to_string())),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// src-tauri/src/room/chat.rs
+
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Utc};
+use std::collections::VecDeque;
+use uuid::Uuid;
+use super::User;
+
+/// Longest message accepted, in characters.
+pub const MAX_MESSAGE_LEN: usize = 4000;
+/// Messages kept per room; older ones drop out of the history.
+pub const HISTORY_LEN: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    /// Position in the room's history; increases by one per message.
+    pub sequence: u64,
+    pub sender: User,
+    pub sent_at: DateTime<Utc>,
+    pub text: String,
+}
+
+/// One room's messages, in the order they were posted.
+#[derive(Default)]
+pub struct ChatLog {
+    messages: VecDeque<ChatMessage>,
+    next_sequence: u64,
+}
+
+impl ChatLog {
+    pub fn post(&mut self, room_id: Uuid, sender: User, text: &str) -> Result<ChatMessage, String> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err("Message is empty".to_string());
+        }
+        if text.chars().count() > MAX_MESSAGE_LEN {
+            return Err(format!("Messages are limited to {} characters", MAX_MESSAGE_LEN));
+        }
+        let message = ChatMessage {
+            id: Uuid::new_v4(),
+            room_id,
+            sequence: self.next_sequence,
+            sender,
+            sent_at: Utc::now(),
+            text: text.to_string(),
+        };
+        self.next_sequence += 1;
+        self.messages.push_back(message.clone());
+        if self.messages.len() > HISTORY_LEN {
+            self.messages.pop_front();
+        }
+        Ok(message)
+    }
+
+    /// Messages after `after`, or the whole history for `None`.
+    pub fn since(&self, after: Option<u64>) -> Vec<ChatMessage> {
+        self.messages
+            .iter()
+            .filter(|message| after.is_none_or(|after| message.sequence > after))
+            .cloned()
+            .collect()
+    }
+}
@@ -1,9 +1,20 @@
 // src-tauri/src/room/mod.rs
+pub mod access;
+pub mod chat;
+pub mod moderation;
+pub mod whisper;
+
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use uuid::Uuid;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+use crate::audio::codec::{AudioMode, EncoderSettings};
+use crate::audio::crypto::{Identity, KeyEnvelope, RoomKey};
+use access::{Invite, JoinCredentials, JoinError, RoomAccess};
+use chat::{ChatLog, ChatMessage};
+use moderation::Role;
+use whisper::SubChannel;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
@@ -18,15 +29,60 @@ pub struct User {
 pub struct Room {
     pub id: Uuid,
     pub name: String,
+    /// The room's current owner; see `Role::Owner`.
     pub creator_id: Uuid,
     pub participants: Vec<User>,
     pub created_at: DateTime<Utc>,
+    pub encoder_settings: EncoderSettings,
+    pub audio_mode: AudioMode,
+    /// Participants currently recording the room.
+    pub recorders: Vec<Uuid>,
+    /// Members' X25519 public keys, published when they join.
+    pub member_keys: HashMap<Uuid, [u8; 32]>,
+    /// The room's audio key, wrapped for each member who has been handed it.
+    pub key_envelopes: Vec<KeyEnvelope>,
+    pub has_password: bool,
+    /// Joins beyond this many participants are refused.
+    pub max_participants: Option<usize>,
+    /// Every participant's role, the owner's included.
+    pub roles: HashMap<Uuid, Role>,
+    /// Participants a moderator has silenced; peers drop their packets.
+    pub force_muted: Vec<Uuid>,
+    /// Set while nobody is in the room; see `RoomManager::expire_idle_rooms`.
+    pub empty_since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub sub_channels: Vec<SubChannel>,
+}
+
+/// A saved room: its public state plus the secrets kept out of `Room`.
+#[derive(Serialize, Deserialize)]
+pub struct SavedRoom {
+    pub room: Room,
+    pub access: RoomAccess,
+}
+
+/// Everything `RoomManager` keeps across restarts.
+#[derive(Default, Serialize, Deserialize)]
+pub struct RoomSnapshot {
+    pub users: Vec<User>,
+    pub rooms: Vec<SavedRoom>,
+}
+
+impl Room {
+    /// Forgets the envelope for `public_key` once no member holds that key.
+    fn drop_unused_envelope(&mut self, public_key: [u8; 32]) {
+        if !self.member_keys.values().any(|key| *key == public_key) {
+            self.key_envelopes.retain(|envelope| envelope.recipient != public_key);
+        }
+    }
 }
 
 pub struct RoomManager {
     rooms: HashMap<Uuid, Room>,
     users: HashMap<Uuid, User>,
     peer_mappings: HashMap<SocketAddr, Uuid>,
+    access: HashMap<Uuid, RoomAccess>,
+    chat: HashMap<Uuid, ChatLog>,
 }
 
 impl RoomManager {
@@ -35,9 +91,51 @@ impl RoomManager {
             rooms: HashMap::new(),
             users: HashMap::new(),
             peer_mappings: HashMap::new(),
+            access: HashMap::new(),
+            chat: HashMap::new(),
         }
     }
 
+    /// Rebuilds a manager from saved rooms and users. Nobody is connected
+    /// after a restart, so session state (participants, addresses, keys,
+    /// recordings, mutes) starts empty; room keys are minted again on the
+    /// next join. Rooms that were occupied start idling now.
+    pub fn from_snapshot(snapshot: RoomSnapshot) -> Self {
+        let now = Utc::now();
+        let mut manager = Self::new();
+        for mut user in snapshot.users {
+            user.peer_addr = None;
+            manager.users.insert(user.id, user);
+        }
+        for SavedRoom { mut room, mut access } in snapshot.rooms {
+            room.participants.clear();
+            room.recorders.clear();
+            room.force_muted.clear();
+            room.member_keys.clear();
+            room.key_envelopes.clear();
+            room.roles.retain(|id, _| *id == room.creator_id);
+            room.empty_since.get_or_insert(now);
+            access.prune_invites();
+            manager.access.insert(room.id, access);
+            manager.rooms.insert(room.id, room);
+        }
+        manager
+    }
+
+    /// Copies the rooms and users worth saving.
+    pub fn snapshot(&self) -> RoomSnapshot {
+        let users = self.users.values().cloned().collect();
+        let rooms = self
+            .rooms
+            .values()
+            .map(|room| SavedRoom {
+                room: room.clone(),
+                access: self.access.get(&room.id).cloned().unwrap_or_default(),
+            })
+            .collect();
+        RoomSnapshot { users, rooms }
+    }
+
     pub fn create_room(&mut self, name: String, creator_id: Uuid) -> Room {
         let room = Room {
             id: Uuid::new_v4(),
@@ -45,36 +143,405 @@ impl RoomManager {
             creator_id,
             participants: Vec::new(),
             created_at: Utc::now(),
+            encoder_settings: EncoderSettings::default(),
+            audio_mode: AudioMode::Voice,
+            recorders: Vec::new(),
+            member_keys: HashMap::new(),
+            key_envelopes: Vec::new(),
+            has_password: false,
+            max_participants: None,
+            roles: HashMap::from([(creator_id, Role::Owner)]),
+            force_muted: Vec::new(),
+            empty_since: Some(Utc::now()),
+            sub_channels: Vec::new(),
         };
         self.rooms.insert(room.id, room.clone());
+        self.access.insert(room.id, RoomAccess::default());
         room
     }
 
-    pub fn join_room(&mut self, room_id: Uuid, user_id: Uuid) -> Result<Room, String> {
+    /// Admits `user_id` if they aren't banned, the room has space and they
+    /// present the room's password or a live invite. Rejoining is always
+    /// allowed for current participants who aren't banned, and the owner
+    /// never needs their own password. A user is in at most one room: once
+    /// admitted they leave the room they were in.
+    pub fn join_room(&mut self, room_id: Uuid, user_id: Uuid, credentials: &JoinCredentials) -> Result<Room, JoinError> {
+        let room = self.rooms.get(&room_id).ok_or(JoinError::RoomNotFound)?;
+        if !self.users.contains_key(&user_id) {
+            return Err(JoinError::UserNotFound);
+        }
+        let access = self.access.entry(room_id).or_default();
+        if room.participants.iter().any(|p| p.id == user_id) {
+            if access.banned.contains(&user_id) {
+                return Err(JoinError::Banned);
+            }
+            return Ok(room.clone());
+        }
+        if user_id != room.creator_id {
+            access.admit(user_id, credentials)?;
+        }
+        if room.max_participants.is_some_and(|max| room.participants.len() >= max) {
+            return Err(JoinError::RoomFull);
+        }
+
+        if let Some(previous) = self.room_of(&user_id) {
+            self.leave_room(previous, user_id)?;
+        }
+        // Leaving drops the address mapping; the user keeps their address.
+        let user = self.users.get(&user_id).cloned().ok_or(JoinError::UserNotFound)?;
+        if let Some(addr) = user.peer_addr {
+            self.peer_mappings.insert(addr, user_id);
+        }
+        let room = self.rooms.get_mut(&room_id).ok_or(JoinError::RoomNotFound)?;
+        room.participants.push(user);
+        room.roles.entry(user_id).or_insert(Role::Member);
+        room.empty_since = None;
+        Ok(room.clone())
+    }
+
+    /// The room `user_id` is currently in.
+    pub fn room_of(&self, user_id: &Uuid) -> Option<Uuid> {
+        self.rooms
+            .values()
+            .find(|room| room.participants.iter().any(|p| p.id == *user_id))
+            .map(|room| room.id)
+    }
+
+    /// Sets or clears (`None` or empty) the room password. Creator only.
+    pub fn set_password(&mut self, room_id: Uuid, user_id: Uuid, password: Option<&str>) -> Result<Room, String> {
         let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
-        let user = self.users.get(&user_id).ok_or("User not found")?;
-        if !room.participants.iter().any(|p| p.id == user_id) {
-            room.participants.push(user.clone());
+        if room.creator_id != user_id {
+            return Err("Only the room creator can change the password".to_string());
+        }
+        let access = self.access.entry(room_id).or_default();
+        access.set_password(password)?;
+        room.has_password = access.has_password();
+        Ok(room.clone())
+    }
+
+    /// Caps how many participants the room admits. Current participants
+    /// beyond the cap stay. Creator only.
+    pub fn set_max_participants(&mut self, room_id: Uuid, user_id: Uuid, max: Option<usize>) -> Result<Room, String> {
+        let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        if room.creator_id != user_id {
+            return Err("Only the room creator can change the participant limit".to_string());
+        }
+        if max == Some(0) {
+            return Err("A room must admit at least one participant".to_string());
         }
+        room.max_participants = max;
         Ok(room.clone())
     }
 
+    /// Issues an invite code valid until `expires_at`. Creator only.
+    pub fn create_invite(&mut self, room_id: Uuid, user_id: Uuid, expires_at: DateTime<Utc>) -> Result<Invite, String> {
+        let room = self.rooms.get(&room_id).ok_or("Room not found")?;
+        if room.creator_id != user_id {
+            return Err("Only the room creator can create invites".to_string());
+        }
+        if expires_at <= Utc::now() {
+            return Err("Invite expiry must be in the future".to_string());
+        }
+        Ok(self.access.entry(room_id).or_default().create_invite(room_id, expires_at))
+    }
+
+    /// Removes `user_id` from the room. An owner who leaves hands the room to
+    /// `Room::successor`; a room left empty keeps its owner and starts idling
+    /// until it is rejoined or expires.
     pub fn leave_room(&mut self, room_id: Uuid, user_id: Uuid) -> Result<(), String> {
         let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        if !room.participants.iter().any(|p| p.id == user_id) {
+            return Err("User is not in this room".to_string());
+        }
         room.participants.retain(|p| p.id != user_id);
+        room.recorders.retain(|id| *id != user_id);
+        room.force_muted.retain(|id| *id != user_id);
+        if room.creator_id != user_id {
+            room.roles.remove(&user_id);
+        }
+        if let Some(public_key) = room.member_keys.remove(&user_id) {
+            room.drop_unused_envelope(public_key);
+        }
         if let Some(user) = self.users.get(&user_id) {
             if let Some(addr) = user.peer_addr {
                 self.peer_mappings.remove(&addr);
             }
         }
-        if room.participants.is_empty() && room.creator_id != user_id {
-            self.rooms.remove(&room_id);
-        } else if room.creator_id == user_id && !room.participants.is_empty() {
-            room.creator_id = room.participants[0].id;
+        if room.participants.is_empty() {
+            room.empty_since = Some(Utc::now());
+        } else if room.creator_id == user_id {
+            if let Some(successor) = room.successor() {
+                room.roles.remove(&user_id);
+                room.set_owner(successor);
+            }
         }
         Ok(())
     }
 
+    /// Takes a user who went away out of their room and forgets their
+    /// address. Returns the room they left.
+    pub fn disconnect_user(&mut self, user_id: Uuid) -> Option<Uuid> {
+        let room_id = self.room_of(&user_id);
+        if let Some(room_id) = room_id {
+            let _ = self.leave_room(room_id, user_id);
+        }
+        if let Some(addr) = self.users.get_mut(&user_id).and_then(|user| user.peer_addr.take()) {
+            self.peer_mappings.remove(&addr);
+        }
+        room_id
+    }
+
+    /// Deletes rooms that have been empty for at least `idle_for`. Returns
+    /// the deleted rooms.
+    pub fn expire_idle_rooms(&mut self, idle_for: Duration) -> Vec<Uuid> {
+        let cutoff = Utc::now() - idle_for;
+        let expired: Vec<Uuid> = self
+            .rooms
+            .values()
+            .filter(|room| room.empty_since.is_some_and(|since| since <= cutoff))
+            .map(|room| room.id)
+            .collect();
+        for room_id in &expired {
+            self.rooms.remove(room_id);
+            self.access.remove(room_id);
+            self.chat.remove(room_id);
+        }
+        expired
+    }
+
+    /// Removes `target_id` from the room. They may rejoin.
+    pub fn kick(&mut self, room_id: Uuid, actor_id: Uuid, target_id: Uuid) -> Result<Room, String> {
+        let room = self.rooms.get(&room_id).ok_or("Room not found")?;
+        room.check_moderation(actor_id, target_id)?;
+        if !room.participants.iter().any(|p| p.id == target_id) {
+            return Err("User is not in this room".to_string());
+        }
+        self.leave_room(room_id, target_id)?;
+        self.rooms.get(&room_id).cloned().ok_or_else(|| "Room not found".to_string())
+    }
+
+    /// Removes `target_id` from the room and refuses their future joins.
+    pub fn ban(&mut self, room_id: Uuid, actor_id: Uuid, target_id: Uuid) -> Result<Room, String> {
+        let room = self.rooms.get(&room_id).ok_or("Room not found")?;
+        room.check_moderation(actor_id, target_id)?;
+        let present = room.participants.iter().any(|p| p.id == target_id);
+        self.access.entry(room_id).or_default().banned.insert(target_id);
+        if present {
+            self.leave_room(room_id, target_id)?;
+        }
+        self.rooms.get(&room_id).cloned().ok_or_else(|| "Room not found".to_string())
+    }
+
+    pub fn unban(&mut self, room_id: Uuid, actor_id: Uuid, target_id: Uuid) -> Result<Room, String> {
+        let room = self.rooms.get(&room_id).ok_or("Room not found")?;
+        room.check_moderation(actor_id, target_id)?;
+        self.access.entry(room_id).or_default().banned.remove(&target_id);
+        Ok(room.clone())
+    }
+
+    /// Silences (or unsilences) a participant for everyone in the room.
+    pub fn set_force_muted(&mut self, room_id: Uuid, actor_id: Uuid, target_id: Uuid, muted: bool) -> Result<Room, String> {
+        let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        room.check_moderation(actor_id, target_id)?;
+        if !room.participants.iter().any(|p| p.id == target_id) {
+            return Err("User is not in this room".to_string());
+        }
+        room.force_muted.retain(|id| *id != target_id);
+        if muted {
+            room.force_muted.push(target_id);
+        }
+        Ok(room.clone())
+    }
+
+    /// Changes a participant's role. Actors can only hand out roles below
+    /// their own; ownership moves with `transfer_ownership`.
+    pub fn set_role(&mut self, room_id: Uuid, actor_id: Uuid, target_id: Uuid, role: Role) -> Result<Room, String> {
+        let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        if role == Role::Owner {
+            return Err("Use an ownership transfer to make someone the owner".to_string());
+        }
+        room.check_moderation(actor_id, target_id)?;
+        if role >= room.role_of(actor_id) {
+            return Err("You can only grant roles below your own".to_string());
+        }
+        if !room.participants.iter().any(|p| p.id == target_id) {
+            return Err("User is not in this room".to_string());
+        }
+        room.roles.insert(target_id, role);
+        Ok(room.clone())
+    }
+
+    /// Hands the room to another participant. Owner only.
+    pub fn transfer_ownership(&mut self, room_id: Uuid, actor_id: Uuid, target_id: Uuid) -> Result<Room, String> {
+        let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        if room.creator_id != actor_id {
+            return Err("Only the owner can transfer the room".to_string());
+        }
+        if !room.participants.iter().any(|p| p.id == target_id) {
+            return Err("User is not in this room".to_string());
+        }
+        room.force_muted.retain(|id| *id != target_id);
+        room.set_owner(target_id);
+        Ok(room.clone())
+    }
+
+    /// Changes the encoder profile every participant of the room streams with.
+    /// Only the room creator may pick the profile.
+    pub fn set_encoder_settings(
+        &mut self,
+        room_id: Uuid,
+        user_id: Uuid,
+        settings: EncoderSettings,
+    ) -> Result<Room, String> {
+        settings.validate()?;
+        let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        if room.creator_id != user_id {
+            return Err("Only the room creator can change the encoder profile".to_string());
+        }
+        room.encoder_settings = settings;
+        Ok(room.clone())
+    }
+
+    pub fn get_room(&self, room_id: &Uuid) -> Option<&Room> {
+        self.rooms.get(room_id)
+    }
+
+    pub fn get_encoder_settings(&self, room_id: &Uuid) -> Option<EncoderSettings> {
+        self.rooms.get(room_id).map(|room| room.encoder_settings.clone())
+    }
+
+    /// Switches the room between voice and music mode, resetting its encoder
+    /// profile to the mode's default. Only the room creator may do this.
+    pub fn set_audio_mode(&mut self, room_id: Uuid, user_id: Uuid, mode: AudioMode) -> Result<Room, String> {
+        let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        if room.creator_id != user_id {
+            return Err("Only the room creator can change the audio mode".to_string());
+        }
+        room.audio_mode = mode;
+        room.encoder_settings = mode.default_encoder_settings();
+        Ok(room.clone())
+    }
+
+    /// Marks a participant as recording (or no longer recording) the room.
+    pub fn set_recording(&mut self, room_id: Uuid, user_id: Uuid, recording: bool) -> Result<Room, String> {
+        let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        if !room.participants.iter().any(|p| p.id == user_id) {
+            return Err("Only participants can record a room".to_string());
+        }
+        room.recorders.retain(|id| *id != user_id);
+        if recording {
+            room.recorders.push(user_id);
+        }
+        Ok(room.clone())
+    }
+
+    /// Publishes a member's public key so others can hand them the room key.
+    pub fn set_member_key(&mut self, room_id: Uuid, user_id: Uuid, public_key: [u8; 32]) -> Result<(), String> {
+        let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        if room.creator_id != user_id && !room.participants.iter().any(|p| p.id == user_id) {
+            return Err("Only participants can publish a key".to_string());
+        }
+        if let Some(old) = room.member_keys.insert(user_id, public_key) {
+            room.drop_unused_envelope(old);
+        }
+        Ok(())
+    }
+
+    pub fn add_key_envelope(&mut self, room_id: Uuid, envelope: KeyEnvelope) -> Result<(), String> {
+        let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        if !room.member_keys.values().any(|key| *key == envelope.recipient) {
+            return Err("Room keys can only be handed to members".to_string());
+        }
+        room.key_envelopes.retain(|existing| existing.recipient != envelope.recipient);
+        room.key_envelopes.push(envelope);
+        Ok(())
+    }
+
+    pub fn key_envelope(&self, room_id: &Uuid, recipient: &[u8; 32]) -> Option<KeyEnvelope> {
+        self.rooms
+            .get(room_id)?
+            .key_envelopes
+            .iter()
+            .find(|envelope| envelope.recipient == *recipient)
+            .cloned()
+    }
+
+    /// Public keys of members still waiting for the room key.
+    pub fn members_missing_key(&self, room_id: &Uuid) -> Vec<[u8; 32]> {
+        let Some(room) = self.rooms.get(room_id) else {
+            return Vec::new();
+        };
+        room.member_keys
+            .values()
+            .filter(|key| !room.key_envelopes.iter().any(|envelope| envelope.recipient == **key))
+            .copied()
+            .collect()
+    }
+
+    /// Wraps the room key for every member still waiting for it, if
+    /// `identity` holds it. A room nobody holds a key for yet (new, or
+    /// restored from disk) gets a fresh one.
+    pub fn share_room_key(&mut self, room_id: Uuid, identity: &Identity) -> Result<(), String> {
+        let unkeyed = self.rooms.get(&room_id).is_some_and(|room| room.key_envelopes.is_empty());
+        let key = match self.key_envelope(&room_id, &identity.public_key()) {
+            Some(envelope) => identity.unwrap_key(&envelope, room_id)?,
+            None if unkeyed => RoomKey::generate(),
+            None => return Ok(()),
+        };
+        for recipient in self.members_missing_key(&room_id) {
+            let envelope = identity.wrap_key(&recipient, room_id, &key)?;
+            self.add_key_envelope(room_id, envelope)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a member of the room published `public_key`.
+    pub fn has_member_key(&self, room_id: &Uuid, public_key: &[u8; 32]) -> bool {
+        self.rooms
+            .get(room_id)
+            .is_some_and(|room| room.member_keys.values().any(|key| key == public_key))
+    }
+
+    /// Replaces the room key with a fresh one, wrapped for the members who
+    /// remain, so someone who left can't follow the room with the key they
+    /// held. Only a remaining member rotates: `identity` outside the room
+    /// leaves the key alone rather than mint the next one itself. A room
+    /// left empty gets its new key on the next join.
+    pub fn rotate_room_key(&mut self, room_id: Uuid, identity: &Identity) -> Result<(), String> {
+        if !self.rooms.contains_key(&room_id) {
+            return Err("Room not found".to_string());
+        }
+        if !self.has_member_key(&room_id, &identity.public_key()) {
+            return Ok(());
+        }
+        if let Some(room) = self.rooms.get_mut(&room_id) {
+            room.key_envelopes.clear();
+        }
+        self.share_room_key(room_id, identity)
+    }
+
+    /// Adds a message from a participant to the room's history.
+    pub fn post_message(&mut self, room_id: Uuid, user_id: Uuid, text: &str) -> Result<ChatMessage, String> {
+        let room = self.rooms.get(&room_id).ok_or("Room not found")?;
+        let sender = room
+            .participants
+            .iter()
+            .find(|p| p.id == user_id)
+            .cloned()
+            .ok_or("Only participants can send messages")?;
+        self.chat.entry(room_id).or_default().post(room_id, sender, text)
+    }
+
+    /// The room's messages after sequence `after` (all kept history for
+    /// `None`), for participants only.
+    pub fn messages(&self, room_id: Uuid, user_id: Uuid, after: Option<u64>) -> Result<Vec<ChatMessage>, String> {
+        let room = self.rooms.get(&room_id).ok_or("Room not found")?;
+        if !room.participants.iter().any(|p| p.id == user_id) {
+            return Err("Only participants can read messages".to_string());
+        }
+        Ok(self.chat.get(&room_id).map(|log| log.since(after)).unwrap_or_default())
+    }
+
     pub fn list_rooms(&self) -> Vec<Room> {
         self.rooms.values().cloned().collect()
     }
@@ -86,12 +553,40 @@ impl RoomManager {
             }
             user.peer_addr = Some(addr);
             self.peer_mappings.insert(addr, user_id);
+            for room in self.rooms.values_mut() {
+                for participant in room.participants.iter_mut().filter(|p| p.id == user_id) {
+                    participant.peer_addr = Some(addr);
+                }
+            }
             Ok(())
         } else {
             Err("User not found".to_string())
         }
     }
 
+    /// Moves every user reachable at `old` to `new`, e.g. after our network
+    /// changed under us. Returns the users moved.
+    pub fn readdress(&mut self, old: SocketAddr, new: SocketAddr) -> Vec<Uuid> {
+        let moved: Vec<Uuid> = self
+            .users
+            .values()
+            .filter(|user| user.peer_addr == Some(old))
+            .map(|user| user.id)
+            .collect();
+        for user_id in &moved {
+            let _ = self.add_peer_address(*user_id, new);
+        }
+        moved
+    }
+
+    pub fn user_for_addr(&self, addr: &SocketAddr) -> Option<Uuid> {
+        self.peer_mappings.get(addr).copied()
+    }
+
+    pub fn addr_for_user(&self, user_id: &Uuid) -> Option<SocketAddr> {
+        self.users.get(user_id).and_then(|user| user.peer_addr)
+    }
+
     pub fn get_room_peers(&self, room_id: &Uuid) -> Vec<SocketAddr> {
         if let Some(room) = self.rooms.get(room_id) {
             room.participants.iter().filter_map(|user| user.peer_addr).collect()
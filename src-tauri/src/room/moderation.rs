@@ -0,0 +1,69 @@
+// src-tauri/src/room/moderation.rs
+
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use super::Room;
+
+/// What a participant may do in a room, lowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Hears the room but is never heard.
+    Listener,
+    Member,
+    /// May kick, ban and mute members and listeners.
+    Moderator,
+    Owner,
+}
+
+impl Room {
+    /// Participants without an explicit role are members; the owner is
+    /// whoever `creator_id` names.
+    pub fn role_of(&self, user_id: Uuid) -> Role {
+        if user_id == self.creator_id {
+            return Role::Owner;
+        }
+        self.roles.get(&user_id).copied().unwrap_or(Role::Member)
+    }
+
+    /// Whether peers play this participant's audio.
+    pub fn can_speak(&self, user_id: Uuid) -> bool {
+        self.role_of(user_id) != Role::Listener && !self.force_muted.contains(&user_id)
+    }
+
+    /// Checks that `actor` may act on `target`: moderators and owners may act
+    /// on anyone ranked below them.
+    pub fn check_moderation(&self, actor: Uuid, target: Uuid) -> Result<(), String> {
+        let actor_role = self.role_of(actor);
+        if actor_role < Role::Moderator {
+            return Err("Only moderators can do that".to_string());
+        }
+        if actor == target || actor_role <= self.role_of(target) {
+            return Err("You can only moderate participants ranked below you".to_string());
+        }
+        Ok(())
+    }
+
+    /// Who takes over when the owner leaves: the highest-ranked remaining
+    /// participant, earliest joiner first.
+    pub(super) fn successor(&self) -> Option<Uuid> {
+        let mut best: Option<(Role, Uuid)> = None;
+        for participant in &self.participants {
+            let role = self.role_of(participant.id);
+            if best.is_none_or(|(best_role, _)| role > best_role) {
+                best = Some((role, participant.id));
+            }
+        }
+        best.map(|(_, id)| id)
+    }
+
+    /// Makes `user_id` the owner; the previous owner stays on as moderator.
+    pub(super) fn set_owner(&mut self, user_id: Uuid) {
+        let previous = self.creator_id;
+        if previous != user_id && self.participants.iter().any(|p| p.id == previous) {
+            self.roles.insert(previous, Role::Moderator);
+        }
+        self.creator_id = user_id;
+        self.roles.insert(user_id, Role::Owner);
+    }
+}
@@ -0,0 +1,99 @@
+// src-tauri/src/room/whisper.rs
+
+use serde::{Serialize, Deserialize};
+use std::net::SocketAddr;
+use uuid::Uuid;
+use super::moderation::Role;
+use super::{Room, RoomManager};
+
+/// A named group within a room that can be whispered to as one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubChannel {
+    pub id: Uuid,
+    pub name: String,
+    pub created_by: Uuid,
+    pub members: Vec<Uuid>,
+}
+
+/// Who a whisper reaches.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WhisperTarget {
+    Users { user_ids: Vec<Uuid> },
+    Channel { channel_id: Uuid },
+}
+
+impl RoomManager {
+    /// Creates a sub-channel of `members`, which always includes its creator.
+    pub fn create_sub_channel(&mut self, room_id: Uuid, user_id: Uuid, name: String, members: Vec<Uuid>) -> Result<Room, String> {
+        let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        if !room.participants.iter().any(|p| p.id == user_id) {
+            return Err("Only participants can create sub-channels".to_string());
+        }
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Sub-channel name is empty".to_string());
+        }
+        let mut channel_members = vec![user_id];
+        for member in members {
+            if !channel_members.contains(&member) {
+                channel_members.push(member);
+            }
+        }
+        room.sub_channels.push(SubChannel {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            created_by: user_id,
+            members: channel_members,
+        });
+        Ok(room.clone())
+    }
+
+    /// Deletes a sub-channel. Its creator and moderators may.
+    pub fn remove_sub_channel(&mut self, room_id: Uuid, user_id: Uuid, channel_id: Uuid) -> Result<Room, String> {
+        let room = self.rooms.get_mut(&room_id).ok_or("Room not found")?;
+        let channel = room
+            .sub_channels
+            .iter()
+            .find(|channel| channel.id == channel_id)
+            .ok_or("Sub-channel not found")?;
+        if channel.created_by != user_id && room.role_of(user_id) < Role::Moderator {
+            return Err("Only its creator or a moderator can remove a sub-channel".to_string());
+        }
+        room.sub_channels.retain(|channel| channel.id != channel_id);
+        Ok(room.clone())
+    }
+
+    /// Addresses of the participants `user_id` would whisper to. Members of
+    /// the target who aren't in the room right now are skipped.
+    pub fn whisper_addrs(&self, room_id: Uuid, user_id: Uuid, target: &WhisperTarget) -> Result<Vec<SocketAddr>, String> {
+        let room = self.rooms.get(&room_id).ok_or("Room not found")?;
+        if !room.participants.iter().any(|p| p.id == user_id) {
+            return Err("User is not in this room".to_string());
+        }
+        if !room.can_speak(user_id) {
+            return Err("You can't speak in this room".to_string());
+        }
+        let user_ids = match target {
+            WhisperTarget::Users { user_ids } => user_ids,
+            WhisperTarget::Channel { channel_id } => {
+                &room
+                    .sub_channels
+                    .iter()
+                    .find(|channel| channel.id == *channel_id)
+                    .ok_or("Sub-channel not found")?
+                    .members
+            }
+        };
+        let addrs: Vec<SocketAddr> = room
+            .participants
+            .iter()
+            .filter(|p| p.id != user_id && user_ids.contains(&p.id))
+            .filter_map(|p| p.peer_addr)
+            .collect();
+        if addrs.is_empty() {
+            return Err("Nobody to whisper to is in the room".to_string());
+        }
+        Ok(addrs)
+    }
+}
@@ -0,0 +1,103 @@
+// src-tauri/src/storage.rs
+//
+// Saved rooms, users and settings live in one JSON document in the app data
+// directory. Every document carries a schema version; older documents are
+// upgraded step by step on load, newer ones are refused rather than
+// overwritten with fields this build doesn't know about.
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use crate::audio::dsp::{AgcSettings, DspStageSettings};
+use crate::audio::spatial::SpatialSettings;
+use crate::room::RoomSnapshot;
+
+pub const SCHEMA_VERSION: u64 = 1;
+const FILE_NAME: &str = "llas.json";
+
+/// Upgrades a document from the version at its index + 1 to the next one.
+/// Append a step here whenever `StoredState`'s layout changes incompatibly.
+const MIGRATIONS: &[fn(&mut Value)] = &[];
+
+/// Audio preferences that outlive a session.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub input_device: Option<String>,
+    pub input_volume: Option<f32>,
+    /// Playback volume for each remote user.
+    pub user_volumes: HashMap<Uuid, f32>,
+    /// Forwarding server to stream through instead of sending to each peer.
+    pub sfu_server: Option<SocketAddr>,
+    /// That server's public key, in hex, when it should mix the room into
+    /// one stream for us. It gets the room key to do so.
+    pub sfu_mix_key: Option<String>,
+    pub spatial: SpatialSettings,
+    /// Capture stages the user has changed; the rest keep their defaults.
+    pub dsp_stages: Vec<DspStageSettings>,
+    pub agc: AgcSettings,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct StoredState {
+    #[serde(flatten)]
+    pub rooms: RoomSnapshot,
+    #[serde(default)]
+    pub settings: Settings,
+}
+
+pub struct Storage {
+    path: PathBuf,
+}
+
+impl Storage {
+    pub fn new(directory: &Path) -> Self {
+        Self { path: directory.join(FILE_NAME) }
+    }
+
+    /// Reads the saved state. A missing file is a first run, not an error.
+    pub fn load(&self) -> Result<StoredState, String> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(StoredState::default()),
+            Err(e) => return Err(format!("Failed to read {}: {}", self.path.display(), e)),
+        };
+        let mut document: Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        migrate(&mut document)?;
+        serde_json::from_value(document).map_err(|e| e.to_string())
+    }
+
+    /// Replaces the saved state. Writes a sibling file and renames it over
+    /// the old one so a crash never leaves a half-written document.
+    pub fn save(&self, state: &StoredState) -> Result<(), String> {
+        let mut document = serde_json::to_value(state).map_err(|e| e.to_string())?;
+        if let Value::Object(fields) = &mut document {
+            fields.insert("version".to_string(), SCHEMA_VERSION.into());
+        }
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+        }
+        let temp = self.path.with_extension("json.tmp");
+        let text = serde_json::to_string_pretty(&document).map_err(|e| e.to_string())?;
+        fs::write(&temp, text).map_err(|e| e.to_string())?;
+        fs::rename(&temp, &self.path).map_err(|e| e.to_string())
+    }
+}
+
+fn migrate(document: &mut Value) -> Result<(), String> {
+    let version = document.get("version").and_then(Value::as_u64).ok_or("Saved state has no schema version")?;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "Saved state is schema version {}, newer than this build's {}",
+            version, SCHEMA_VERSION
+        ));
+    }
+    for step in MIGRATIONS.iter().skip(version.saturating_sub(1) as usize) {
+        step(document);
+    }
+    Ok(())
+}
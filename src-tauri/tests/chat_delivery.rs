@@ -0,0 +1,156 @@
+// src-tauri/tests/chat_delivery.rs
+//
+// Chat from posting to delivery: the room's history, and the numbered,
+// acknowledged streams that carry it between peers over a lossy link.
+
+use llas_lib::audio::control::{ChatInbox, ChatOutbox, ControlMessage, MAX_CHAT_CHUNK, RETRANSMIT_AFTER};
+use llas_lib::room::chat::{ChatLog, HISTORY_LEN};
+use llas_lib::room::User;
+use std::net::SocketAddr;
+use std::time::Instant;
+use uuid::Uuid;
+
+fn user(name: &str) -> User {
+    User { id: Uuid::new_v4(), name: name.to_string(), is_muted: false, is_deafened: false, peer_addr: None }
+}
+
+fn addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+fn texts(log: &ChatLog, after: Option<u64>) -> Vec<String> {
+    log.since(after).into_iter().map(|message| message.text).collect()
+}
+
+#[test]
+fn history_keeps_posting_order() {
+    let mut log = ChatLog::default();
+    let room_id = Uuid::new_v4();
+    let (alice, bob) = (user("alice"), user("bob"));
+    for (sender, text) in [(&alice, "one"), (&bob, "two"), (&alice, "three")] {
+        log.post(room_id, sender.clone(), text).unwrap();
+    }
+
+    let messages = log.since(None);
+    assert_eq!(messages.iter().map(|m| m.sequence).collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(texts(&log, None), vec!["one", "two", "three"]);
+    assert_eq!(messages[1].sender.id, bob.id);
+}
+
+#[test]
+fn history_drops_the_oldest_past_its_limit() {
+    let mut log = ChatLog::default();
+    let room_id = Uuid::new_v4();
+    let sender = user("alice");
+    for i in 0..HISTORY_LEN + 3 {
+        log.post(room_id, sender.clone(), &i.to_string()).unwrap();
+    }
+
+    let messages = log.since(None);
+    assert_eq!(messages.len(), HISTORY_LEN);
+    assert_eq!(messages[0].sequence, 3);
+    assert_eq!(messages.last().unwrap().sequence, HISTORY_LEN as u64 + 2);
+}
+
+#[test]
+fn since_returns_only_later_messages() {
+    let mut log = ChatLog::default();
+    let room_id = Uuid::new_v4();
+    let sender = user("alice");
+    for text in ["one", "two", "three"] {
+        log.post(room_id, sender.clone(), text).unwrap();
+    }
+
+    assert_eq!(texts(&log, Some(0)), vec!["two", "three"]);
+    assert_eq!(texts(&log, Some(2)), Vec::<String>::new());
+    assert!(log.post(room_id, sender, "   ").is_err());
+}
+
+/// Feeds `packet` to `inbox` as if it came from `from`. Returns the ack to
+/// send back, if any, and the texts it completed.
+fn deliver(inbox: &mut ChatInbox, from: SocketAddr, packet: &[u8]) -> (Option<Vec<u8>>, Vec<String>) {
+    let Some(ControlMessage::Chat { stream, sequence, more, text }) = ControlMessage::parse(packet) else {
+        panic!("not a chat packet");
+    };
+    let (ack, texts) = inbox.receive(from, stream, sequence, more, text);
+    (ack.then(|| ControlMessage::Ack { stream, sequence }.encode()), texts)
+}
+
+fn acknowledge(outbox: &mut ChatOutbox, to: SocketAddr, ack: &[u8]) {
+    let Some(ControlMessage::Ack { stream, sequence }) = ControlMessage::parse(ack) else {
+        panic!("not an ack");
+    };
+    outbox.acked(to, stream, sequence);
+}
+
+#[test]
+fn reordered_chat_is_delivered_in_order_once() {
+    let (sender, receiver) = (addr(5000), addr(5001));
+    let mut outbox = ChatOutbox::default();
+    let mut inbox = ChatInbox::default();
+    let now = Instant::now();
+    let mut packets: Vec<Vec<u8>> = ["one", "two", "three"]
+        .iter()
+        .flat_map(|text| outbox.send(text, &[receiver], now))
+        .map(|(_, packet)| packet)
+        .collect();
+    packets.reverse();
+
+    let mut received = Vec::new();
+    for packet in &packets {
+        received.extend(deliver(&mut inbox, sender, packet).1);
+    }
+    assert_eq!(received, vec!["one", "two", "three"]);
+    // A duplicate is acknowledged again but not delivered twice.
+    let (ack, texts) = deliver(&mut inbox, sender, &packets[0]);
+    assert!(ack.is_some());
+    assert!(texts.is_empty());
+}
+
+#[test]
+fn lost_chat_is_resent_until_acknowledged() {
+    let (sender, receiver) = (addr(5000), addr(5001));
+    let mut outbox = ChatOutbox::default();
+    let mut inbox = ChatInbox::default();
+    let start = Instant::now();
+    // Lost on the way.
+    outbox.send("hello", &[receiver], start);
+    assert!(outbox.due(start).is_empty());
+
+    let resent = outbox.due(start + RETRANSMIT_AFTER);
+    assert_eq!(resent.len(), 1);
+    let (to, packet) = &resent[0];
+    assert_eq!(*to, receiver);
+    let (ack, texts) = deliver(&mut inbox, sender, packet);
+    assert_eq!(texts, vec!["hello"]);
+
+    acknowledge(&mut outbox, receiver, &ack.unwrap());
+    assert!(outbox.due(start + RETRANSMIT_AFTER * 4).is_empty());
+}
+
+#[test]
+fn long_texts_are_split_and_joined_again() {
+    let (sender, receiver) = (addr(5000), addr(5001));
+    let mut outbox = ChatOutbox::default();
+    let mut inbox = ChatInbox::default();
+    let text = "é".repeat(MAX_CHAT_CHUNK);
+    let packets = outbox.send(&text, &[receiver], Instant::now());
+    assert_eq!(packets.len(), 2);
+
+    assert!(deliver(&mut inbox, sender, &packets[1].1).1.is_empty());
+    assert_eq!(deliver(&mut inbox, sender, &packets[0].1).1, vec![text]);
+}
+
+#[test]
+fn each_peer_gets_its_own_stream() {
+    let (sender, first, second) = (addr(5000), addr(5001), addr(5002));
+    let mut outbox = ChatOutbox::default();
+    let now = Instant::now();
+    outbox.send("before", &[first], now);
+    let packets = outbox.send("after", &[first, second], now);
+
+    // The newcomer starts at the beginning of its stream, not stuck
+    // waiting for what it was never sent.
+    let for_second = packets.iter().find(|(to, _)| *to == second).unwrap();
+    assert_eq!(deliver(&mut ChatInbox::default(), sender, &for_second.1).1, vec!["after"]);
+}
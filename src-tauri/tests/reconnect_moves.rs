@@ -0,0 +1,188 @@
+// src-tauri/tests/reconnect_moves.rs
+//
+// Two networks in one room on localhost, behind a stand-in TURN server.
+// When one of them reconnects from a new address, the other has to follow
+// it there, whether they talk directly or through a forwarding server.
+
+use llas_lib::audio::crypto::RoomKey;
+use llas_lib::audio::reconnect::LinkEvent;
+use llas_lib::audio::sfu::{self, SfuRoute, SfuSettings};
+use llas_lib::audio::{AudioNetwork, AudioProcessor};
+use llas_lib::config::TurnConfig;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+const STUN_MAGIC_COOKIE: u32 = 0x2112A442;
+const FRAME_INTERVAL: Duration = Duration::from_millis(20);
+const WAIT: Duration = Duration::from_secs(5);
+
+/// Answers every allocation request with a success naming the requester's
+/// own address as the relayed one.
+async fn fake_turn_server() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 1024];
+        while let Ok((size, source)) = socket.recv_from(&mut buffer).await {
+            if size < 20 || buffer[..2] != [0x00, 0x03] {
+                continue;
+            }
+            let SocketAddr::V4(source_v4) = source else {
+                continue;
+            };
+            let mut response = vec![0x01, 0x03, 0x00, 12];
+            // Magic cookie and transaction id, echoed.
+            response.extend_from_slice(&buffer[4..20]);
+            response.extend_from_slice(&[0x00, 0x16, 0x00, 8, 0x00, 0x01]);
+            response.extend_from_slice(&(source.port() ^ (STUN_MAGIC_COOKIE >> 16) as u16).to_be_bytes());
+            response.extend_from_slice(&(u32::from(*source_v4.ip()) ^ STUN_MAGIC_COOKIE).to_be_bytes());
+            let _ = socket.send_to(&response, source).await;
+        }
+    });
+    addr
+}
+
+async fn network(turn_server: SocketAddr, key: &RoomKey, sfu: Option<SfuRoute>) -> AudioNetwork {
+    let turn_config = TurnConfig {
+        url: format!("turn:{}", turn_server),
+        username: "test".to_string(),
+        credential: "test".to_string(),
+        realm: "test".to_string(),
+    };
+    let mut net = AudioNetwork::new("127.0.0.1:0", turn_config).await.unwrap();
+    net.set_sfu(sfu);
+    net.set_room_key(Some(key.clone()));
+    net
+}
+
+/// Streams a small frame every 20 ms, as a running capture would, and
+/// receives until `cancel` fires.
+async fn start(net: &mut AudioNetwork, cancel: &CancellationToken) {
+    let (audio_tx, audio_rx) = mpsc::channel(100);
+    net.start_streaming(audio_rx, cancel.clone()).await.unwrap();
+    let pump_cancel = cancel.clone();
+    tokio::spawn(async move {
+        let mut frames = tokio::time::interval(FRAME_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = pump_cancel.cancelled() => break,
+                _ = frames.tick() => {
+                    let _ = audio_tx.try_send(vec![0u8; 16]);
+                }
+            }
+        }
+    });
+    let (processor_tx, _) = mpsc::channel(100);
+    let processor = AudioProcessor::new(processor_tx).unwrap();
+    net.handle_incoming(processor, cancel.clone()).await.unwrap();
+}
+
+async fn wait_until_heard(net: &AudioNetwork, peer: SocketAddr) {
+    timeout(WAIT, async {
+        while !net.peer_presence().iter().any(|presence| presence.addr == peer) {
+            tokio::time::sleep(FRAME_INTERVAL).await;
+        }
+    })
+    .await
+    .expect("peer was never heard");
+}
+
+/// Has `net` reconnect and returns its old and new address.
+async fn reconnect(net: &AudioNetwork) -> (SocketAddr, SocketAddr) {
+    let mut link_rx = net.subscribe_to_link();
+    net.reconnect();
+    timeout(WAIT, async {
+        loop {
+            if let LinkEvent::Reconnected { old_addr, new_addr } = link_rx.recv().await.unwrap() {
+                return (old_addr, new_addr);
+            }
+        }
+    })
+    .await
+    .expect("never reconnected")
+}
+
+/// Alice and Bob in one room under `key`, streaming to each other and
+/// Alice reconnecting whenever asked.
+async fn room(key: &RoomKey, sfu: Option<SfuRoute>, cancel: &CancellationToken) -> (AudioNetwork, AudioNetwork) {
+    let turn_server = fake_turn_server().await;
+    let mut alice = network(turn_server, key, sfu.clone()).await;
+    let mut bob = network(turn_server, key, sfu).await;
+    let (alice_addr, bob_addr) = (alice.get_local_addr().unwrap(), bob.get_local_addr().unwrap());
+    alice.add_peer(bob_addr);
+    bob.add_peer(alice_addr);
+    start(&mut alice, cancel).await;
+    start(&mut bob, cancel).await;
+    alice.supervise(cancel.clone()).unwrap();
+    wait_until_heard(&bob, alice_addr).await;
+    (alice, bob)
+}
+
+async fn assert_bob_follows_alice(alice: &AudioNetwork, bob: &AudioNetwork) {
+    let mut moves = bob.subscribe_to_moves();
+    let (old_addr, new_addr) = reconnect(alice).await;
+    assert_ne!(old_addr, new_addr);
+
+    let moved = timeout(WAIT, moves.recv()).await.expect("the move was never announced").unwrap();
+    assert_eq!(moved, (old_addr, new_addr));
+    let presence = bob.peer_presence();
+    assert!(presence.iter().any(|presence| presence.addr == new_addr));
+    assert!(!presence.iter().any(|presence| presence.addr == old_addr));
+    // Alice's audio from the new address is hers again, not a stranger's.
+    let unknown = bob.dropped_packets().unknown_source;
+    tokio::time::sleep(FRAME_INTERVAL * 10).await;
+    assert_eq!(bob.dropped_packets().unknown_source, unknown);
+}
+
+#[tokio::test]
+async fn peers_follow_a_member_who_reconnects() {
+    let cancel = CancellationToken::new();
+    let (alice, bob) = room(&RoomKey::generate(), None, &cancel).await;
+    assert_bob_follows_alice(&alice, &bob).await;
+    cancel.cancel();
+}
+
+#[tokio::test]
+async fn peers_follow_a_member_who_reconnects_behind_a_forwarding_server() {
+    let server = {
+        let probe = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        probe.local_addr().unwrap()
+    };
+    let bind_addr = server.to_string();
+    tokio::spawn(async move { sfu::serve(&bind_addr, SfuSettings::default(), None).await });
+    let route = SfuRoute { server, room_id: Uuid::new_v4(), mix_key: None };
+
+    let cancel = CancellationToken::new();
+    let (alice, bob) = room(&RoomKey::generate(), Some(route), &cancel).await;
+    assert_bob_follows_alice(&alice, &bob).await;
+    cancel.cancel();
+}
+
+#[tokio::test]
+async fn a_stranger_cannot_take_over_a_members_address() {
+    let cancel = CancellationToken::new();
+    let key = RoomKey::generate();
+    let (alice, bob) = room(&key, None, &cancel).await;
+    let alice_addr = alice.get_local_addr().unwrap();
+    let bob_addr = bob.get_local_addr().unwrap();
+
+    // Carol holds the room key but isn't one of Bob's peers; her own move
+    // names an address Bob never knew her by.
+    let turn_server = fake_turn_server().await;
+    let mut carol = network(turn_server, &key, None).await;
+    carol.add_peer(bob_addr);
+    start(&mut carol, &cancel).await;
+    carol.supervise(cancel.clone()).unwrap();
+    let mut moves = bob.subscribe_to_moves();
+    reconnect(&carol).await;
+
+    assert!(timeout(Duration::from_secs(1), moves.recv()).await.is_err());
+    assert!(bob.peer_presence().iter().any(|presence| presence.addr == alice_addr));
+    assert!(bob.dropped_packets().unknown_source > 0);
+    cancel.cancel();
+}
@@ -0,0 +1,349 @@
+// src-tauri/tests/room_lifecycle.rs
+//
+// Join/leave orderings against RoomManager: membership, ownership hand-off,
+// idle-room expiry and room key rotation.
+
+use chrono::Duration;
+use llas_lib::audio::crypto::{Identity, RoomKey};
+use llas_lib::room::access::{JoinCredentials, JoinError};
+use llas_lib::room::moderation::Role;
+use llas_lib::room::{Room, RoomManager};
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+fn manager_with_users(count: usize) -> (RoomManager, Vec<Uuid>) {
+    let mut manager = RoomManager::new();
+    let users = (0..count).map(|i| manager.add_user(format!("user{}", i)).id).collect();
+    (manager, users)
+}
+
+fn join(manager: &mut RoomManager, room_id: Uuid, user_id: Uuid) -> Room {
+    manager.join_room(room_id, user_id, &JoinCredentials::default()).unwrap()
+}
+
+fn room(manager: &RoomManager, room_id: Uuid) -> &Room {
+    manager.get_room(&room_id).expect("room exists")
+}
+
+fn participant_ids(manager: &RoomManager, room_id: Uuid) -> Vec<Uuid> {
+    room(manager, room_id).participants.iter().map(|p| p.id).collect()
+}
+
+fn addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+/// Every ordering of `items`.
+fn permutations(items: &[usize]) -> Vec<Vec<usize>> {
+    if items.len() <= 1 {
+        return vec![items.to_vec()];
+    }
+    let mut result = Vec::new();
+    for i in 0..items.len() {
+        let mut rest = items.to_vec();
+        let first = rest.remove(i);
+        for mut tail in permutations(&rest) {
+            tail.insert(0, first);
+            result.push(tail);
+        }
+    }
+    result
+}
+
+#[test]
+fn every_join_and_leave_order_keeps_an_owner_in_the_room() {
+    let orders = permutations(&[0, 1, 2]);
+    for join_order in &orders {
+        for leave_order in &orders {
+            let (mut manager, users) = manager_with_users(3);
+            let room_id = manager.create_room("room".into(), users[0]).id;
+            for &i in join_order {
+                join(&mut manager, room_id, users[i]);
+            }
+            assert_eq!(room(&manager, room_id).creator_id, users[0]);
+
+            let mut remaining: Vec<Uuid> = join_order.iter().map(|&i| users[i]).collect();
+            for &i in leave_order {
+                manager.leave_room(room_id, users[i]).unwrap();
+                remaining.retain(|id| *id != users[i]);
+                let room = room(&manager, room_id);
+                assert_eq!(participant_ids(&manager, room_id), remaining);
+                if remaining.is_empty() {
+                    assert!(room.empty_since.is_some());
+                } else {
+                    assert!(room.empty_since.is_none());
+                    assert!(remaining.contains(&room.creator_id), "owner left the room: {:?} {:?}", join_order, leave_order);
+                    assert_eq!(room.role_of(room.creator_id), Role::Owner);
+                    let owners = remaining.iter().filter(|id| room.role_of(**id) == Role::Owner).count();
+                    assert_eq!(owners, 1);
+                }
+            }
+            // The room outlives its last participant until it expires.
+            assert!(manager.get_room(&room_id).is_some());
+        }
+    }
+}
+
+#[test]
+fn ownership_passes_to_the_earliest_joiner() {
+    let (mut manager, users) = manager_with_users(3);
+    let room_id = manager.create_room("room".into(), users[0]).id;
+    join(&mut manager, room_id, users[0]);
+    join(&mut manager, room_id, users[2]);
+    join(&mut manager, room_id, users[1]);
+
+    manager.leave_room(room_id, users[0]).unwrap();
+    assert_eq!(room(&manager, room_id).creator_id, users[2]);
+    manager.leave_room(room_id, users[2]).unwrap();
+    assert_eq!(room(&manager, room_id).creator_id, users[1]);
+}
+
+#[test]
+fn ownership_prefers_moderators_over_earlier_members() {
+    let (mut manager, users) = manager_with_users(3);
+    let room_id = manager.create_room("room".into(), users[0]).id;
+    for &user in &users {
+        join(&mut manager, room_id, user);
+    }
+    manager.set_role(room_id, users[0], users[2], Role::Moderator).unwrap();
+
+    manager.leave_room(room_id, users[0]).unwrap();
+    assert_eq!(room(&manager, room_id).creator_id, users[2]);
+    assert_eq!(room(&manager, room_id).role_of(users[1]), Role::Member);
+}
+
+#[test]
+fn former_owner_rejoins_as_member() {
+    let (mut manager, users) = manager_with_users(2);
+    let room_id = manager.create_room("room".into(), users[0]).id;
+    join(&mut manager, room_id, users[0]);
+    join(&mut manager, room_id, users[1]);
+    manager.leave_room(room_id, users[0]).unwrap();
+
+    let room = join(&mut manager, room_id, users[0]);
+    assert_eq!(room.creator_id, users[1]);
+    assert_eq!(room.role_of(users[0]), Role::Member);
+}
+
+#[test]
+fn owner_leaving_last_keeps_the_room() {
+    let (mut manager, users) = manager_with_users(1);
+    let room_id = manager.create_room("room".into(), users[0]).id;
+    join(&mut manager, room_id, users[0]);
+    manager.leave_room(room_id, users[0]).unwrap();
+
+    assert_eq!(room(&manager, room_id).creator_id, users[0]);
+    assert!(room(&manager, room_id).empty_since.is_some());
+    let room = join(&mut manager, room_id, users[0]);
+    assert_eq!(room.role_of(users[0]), Role::Owner);
+    assert!(room.empty_since.is_none());
+}
+
+#[test]
+fn joining_another_room_leaves_the_first() {
+    let (mut manager, users) = manager_with_users(2);
+    let first = manager.create_room("first".into(), users[0]).id;
+    let second = manager.create_room("second".into(), users[1]).id;
+    join(&mut manager, first, users[0]);
+    join(&mut manager, first, users[1]);
+
+    join(&mut manager, second, users[0]);
+    assert_eq!(manager.room_of(&users[0]), Some(second));
+    assert_eq!(participant_ids(&manager, first), vec![users[1]]);
+    // The owner moved out, so the room they left has a new one.
+    assert_eq!(room(&manager, first).creator_id, users[1]);
+}
+
+#[test]
+fn refused_join_keeps_the_current_room() {
+    let (mut manager, users) = manager_with_users(2);
+    let first = manager.create_room("first".into(), users[0]).id;
+    let locked = manager.create_room("locked".into(), users[1]).id;
+    manager.set_password(locked, users[1], Some("secret")).unwrap();
+    join(&mut manager, first, users[0]);
+
+    let error = manager.join_room(locked, users[0], &JoinCredentials::default()).unwrap_err();
+    assert_eq!(error, JoinError::PasswordRequired);
+    assert_eq!(manager.room_of(&users[0]), Some(first));
+}
+
+#[test]
+fn rejoining_the_same_room_is_a_no_op() {
+    let (mut manager, users) = manager_with_users(2);
+    let room_id = manager.create_room("room".into(), users[0]).id;
+    join(&mut manager, room_id, users[0]);
+    join(&mut manager, room_id, users[1]);
+    join(&mut manager, room_id, users[1]);
+    assert_eq!(participant_ids(&manager, room_id), vec![users[0], users[1]]);
+}
+
+#[test]
+fn leaving_twice_is_an_error() {
+    let (mut manager, users) = manager_with_users(2);
+    let room_id = manager.create_room("room".into(), users[0]).id;
+    join(&mut manager, room_id, users[0]);
+    join(&mut manager, room_id, users[1]);
+    manager.leave_room(room_id, users[1]).unwrap();
+    assert!(manager.leave_room(room_id, users[1]).is_err());
+    assert_eq!(participant_ids(&manager, room_id), vec![users[0]]);
+}
+
+#[test]
+fn disconnect_removes_user_and_address() {
+    let (mut manager, users) = manager_with_users(2);
+    let room_id = manager.create_room("room".into(), users[0]).id;
+    manager.add_peer_address(users[0], addr(5000)).unwrap();
+    manager.add_peer_address(users[1], addr(5001)).unwrap();
+    join(&mut manager, room_id, users[0]);
+    join(&mut manager, room_id, users[1]);
+
+    assert_eq!(manager.disconnect_user(users[0]), Some(room_id));
+    assert_eq!(participant_ids(&manager, room_id), vec![users[1]]);
+    assert_eq!(room(&manager, room_id).creator_id, users[1]);
+    assert_eq!(manager.user_for_addr(&addr(5000)), None);
+    assert_eq!(manager.addr_for_user(&users[0]), None);
+    assert_eq!(manager.get_room_peers(&room_id), vec![addr(5001)]);
+
+    // Disconnecting someone who isn't in a room is harmless.
+    assert_eq!(manager.disconnect_user(users[0]), None);
+}
+
+#[test]
+fn moving_rooms_keeps_the_address_mapping() {
+    let (mut manager, users) = manager_with_users(1);
+    let first = manager.create_room("first".into(), users[0]).id;
+    let second = manager.create_room("second".into(), users[0]).id;
+    manager.add_peer_address(users[0], addr(5000)).unwrap();
+    join(&mut manager, first, users[0]);
+    join(&mut manager, second, users[0]);
+    assert_eq!(manager.user_for_addr(&addr(5000)), Some(users[0]));
+    assert!(manager.get_room_peers(&first).is_empty());
+    assert_eq!(manager.get_room_peers(&second), vec![addr(5000)]);
+}
+
+#[test]
+fn only_idle_rooms_expire() {
+    let (mut manager, users) = manager_with_users(2);
+    let occupied = manager.create_room("occupied".into(), users[0]).id;
+    let abandoned = manager.create_room("abandoned".into(), users[1]).id;
+    let never_joined = manager.create_room("never joined".into(), users[1]).id;
+    join(&mut manager, occupied, users[0]);
+    join(&mut manager, abandoned, users[1]);
+    manager.leave_room(abandoned, users[1]).unwrap();
+
+    assert!(manager.expire_idle_rooms(Duration::hours(1)).is_empty());
+
+    let mut expired = manager.expire_idle_rooms(Duration::zero());
+    expired.sort();
+    let mut expected = vec![abandoned, never_joined];
+    expected.sort();
+    assert_eq!(expired, expected);
+    assert!(manager.get_room(&occupied).is_some());
+    assert!(manager.get_room(&abandoned).is_none());
+    let error = manager.join_room(abandoned, users[1], &JoinCredentials::default()).unwrap_err();
+    assert_eq!(error, JoinError::RoomNotFound);
+}
+
+#[test]
+fn rejoining_stops_the_idle_timer() {
+    let (mut manager, users) = manager_with_users(1);
+    let room_id = manager.create_room("room".into(), users[0]).id;
+    join(&mut manager, room_id, users[0]);
+    manager.leave_room(room_id, users[0]).unwrap();
+    join(&mut manager, room_id, users[0]);
+    assert!(manager.expire_idle_rooms(Duration::zero()).is_empty());
+}
+
+#[test]
+fn restored_rooms_start_empty_and_idle() {
+    let (mut manager, users) = manager_with_users(2);
+    let room_id = manager.create_room("room".into(), users[0]).id;
+    join(&mut manager, room_id, users[0]);
+    join(&mut manager, room_id, users[1]);
+
+    let mut restored = RoomManager::from_snapshot(manager.snapshot());
+    assert!(participant_ids(&restored, room_id).is_empty());
+    assert_eq!(restored.room_of(&users[0]), None);
+    assert!(room(&restored, room_id).empty_since.is_some());
+    assert!(restored.expire_idle_rooms(Duration::hours(1)).is_empty());
+    assert_eq!(join(&mut restored, room_id, users[0]).role_of(users[0]), Role::Owner);
+}
+
+#[test]
+fn readdress_moves_room_peers() {
+    let (mut manager, users) = manager_with_users(2);
+    let room_id = manager.create_room("room".into(), users[0]).id;
+    manager.add_peer_address(users[0], addr(5000)).unwrap();
+    manager.add_peer_address(users[1], addr(5001)).unwrap();
+    join(&mut manager, room_id, users[0]);
+    join(&mut manager, room_id, users[1]);
+
+    assert_eq!(manager.readdress(addr(5000), addr(6000)), vec![users[0]]);
+    assert_eq!(manager.get_room_peers(&room_id), vec![addr(6000), addr(5001)]);
+    assert_eq!(manager.user_for_addr(&addr(6000)), Some(users[0]));
+    assert_eq!(manager.user_for_addr(&addr(5000)), None);
+}
+
+/// A room owned by `users[0]` with everyone in it, each holding the room
+/// key under their own identity.
+fn keyed_room(count: usize) -> (RoomManager, Uuid, Vec<Uuid>, Vec<Identity>) {
+    let (mut manager, users) = manager_with_users(count);
+    let identities: Vec<Identity> = users.iter().map(|_| Identity::generate()).collect();
+    let room_id = manager.create_room("room".into(), users[0]).id;
+    for (user, identity) in users.iter().zip(&identities) {
+        join(&mut manager, room_id, *user);
+        manager.set_member_key(room_id, *user, identity.public_key()).unwrap();
+    }
+    manager.share_room_key(room_id, &identities[0]).unwrap();
+    (manager, room_id, users, identities)
+}
+
+fn room_key(manager: &RoomManager, room_id: Uuid, identity: &Identity) -> Option<RoomKey> {
+    let envelope = manager.key_envelope(&room_id, &identity.public_key())?;
+    Some(identity.unwrap_key(&envelope, room_id).unwrap())
+}
+
+#[test]
+fn rotating_the_key_leaves_out_whoever_was_removed() {
+    let (mut manager, room_id, users, identities) = keyed_room(3);
+    let old_key = room_key(&manager, room_id, &identities[0]).unwrap();
+
+    manager.kick(room_id, users[0], users[2]).unwrap();
+    manager.rotate_room_key(room_id, &identities[0]).unwrap();
+
+    let new_key = room_key(&manager, room_id, &identities[0]).unwrap();
+    assert!(new_key != old_key);
+    assert!(room_key(&manager, room_id, &identities[1]) == Some(new_key));
+    assert!(room_key(&manager, room_id, &identities[2]).is_none());
+}
+
+#[test]
+fn a_leaver_does_not_mint_the_next_key() {
+    let (mut manager, room_id, users, identities) = keyed_room(3);
+    let old_key = room_key(&manager, room_id, &identities[0]).unwrap();
+
+    manager.leave_room(room_id, users[2]).unwrap();
+    manager.rotate_room_key(room_id, &identities[2]).unwrap();
+    assert!(room_key(&manager, room_id, &identities[2]).is_none());
+    assert!(room_key(&manager, room_id, &identities[1]) == Some(old_key.clone()));
+
+    manager.rotate_room_key(room_id, &identities[1]).unwrap();
+    let new_key = room_key(&manager, room_id, &identities[0]).unwrap();
+    assert!(new_key != old_key);
+    assert!(room_key(&manager, room_id, &identities[1]) == Some(new_key));
+}
+
+#[test]
+fn a_room_left_empty_gets_a_new_key_on_the_next_join() {
+    let (mut manager, room_id, users, identities) = keyed_room(1);
+    let old_key = room_key(&manager, room_id, &identities[0]).unwrap();
+
+    manager.leave_room(room_id, users[0]).unwrap();
+    manager.rotate_room_key(room_id, &identities[0]).unwrap();
+    assert!(room(&manager, room_id).key_envelopes.is_empty());
+
+    join(&mut manager, room_id, users[0]);
+    manager.set_member_key(room_id, users[0], identities[0].public_key()).unwrap();
+    manager.share_room_key(room_id, &identities[0]).unwrap();
+    assert!(room_key(&manager, room_id, &identities[0]).is_some_and(|key| key != old_key));
+}
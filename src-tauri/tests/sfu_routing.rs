@@ -0,0 +1,267 @@
+// src-tauri/tests/sfu_routing.rs
+//
+// Forwarder::route against spoofed and moving sources: who may speak for a
+// room address, when that address is free again, and whose room keys the
+// mixer takes.
+
+use llas_lib::audio::crypto::{Identity, PacketSealer, RoomKey};
+use llas_lib::audio::packet::{build_audio_packet, FLAG_BYE, FLAG_CONTROL};
+use llas_lib::audio::presence::{DISCONNECTED_AFTER, RECONNECTING_AFTER};
+use llas_lib::audio::sfu::{client_frame, key_frame, muted_frame, parse_client_frame, ClientMessage, Forwarder, SfuRoute, SfuSettings};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+fn addr(last: u8, port: u16) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, last], port))
+}
+
+fn frame(room_id: Uuid, from: SocketAddr, flags: u8) -> Vec<u8> {
+    let route = SfuRoute { server: addr(1, 7000), room_id, mix_key: None };
+    client_frame(&route, from, &[], &build_audio_packet(0, flags, b"audio")).to_vec()
+}
+
+/// Sends one packet claiming `from` and returns where it was forwarded.
+fn send(forwarder: &mut Forwarder, now: Instant, source: SocketAddr, room_id: Uuid, from: SocketAddr) -> Option<Vec<SocketAddr>> {
+    forwarder.route(source, &frame(room_id, from, 0), now).map(|(_, destinations)| destinations)
+}
+
+fn bye(forwarder: &mut Forwarder, now: Instant, source: SocketAddr, room_id: Uuid, from: SocketAddr) {
+    forwarder.route(source, &frame(room_id, from, FLAG_BYE), now);
+}
+
+/// A forwarder with `count` members in one room, each past its first
+/// packet. Member i sends from `addr(i + 10, 9000)` as `addr(i + 10, 5000)`.
+fn room_with_members(count: u8, now: Instant) -> (Forwarder, Uuid, Vec<(SocketAddr, SocketAddr)>) {
+    let mut forwarder = Forwarder::new(SfuSettings::default(), None);
+    let room_id = Uuid::new_v4();
+    let members: Vec<_> = (0..count).map(|i| (addr(i + 10, 9000), addr(i + 10, 5000))).collect();
+    for _ in 0..2 {
+        for &(source, from) in &members {
+            send(&mut forwarder, now, source, room_id, from);
+        }
+    }
+    (forwarder, room_id, members)
+}
+
+#[test]
+fn nothing_is_forwarded_for_a_sources_first_packet() {
+    let now = Instant::now();
+    let (mut forwarder, room_id, members) = room_with_members(1, now);
+    let (source, from) = (addr(20, 9000), addr(20, 5000));
+
+    assert_eq!(send(&mut forwarder, now, source, room_id, from), None);
+    assert_eq!(send(&mut forwarder, now, source, room_id, from), Some(vec![members[0].0]));
+}
+
+#[test]
+fn clients_seen_once_are_not_sent_to() {
+    let now = Instant::now();
+    let (mut forwarder, room_id, members) = room_with_members(2, now);
+    send(&mut forwarder, now, addr(20, 9000), room_id, addr(20, 5000));
+
+    let (source, from) = members[0];
+    assert_eq!(send(&mut forwarder, now, source, room_id, from), Some(vec![members[1].0]));
+}
+
+#[test]
+fn a_claimed_address_is_refused_from_another_source() {
+    let now = Instant::now();
+    let (mut forwarder, room_id, members) = room_with_members(2, now);
+    let (_, victim) = members[0];
+    let intruder = addr(66, 9000);
+
+    for _ in 0..3 {
+        assert_eq!(send(&mut forwarder, now, intruder, room_id, victim), None);
+    }
+    let (source, from) = members[1];
+    assert_eq!(send(&mut forwarder, now, source, room_id, from), Some(vec![members[0].0]));
+    assert_eq!(send(&mut forwarder, now, members[0].0, room_id, victim), Some(vec![source]));
+}
+
+#[test]
+fn a_goodbye_frees_the_address() {
+    let now = Instant::now();
+    let (mut forwarder, room_id, members) = room_with_members(2, now);
+    let (old_source, from) = members[0];
+    bye(&mut forwarder, now, old_source, room_id, from);
+
+    let new_source = addr(30, 9000);
+    assert_eq!(send(&mut forwarder, now, new_source, room_id, from), None);
+    assert_eq!(send(&mut forwarder, now, new_source, room_id, from), Some(vec![members[1].0]));
+    // The old source no longer speaks for the address, nor hears the room.
+    assert_eq!(send(&mut forwarder, now, old_source, room_id, from), None);
+    assert_eq!(send(&mut forwarder, now, members[1].0, room_id, members[1].1), Some(vec![new_source]));
+}
+
+#[test]
+fn a_quiet_address_moves_to_a_new_source() {
+    let start = Instant::now();
+    let (mut forwarder, room_id, members) = room_with_members(2, start);
+    let (old_source, from) = members[0];
+    let new_source = addr(30, 9000);
+    let (other_source, other) = members[1];
+
+    // Still fresh: the NAT-rebound client has to wait.
+    let soon = start + RECONNECTING_AFTER - Duration::from_millis(100);
+    send(&mut forwarder, soon, other_source, room_id, other);
+    assert_eq!(send(&mut forwarder, soon, new_source, room_id, from), None);
+
+    let later = start + RECONNECTING_AFTER;
+    send(&mut forwarder, later, other_source, room_id, other);
+    send(&mut forwarder, later, new_source, room_id, from);
+    assert_eq!(send(&mut forwarder, later, new_source, room_id, from), Some(vec![other_source]));
+    assert_eq!(send(&mut forwarder, later, other_source, room_id, other), Some(vec![new_source]));
+    assert_eq!(send(&mut forwarder, later, old_source, room_id, from), None);
+}
+
+#[test]
+fn a_source_speaks_for_one_room() {
+    let now = Instant::now();
+    let (mut forwarder, room_id, members) = room_with_members(2, now);
+    let (source, from) = members[0];
+    let other_room = Uuid::new_v4();
+    send(&mut forwarder, now, source, other_room, from);
+
+    let (listener, listener_addr) = members[1];
+    assert_eq!(send(&mut forwarder, now, listener, room_id, listener_addr), Some(vec![]));
+    assert_eq!(forwarder.client_count(), 2);
+}
+
+#[test]
+fn expiry_forgets_quiet_clients_and_frees_their_addresses() {
+    let start = Instant::now();
+    let (mut forwarder, room_id, members) = room_with_members(2, start);
+    let later = start + DISCONNECTED_AFTER;
+    forwarder.expire(later);
+    assert_eq!(forwarder.client_count(), 0);
+
+    let (_, from) = members[0];
+    let new_source = addr(30, 9000);
+    send(&mut forwarder, later, new_source, room_id, from);
+    assert_eq!(send(&mut forwarder, later, new_source, room_id, from), Some(vec![]));
+}
+
+/// A mixing forwarder and a room with a member who streams sealed audio
+/// and a listener who asked for a mix, both past their first packet.
+struct MixSetup {
+    forwarder: Forwarder,
+    server: Identity,
+    room_id: Uuid,
+    key: RoomKey,
+    speaker: (SocketAddr, SocketAddr),
+    listener: (SocketAddr, SocketAddr),
+}
+
+impl MixSetup {
+    fn new(now: Instant) -> Self {
+        let server = Identity::generate();
+        let mut setup = MixSetup {
+            forwarder: Forwarder::new(SfuSettings::default(), Some(Identity::from_secret(server.secret_bytes()))),
+            server,
+            room_id: Uuid::new_v4(),
+            key: RoomKey::generate(),
+            speaker: (addr(10, 9000), addr(10, 5000)),
+            listener: (addr(11, 9000), addr(11, 5000)),
+        };
+        for _ in 0..2 {
+            setup.speak(now);
+            setup.listen(now);
+        }
+        setup
+    }
+
+    fn route(&self, mix_key: Option<&RoomKey>) -> SfuRoute {
+        let mix_key = mix_key.map(|key| Identity::generate().wrap_key(&self.server.public_key(), self.room_id, key).unwrap());
+        SfuRoute { server: addr(1, 7000), room_id: self.room_id, mix_key }
+    }
+
+    /// Sends sealed audio from the speaker; returns where it was forwarded.
+    fn speak(&mut self, now: Instant) -> Option<Vec<SocketAddr>> {
+        self.send(now, 0, b"audio")
+    }
+
+    fn send(&mut self, now: Instant, flags: u8, payload: &[u8]) -> Option<Vec<SocketAddr>> {
+        let packet = PacketSealer::new(&self.key).seal(build_audio_packet(0, flags, payload)).unwrap();
+        let frame = client_frame(&self.route(None), self.speaker.1, &[], &packet);
+        self.forwarder.route(self.speaker.0, &frame, now).map(|(_, destinations)| destinations)
+    }
+
+    /// A keepalive from the listener, asking for a mix.
+    fn listen(&mut self, now: Instant) {
+        let packet = PacketSealer::new(&self.key).seal(build_audio_packet(0, 0, b"")).unwrap();
+        let frame = client_frame(&self.route(Some(&self.key)), self.listener.1, &[], &packet);
+        self.forwarder.route(self.listener.0, &frame, now);
+    }
+
+    fn offer_key(&mut self, now: Instant, source: SocketAddr, from: SocketAddr, key: &RoomKey) {
+        let frame = key_frame(&self.route(Some(key)), from).unwrap();
+        self.forwarder.route(source, &frame, now);
+    }
+}
+
+#[test]
+fn a_members_key_starts_mixing_once_it_opens_the_rooms_audio() {
+    let now = Instant::now();
+    let mut setup = MixSetup::new(now);
+    let key = setup.key.clone();
+    let (source, from) = setup.listener;
+    setup.offer_key(now, source, from, &key);
+
+    // Mixed, so the listener isn't sent the speaker's own stream.
+    assert_eq!(setup.speak(now), Some(vec![]));
+}
+
+#[test]
+fn keys_from_outside_the_room_are_ignored() {
+    let now = Instant::now();
+    let mut setup = MixSetup::new(now);
+    let key = setup.key.clone();
+    let (_, from) = setup.listener;
+    setup.offer_key(now, addr(66, 9000), from, &key);
+
+    assert_eq!(setup.speak(now), Some(vec![setup.listener.0]));
+}
+
+#[test]
+fn a_forged_key_does_not_replace_the_rooms_key() {
+    let now = Instant::now();
+    let mut setup = MixSetup::new(now);
+    let (intruder, intruder_addr) = (addr(66, 9000), addr(66, 5000));
+    setup.forwarder.route(intruder, &client_frame(&setup.route(None), intruder_addr, &[], &build_audio_packet(0, 0, b"")), now);
+    setup.offer_key(now, intruder, intruder_addr, &RoomKey::generate());
+    // Opens nothing, so nothing is mixed under it.
+    assert_eq!(setup.speak(now).map(|destinations| destinations.contains(&setup.listener.0)), Some(true));
+
+    let key = setup.key.clone();
+    let (source, from) = setup.listener;
+    setup.offer_key(now, source, from, &key);
+    setup.offer_key(now, intruder, intruder_addr, &RoomKey::generate());
+    assert_eq!(setup.speak(now).map(|destinations| destinations.contains(&setup.listener.0)), Some(false));
+}
+
+#[test]
+fn control_packets_reach_listeners_getting_a_mix() {
+    let now = Instant::now();
+    let mut setup = MixSetup::new(now);
+    let key = setup.key.clone();
+    let (source, from) = setup.listener;
+    setup.offer_key(now, source, from, &key);
+    setup.speak(now);
+
+    assert_eq!(setup.send(now, FLAG_CONTROL, b"chat"), Some(vec![setup.listener.0]));
+}
+
+#[test]
+fn mutes_are_only_sent_to_a_server_that_mixes_for_us() {
+    let now = Instant::now();
+    let setup = MixSetup::new(now);
+    let muted = [setup.speaker.1, addr(12, 5000)];
+    assert!(muted_frame(&setup.route(None), setup.listener.1, &muted).is_none());
+
+    let key = setup.key.clone();
+    let frame = muted_frame(&setup.route(Some(&key)), setup.listener.1, &muted).unwrap();
+    let parsed = parse_client_frame(&frame).unwrap();
+    assert_eq!((parsed.room_id, parsed.from), (setup.room_id, setup.listener.1));
+    assert!(matches!(parsed.message, ClientMessage::Muted(addrs) if addrs == muted));
+}